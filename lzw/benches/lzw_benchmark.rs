@@ -0,0 +1,210 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use lzw::alphabets::Alphabet;
+use lzw::block_mode;
+use lzw::lzw_dictionary::LzwDictionary;
+use lzw::mapped_input::InputFile;
+use lzw::mutable_trie_dict::MutableTrieDictionary;
+use lzw::trie_dictionary::TrieDictionary;
+use lzw::{compress, decompress, Coding, Growth, LzwSpec};
+use std::fs;
+
+// Inputs are generated so the suite runs without any extra files
+
+const INPUT_LEN: usize = 128 * 1024;
+
+const FIXED_SPEC: LzwSpec = LzwSpec {
+    alphabet: Alphabet::Bytes,
+    variable_width: false,
+    width: 12,
+    min_width: 9,
+    max_width: 12,
+    end_code: true,
+    clear_code: true,
+    pack_msb_first: true,
+    early_change: false,
+    evict_lru: false,
+    growth: Growth::Lzw,
+    coding: Coding::Packed,
+    memory_budget: None,
+};
+
+const VARIABLE_SPEC: LzwSpec = LzwSpec {
+    variable_width: true,
+    max_width: 16,
+    ..FIXED_SPEC
+};
+
+// Linear congruential generator, so the inputs are the same every run
+fn next_random(state: &mut u32) -> u32 {
+    *state = state.wrapping_mul(1103515245).wrapping_add(12345);
+    *state >> 16
+}
+
+fn text_input() -> Vec<u8> {
+    let words = [
+        "lorem",
+        "ipsum",
+        "dolor",
+        "sit",
+        "amet",
+        "consectetur",
+        "adipiscing",
+        "elit",
+        "sed",
+        "do",
+        "eiusmod",
+        "tempor",
+        "incididunt",
+        "ut",
+        "labore",
+        "et",
+        "dolore",
+        "magna",
+        "aliqua",
+        "the",
+        "of",
+        "and",
+        "a",
+        "to",
+        "in",
+        "is",
+        "it",
+    ];
+    let mut state = 1;
+    let mut input = Vec::with_capacity(INPUT_LEN);
+    while input.len() < INPUT_LEN {
+        input.extend_from_slice(words[next_random(&mut state) as usize % words.len()].as_bytes());
+        input.push(match next_random(&mut state) % 12 {
+            0 => b'\n',
+            1 => b',',
+            _ => b' ',
+        });
+    }
+    input.truncate(INPUT_LEN);
+    input
+}
+
+fn binary_input() -> Vec<u8> {
+    // Fixed size records: an id counting up, a small type field, and a noisy measurement
+    let mut state = 2;
+    let mut input = Vec::with_capacity(INPUT_LEN);
+    let mut id: u32 = 0;
+    while input.len() < INPUT_LEN {
+        input.extend_from_slice(&id.to_le_bytes());
+        input.extend_from_slice(&((next_random(&mut state) % 4) as u16).to_le_bytes());
+        input.extend_from_slice(&(1000 + next_random(&mut state) % 64).to_le_bytes());
+        id += 1;
+    }
+    input.truncate(INPUT_LEN);
+    input
+}
+
+fn random_input() -> Vec<u8> {
+    let mut state = 3;
+    (0..INPUT_LEN)
+        .map(|_| next_random(&mut state) as u8)
+        .collect()
+}
+
+fn repetitive_input() -> Vec<u8> {
+    b"tobeornottobe"
+        .iter()
+        .copied()
+        .cycle()
+        .take(INPUT_LEN)
+        .collect()
+}
+
+fn inputs() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("text", text_input()),
+        ("binary", binary_input()),
+        ("random", random_input()),
+        ("repetitive", repetitive_input()),
+    ]
+}
+
+fn specs() -> Vec<(&'static str, LzwSpec)> {
+    vec![("fixed", FIXED_SPEC), ("variable", VARIABLE_SPEC)]
+}
+
+fn bench_compress_with<D: LzwDictionary<u8>>(c: &mut Criterion, dictionary: &str) {
+    let mut group = c.benchmark_group(format!("Compress {}", dictionary));
+    for (input_name, input) in inputs() {
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        for (spec_name, spec) in specs() {
+            group.bench_with_input(
+                BenchmarkId::new(spec_name, input_name),
+                &input,
+                |b, input| b.iter(|| compress::<D>(spec, input).unwrap()),
+            );
+        }
+    }
+    group.finish();
+}
+
+fn bench_compress(c: &mut Criterion) {
+    bench_compress_with::<TrieDictionary<u8>>(c, "TrieDictionary");
+    bench_compress_with::<MutableTrieDictionary<u8>>(c, "MutableTrieDictionary");
+}
+
+fn bench_decompress(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Decompress");
+    for (input_name, input) in inputs() {
+        // Throughput is measured against the uncompressed size, as for compression
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        for (spec_name, spec) in specs() {
+            let compressed = compress::<TrieDictionary<u8>>(spec, &input).unwrap();
+            group.bench_with_input(
+                BenchmarkId::new(spec_name, input_name),
+                &compressed,
+                |b, compressed| b.iter(|| decompress(spec, compressed).unwrap()),
+            );
+        }
+    }
+    group.finish();
+}
+
+// A file compressed as the command line does, read into memory or mapped, see mapped_input.rs
+// The file is written out first and removed afterwards, so it is likely in the page cache for both
+fn bench_compress_file(c: &mut Criterion) {
+    let path = std::env::temp_dir().join(format!("lzw-bench-input-{}", std::process::id()));
+    let text = text_input();
+    let contents: Vec<u8> = text.iter().copied().cycle().take(64 * INPUT_LEN).collect();
+    fs::write(&path, &contents).unwrap();
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+
+    let mut group = c.benchmark_group("Compress file");
+    group.sample_size(20);
+    group.throughput(Throughput::Bytes(contents.len() as u64));
+    for (name, mapped) in [("read", false), ("mapped", true)] {
+        group.bench_with_input(BenchmarkId::new(name, "text"), &path, |b, path| {
+            b.iter(|| {
+                // The file is only removed once the group is done
+                let input = if mapped {
+                    unsafe { InputFile::map(path) }.unwrap()
+                } else {
+                    InputFile::read(path).unwrap()
+                };
+                block_mode::compress_blocks::<TrieDictionary<u8>>(
+                    VARIABLE_SPEC,
+                    &input,
+                    block_mode::DEFAULT_BLOCK_SIZE,
+                    threads,
+                )
+                .unwrap()
+            })
+        });
+    }
+    group.finish();
+    fs::remove_file(&path).unwrap();
+}
+
+criterion_group!(
+    benches,
+    bench_compress,
+    bench_decompress,
+    bench_compress_file
+);
+criterion_main!(benches);
@@ -0,0 +1,185 @@
+/* Drives the C API from C, see src/ffi.rs. Build and run with make -C ffi test */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "lzw.h"
+
+static int failures = 0;
+
+#define CHECK(condition)                                                   \
+    do {                                                                   \
+        if (!(condition)) {                                                \
+            fprintf(stderr, "%s:%d: %s failed: %s\n", __FILE__, __LINE__, \
+                    #condition, lzw_last_error());                         \
+            failures++;                                                    \
+        }                                                                  \
+    } while (0)
+
+/* Rows of a synthetic 8 bit image, with the repeats that LZW does well on */
+static unsigned char *sample_image(size_t len) {
+    unsigned char *image = malloc(len);
+    unsigned int state = 7;
+    for (size_t i = 0; i < len; i++) {
+        state = state * 1103515245u + 12345u;
+        image[i] = (unsigned char)((i % 640) / 40 + ((state >> 16) % 3));
+    }
+    return image;
+}
+
+static void one_shot(const LzwCSpec *spec, const unsigned char *image, size_t len) {
+    LzwBuffer compressed = {0};
+    LzwBuffer output = {0};
+    CHECK(lzw_compress(spec, image, len, &compressed) == LZW_STATUS_OK);
+    CHECK(compressed.len > 0 && compressed.len < len / 2);
+    CHECK(lzw_decompress(spec, compressed.data, compressed.len, &output) == LZW_STATUS_OK);
+    CHECK(output.len == len && memcmp(output.data, image, len) == 0);
+
+    LzwBuffer container = {0};
+    output.len = 0;
+    CHECK(lzw_compress_container(spec, image, len, 100000, 0, &container) == LZW_STATUS_OK);
+    CHECK(lzw_decompress_container(container.data, container.len, 0, &output) == LZW_STATUS_OK);
+    CHECK(output.len == len && memcmp(output.data, image, len) == 0);
+
+    lzw_buffer_free(&compressed);
+    lzw_buffer_free(&container);
+    lzw_buffer_free(&output);
+}
+
+static void streamed(const LzwCSpec *spec, const unsigned char *image, size_t len) {
+    LzwBuffer whole = {0};
+    CHECK(lzw_compress(spec, image, len, &whole) == LZW_STATUS_OK);
+
+    /* In pieces of an odd size, as a row at a time might be */
+    LzwEncodeStream *encoder = NULL;
+    LzwBuffer compressed = {0};
+    CHECK(lzw_encoder_init(spec, &encoder) == LZW_STATUS_OK);
+    for (size_t start = 0; start < len; start += 1234) {
+        size_t piece = len - start < 1234 ? len - start : 1234;
+        CHECK(lzw_encoder_update(encoder, image + start, piece, &compressed) == LZW_STATUS_OK);
+    }
+    CHECK(lzw_encoder_finish(encoder, &compressed) == LZW_STATUS_OK);
+    CHECK(compressed.len == whole.len && memcmp(compressed.data, whole.data, whole.len) == 0);
+
+    LzwDecodeStream *decoder = NULL;
+    LzwBuffer output = {0};
+    CHECK(lzw_decoder_init(spec, &decoder) == LZW_STATUS_OK);
+    for (size_t start = 0; start < compressed.len; start += 77) {
+        size_t piece = compressed.len - start < 77 ? compressed.len - start : 77;
+        CHECK(lzw_decoder_update(decoder, compressed.data + start, piece, &output) == LZW_STATUS_OK);
+    }
+    CHECK(lzw_decoder_finish(decoder) == LZW_STATUS_OK);
+    CHECK(output.len == len && memcmp(output.data, image, len) == 0);
+
+    lzw_buffer_free(&whole);
+    lzw_buffer_free(&compressed);
+    lzw_buffer_free(&output);
+}
+
+/* What a GUI might keep: the last progress seen, and a cancel button */
+typedef struct {
+    LzwProgress last;
+    int calls;
+    int cancel_after;
+} ProgressState;
+
+static bool on_progress(const LzwProgress *progress, void *user_data) {
+    ProgressState *state = user_data;
+    state->last = *progress;
+    state->calls++;
+    return state->calls != state->cancel_after;
+}
+
+static void progress(const LzwCSpec *spec, const unsigned char *image, size_t len) {
+    /* One thread, so the blocks are done in order */
+    ProgressState state = {0};
+    LzwBuffer container = {0};
+    CHECK(lzw_compress_container_with_progress(spec, image, len, 100000, 1, on_progress, &state,
+                                               &container) == LZW_STATUS_OK);
+    CHECK(state.calls == 11);
+    CHECK(state.last.bytes_in == len && state.last.total_in == len);
+    CHECK(state.last.ratio > 2.0);
+
+    LzwBuffer output = {0};
+    state.calls = 0;
+    state.cancel_after = 4;
+    CHECK(lzw_decompress_container_with_progress(container.data, container.len, 1, on_progress,
+                                                 &state, &output) == LZW_STATUS_CANCELLED);
+    CHECK(state.calls == 4 && state.last.bytes_out == 300000);
+    CHECK(output.len == 0);
+
+    /* A stream calls back after each update, and the one after cancelling fails */
+    LzwEncodeStream *encoder = NULL;
+    state.calls = 0;
+    state.cancel_after = 2;
+    CHECK(lzw_encoder_init(spec, &encoder) == LZW_STATUS_OK);
+    CHECK(lzw_encoder_set_progress(encoder, on_progress, &state) == LZW_STATUS_OK);
+    CHECK(lzw_encoder_update(encoder, image, 1000, &output) == LZW_STATUS_OK);
+    CHECK(lzw_encoder_update(encoder, image + 1000, 1000, &output) == LZW_STATUS_OK);
+    CHECK(state.calls == 2 && state.last.bytes_in == 2000 && state.last.total_in == 0);
+    CHECK(lzw_encoder_update(encoder, image + 2000, 1000, &output) == LZW_STATUS_CANCELLED);
+    CHECK(state.calls == 2);
+    lzw_encoder_free(encoder);
+
+    lzw_buffer_free(&container);
+    lzw_buffer_free(&output);
+}
+
+static void errors(const LzwCSpec *spec) {
+    LzwBuffer output = {0};
+    LzwCSpec wide = *spec;
+    wide.max_width = 40;
+    CHECK(lzw_compress(&wide, (const uint8_t *)"abc", 3, &output) == LZW_STATUS_SPEC);
+    CHECK(strstr(lzw_last_error(), "wider") != NULL);
+    CHECK(lzw_compress(NULL, (const uint8_t *)"abc", 3, &output) == LZW_STATUS_NULL_POINTER);
+    CHECK(lzw_decompress_container((const uint8_t *)"not a container", 15, 1, &output)
+          == LZW_STATUS_FORMAT);
+
+    /* Cut short */
+    LzwDecodeStream *decoder = NULL;
+    CHECK(lzw_decoder_init(spec, &decoder) == LZW_STATUS_OK);
+    CHECK(lzw_decoder_finish(decoder) == LZW_STATUS_DECODE);
+
+    LzwEncodeStream *encoder = NULL;
+    CHECK(lzw_encoder_init(spec, &encoder) == LZW_STATUS_OK);
+    lzw_encoder_free(encoder);
+    lzw_buffer_free(&output);
+}
+
+int main(void) {
+    CHECK(lzw_abi_version() == LZW_ABI_VERSION);
+
+    LzwCSpec spec;
+    CHECK(lzw_spec_default(&spec) == LZW_STATUS_OK);
+    spec.variable_width = true;
+    spec.end_code = true;
+    spec.clear_code = true;
+
+    size_t len = 1000000;
+    unsigned char *image = sample_image(len);
+    one_shot(&spec, image, len);
+    streamed(&spec, image, len);
+
+    /* GIF style: LSB first and no End code, TIFF style: MSB first with early change */
+    LzwCSpec gif = spec;
+    gif.end_code = false;
+    gif.max_width = 12;
+    streamed(&gif, image, len);
+    LzwCSpec tiff = spec;
+    tiff.pack_msb_first = true;
+    tiff.early_change = true;
+    tiff.max_width = 12;
+    streamed(&tiff, image, len);
+
+    progress(&spec, image, len);
+    errors(&spec);
+    free(image);
+
+    if (failures > 0) {
+        fprintf(stderr, "%d checks failed\n", failures);
+        return 1;
+    }
+    printf("All checks passed\n");
+    return 0;
+}
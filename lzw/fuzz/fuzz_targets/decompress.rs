@@ -0,0 +1,49 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lzw::alphabets::Alphabet;
+use lzw::{decompress, Coding, Growth, LzwSpec};
+
+// The first three bytes pick the spec, the third picking both growth and alphabet
+// The rest is fed to the decoder as a compressed stream
+// Decoding garbage should give an error, never a panic
+fuzz_target!(|data: &[u8]| {
+    let [flags, widths, growth, stream @ ..] = data else {
+        return;
+    };
+    let spec = LzwSpec {
+        alphabet: [
+            Alphabet::Bytes,
+            Alphabet::Ascii,
+            Alphabet::Words,
+            Alphabet::U16Le,
+            Alphabet::U16Be,
+            Alphabet::U32Le,
+            Alphabet::U32Be,
+        ][(growth / 3 % 7) as usize],
+        variable_width: flags & 2 != 0,
+        width: 1 + (widths >> 4),
+        min_width: 1 + (widths & 0x0f),
+        max_width: 1 + (widths >> 4),
+        end_code: flags & 4 != 0,
+        clear_code: flags & 8 != 0,
+        pack_msb_first: flags & 16 != 0,
+        early_change: flags & 32 != 0,
+        evict_lru: flags & 64 != 0,
+        growth: match growth % 3 {
+            0 => Growth::Lzw,
+            1 => Growth::Lzmw,
+            _ => Growth::Lzap,
+        },
+        coding: if flags & 1 != 0 {
+            Coding::Range
+        } else {
+            Coding::Packed
+        },
+        memory_budget: None,
+    };
+    if spec.validate().is_err() {
+        return;
+    }
+    let _ = decompress(spec, stream);
+});
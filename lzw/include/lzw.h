@@ -0,0 +1,260 @@
+#ifndef LZW_H
+#define LZW_H
+
+/* Generated by cbindgen from src/ffi.rs, don't edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define LZW_ABI_VERSION 1
+
+#define LZW_ALPHABET_ASCII 0
+
+#define LZW_ALPHABET_BYTES 1
+
+#define LZW_ALPHABET_WORDS 2
+
+#define LZW_ALPHABET_U16_LE 3
+
+#define LZW_ALPHABET_U16_BE 4
+
+#define LZW_ALPHABET_U32_LE 5
+
+#define LZW_ALPHABET_U32_BE 6
+
+#define LZW_GROWTH_LZW 0
+
+#define LZW_GROWTH_LZMW 1
+
+#define LZW_GROWTH_LZAP 2
+
+#define LZW_CODING_PACKED 0
+
+#define LZW_CODING_RANGE 1
+
+typedef enum LzwStatus {
+  LZW_STATUS_OK = 0,
+  LZW_STATUS_NULL_POINTER = 1,
+  LZW_STATUS_SPEC = 2,
+  LZW_STATUS_DICTIONARY = 3,
+  LZW_STATUS_ENCODE = 4,
+  LZW_STATUS_DECODE = 5,
+  LZW_STATUS_FORMAT = 6,
+  LZW_STATUS_WORKER = 7,
+  LZW_STATUS_IO = 8,
+  LZW_STATUS_STREAM_FAILED = 9,
+  LZW_STATUS_PANIC = 10,
+  LZW_STATUS_CANCELLED = 11,
+} LzwStatus;
+
+typedef struct LzwDecodeStream LzwDecodeStream;
+
+typedef struct LzwEncodeStream LzwEncodeStream;
+
+typedef struct LzwCSpec {
+  uint32_t alphabet;
+  bool variable_width;
+  uint8_t width;
+  uint8_t min_width;
+  uint8_t max_width;
+  bool end_code;
+  bool clear_code;
+  bool pack_msb_first;
+  bool early_change;
+  bool evict_lru;
+  uint32_t growth;
+  uint32_t coding;
+  uint64_t memory_budget;
+} LzwCSpec;
+
+typedef struct LzwBuffer {
+  uint8_t *data;
+  size_t len;
+  size_t capacity;
+} LzwBuffer;
+
+typedef struct LzwProgress {
+  uint64_t bytes_in;
+  uint64_t bytes_out;
+  uint64_t total_in;
+  double ratio;
+} LzwProgress;
+
+// Told how far a container or stream has got, and given back the user_data passed with it.
+// Return true to carry on, or false to cancel.
+typedef bool (*LzwProgressCallback)(const struct LzwProgress *progress, void *user_data);
+
+
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// The version of this API, LZW_ABI_VERSION when the header was generated.
+uint32_t lzw_abi_version(void);
+
+// The message for the last failure on this thread, valid until the next call on it.
+const char *lzw_last_error(void);
+
+// Fill in the command line's default spec: bytes in fixed 12 bit codes, LZW growth, no End or
+// Clear code.
+//
+// # Safety
+// spec must point to an LzwCSpec.
+enum LzwStatus lzw_spec_default(struct LzwCSpec *spec);
+
+// Free the bytes of a buffer, leaving it empty.
+//
+// # Safety
+// buffer must be null or point to a buffer filled in by this library, or zeroed.
+void lzw_buffer_free(struct LzwBuffer *buffer);
+
+// Compress input into a bare stream, appended to output.
+//
+// # Safety
+// spec and output must be valid, and input must hold input_len bytes.
+enum LzwStatus lzw_compress(const struct LzwCSpec *spec,
+                            const uint8_t *input,
+                            size_t input_len,
+                            struct LzwBuffer *output);
+
+// Decompress a bare stream compressed with the same spec, appended to output.
+//
+// # Safety
+// spec and output must be valid, and input must hold input_len bytes.
+enum LzwStatus lzw_decompress(const struct LzwCSpec *spec,
+                              const uint8_t *input,
+                              size_t input_len,
+                              struct LzwBuffer *output);
+
+// Compress input into a container of independent blocks, appended to output.
+// A block_size of 0 is the default of 4 MiB, and threads of 0 uses every core.
+//
+// # Safety
+// spec and output must be valid, and input must hold input_len bytes.
+enum LzwStatus lzw_compress_container(const struct LzwCSpec *spec,
+                                      const uint8_t *input,
+                                      size_t input_len,
+                                      size_t block_size,
+                                      size_t threads,
+                                      struct LzwBuffer *output);
+
+// As lzw_compress_container, calling callback with user_data as each block is done.
+// callback may be NULL, and is called from the worker threads, one call at a time.
+//
+// # Safety
+// spec and output must be valid, input must hold input_len bytes, and callback must be safe to
+// call from other threads with user_data.
+enum LzwStatus lzw_compress_container_with_progress(const struct LzwCSpec *spec,
+                                                    const uint8_t *input,
+                                                    size_t input_len,
+                                                    size_t block_size,
+                                                    size_t threads,
+                                                    LzwProgressCallback callback,
+                                                    void *user_data,
+                                                    struct LzwBuffer *output);
+
+// Decompress a container, appended to output. threads of 0 uses every core.
+//
+// # Safety
+// output must be valid, and input must hold input_len bytes.
+enum LzwStatus lzw_decompress_container(const uint8_t *input,
+                                        size_t input_len,
+                                        size_t threads,
+                                        struct LzwBuffer *output);
+
+// As lzw_decompress_container, calling callback with user_data as each block is done.
+// callback may be NULL, and is called from the worker threads, one call at a time.
+//
+// # Safety
+// output must be valid, input must hold input_len bytes, and callback must be safe to call
+// from other threads with user_data.
+enum LzwStatus lzw_decompress_container_with_progress(const uint8_t *input,
+                                                      size_t input_len,
+                                                      size_t threads,
+                                                      LzwProgressCallback callback,
+                                                      void *user_data,
+                                                      struct LzwBuffer *output);
+
+// Start compressing a bare stream a piece at a time. Only byte alphabets and packed codes
+// can be streamed.
+//
+// # Safety
+// spec must be valid, and stream must point to where to put the new stream.
+enum LzwStatus lzw_encoder_init(const struct LzwCSpec *spec, struct LzwEncodeStream **stream);
+
+// Take more input, appending whatever compressed data is ready to output.
+//
+// # Safety
+// stream must be from lzw_encoder_init, output must be valid, and input must hold input_len bytes.
+enum LzwStatus lzw_encoder_update(struct LzwEncodeStream *stream,
+                                  const uint8_t *input,
+                                  size_t input_len,
+                                  struct LzwBuffer *output);
+
+// Call callback with user_data after each later update, in place of any callback set before.
+// callback may be NULL to stop the calls. Returning false from it cancels the stream.
+//
+// # Safety
+// stream must be from lzw_encoder_init.
+enum LzwStatus lzw_encoder_set_progress(struct LzwEncodeStream *stream,
+                                        LzwProgressCallback callback,
+                                        void *user_data);
+
+// Compress the rest of the input, appended to output, and free the stream.
+//
+// # Safety
+// stream must be from lzw_encoder_init and not used again, and output must be valid.
+enum LzwStatus lzw_encoder_finish(struct LzwEncodeStream *stream, struct LzwBuffer *output);
+
+// Free a stream without finishing it.
+//
+// # Safety
+// stream must be null or from lzw_encoder_init, and not used again.
+void lzw_encoder_free(struct LzwEncodeStream *stream);
+
+// Start decompressing a bare stream a piece at a time.
+//
+// # Safety
+// spec must be valid, and stream must point to where to put the new stream.
+enum LzwStatus lzw_decoder_init(const struct LzwCSpec *spec, struct LzwDecodeStream **stream);
+
+// Take more compressed data, appending what it decodes to output.
+//
+// # Safety
+// stream must be from lzw_decoder_init, output must be valid, and input must hold input_len bytes.
+enum LzwStatus lzw_decoder_update(struct LzwDecodeStream *stream,
+                                  const uint8_t *input,
+                                  size_t input_len,
+                                  struct LzwBuffer *output);
+
+// Call callback with user_data after each later update, in place of any callback set before.
+// callback may be NULL to stop the calls. Returning false from it cancels the stream.
+//
+// # Safety
+// stream must be from lzw_decoder_init.
+enum LzwStatus lzw_decoder_set_progress(struct LzwDecodeStream *stream,
+                                        LzwProgressCallback callback,
+                                        void *user_data);
+
+// Check the stream wasn't cut short, and free it. Fails with LZW_STATUS_DECODE if the spec has
+// an End code and it wasn't reached.
+//
+// # Safety
+// stream must be from lzw_decoder_init and not used again.
+enum LzwStatus lzw_decoder_finish(struct LzwDecodeStream *stream);
+
+// Free a stream without finishing it.
+//
+// # Safety
+// stream must be null or from lzw_decoder_init, and not used again.
+void lzw_decoder_free(struct LzwDecodeStream *stream);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* LZW_H */
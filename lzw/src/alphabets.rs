@@ -1,10 +1,18 @@
-use crate::lzw_token::{ControlToken, HashableToken, Token};
+use crate::lzw_token::Token;
 use crate::ArgAlphabet;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Alphabet {
     _Test,
     Ascii,
+    Bytes,
+    // Word tokens, with the vocabulary stored alongside the codes, see word_mode.rs
+    Words,
+    // Fixed width samples, little or big endian, see sample_mode.rs
+    U16Le,
+    U16Be,
+    U32Le,
+    U32Be,
     // TODO add more
 }
 
@@ -13,8 +21,44 @@ impl Alphabet {
         match alpha {
             ArgAlphabet::_Test => Alphabet::_Test,
             ArgAlphabet::Ascii => Alphabet::Ascii,
+            ArgAlphabet::Bytes => Alphabet::Bytes,
+            ArgAlphabet::Words => Alphabet::Words,
+            ArgAlphabet::U16Le => Alphabet::U16Le,
+            ArgAlphabet::U16Be => Alphabet::U16Be,
+            ArgAlphabet::U32Le => Alphabet::U32Le,
+            ArgAlphabet::U32Be => Alphabet::U32Be,
         }
     }
+
+    // Tokens in the initial dictionary, None when they depend on the input
+    // Every u16 is in the alphabet, but u32 blocks only have the values they use
+    pub fn size(self) -> Option<usize> {
+        match self {
+            Alphabet::_Test | Alphabet::Ascii => Some(95),
+            Alphabet::Bytes => Some(256),
+            Alphabet::U16Le | Alphabet::U16Be => Some(1 << 16),
+            Alphabet::Words | Alphabet::U32Le | Alphabet::U32Be => None,
+        }
+    }
+
+    // Bytes of input per token, the input must be a whole number of them
+    // Words vary in length, but can be split anywhere
+    pub fn sample_bytes(self) -> usize {
+        match self {
+            Alphabet::U16Le | Alphabet::U16Be => 2,
+            Alphabet::U32Le | Alphabet::U32Be => 4,
+            _ => 1,
+        }
+    }
+
+    // Whether the tokens are the input's bytes, as produce_byte_alphabet gives
+    pub fn is_bytes(self) -> bool {
+        matches!(self, Alphabet::_Test | Alphabet::Ascii | Alphabet::Bytes)
+    }
+
+    pub fn big_endian(self) -> bool {
+        matches!(self, Alphabet::U16Be | Alphabet::U32Be)
+    }
 }
 
 // TODO: fix this if it is needed?
@@ -28,9 +72,20 @@ impl Alphabet {
 pub fn generate_ascii() -> Vec<Token<char>> {
     let printable_chars: String = String::from(" !\"#$%&\'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~");
     let alphabet = printable_chars.chars();
-    let res: Vec<Token<char>> = alphabet.map(Token::new).collect();
-    println!("Length of initial alphabet {}", res.len());
-    res
+    alphabet.map(Token::new).collect()
+}
+
+// The byte valued alphabets, for compressing arbitrary input
+pub fn produce_byte_alphabet(alpha: Alphabet) -> Vec<Token<u8>> {
+    match alpha {
+        // Same printable characters as generate_ascii, in the same order
+        Alphabet::_Test | Alphabet::Ascii => (b' '..=b'~').map(Token::new).collect(),
+        Alphabet::Bytes => (0..=u8::MAX).map(Token::new).collect(),
+        // Not bytes, see word_mode.rs and sample_mode.rs
+        Alphabet::Words | Alphabet::U16Le | Alphabet::U16Be | Alphabet::U32Le | Alphabet::U32Be => {
+            Vec::new()
+        }
+    }
 }
 
 // fn generate_test_alphabet<T>() -> Vec<T> {
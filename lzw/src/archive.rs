@@ -0,0 +1,619 @@
+use crate::alphabets::Alphabet;
+use crate::block_mode;
+use crate::checksum::crc32;
+use crate::lzw_error::LzwError;
+use crate::trie_dictionary::TrieDictionary;
+use crate::LzwSpec;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/* ARCHIVE FORMAT
+    Many files and directories in one file, each file compressed on its own as a block mode container.
+    The directory goes at the end, so entries can be written as they are compressed, and a reader
+    can list the archive or pull out one file from the directory alone.
+    All integers are little endian.
+
+    magic               4 bytes     "LZWA"
+    version             1 byte
+    file data           every file's container, one after another
+    directory           a record for every entry, in the order they were added
+        kind            1 byte      file, directory
+        mode            4 bytes     unix permission bits
+        mtime           8 bytes     signed seconds since the unix epoch
+        size            8 bytes     uncompressed size
+        checksum        4 bytes     CRC-32 of the uncompressed contents
+        data_offset     8 bytes     from the start of the archive, 0 for directories
+        data_len        8 bytes
+        path_len        2 bytes
+        path            path_len bytes, UTF-8, relative with / between components
+    directory_offset    8 bytes
+    entry_count         4 bytes
+    magic               4 bytes     "LZWA" again, to spot a truncated archive
+
+    Paths are checked on the way in and out, so an archive can't write outside the directory it is extracted to.
+*/
+
+pub const MAGIC: &[u8; 4] = b"LZWA";
+pub const VERSION: u8 = 1;
+
+const TRAILER_LEN: u64 = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EntryKind {
+    File,
+    Directory,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveEntry {
+    pub path: String,
+    pub kind: EntryKind,
+    pub mode: u32,
+    pub mtime: i64,
+    pub size: u64,
+    pub checksum: u32,
+    data_offset: u64,
+    data_len: u64,
+}
+
+impl ArchiveEntry {
+    fn write_to(&self, output: &mut Vec<u8>) {
+        output.push(match self.kind {
+            EntryKind::File => 0,
+            EntryKind::Directory => 1,
+        });
+        output.extend_from_slice(&self.mode.to_le_bytes());
+        output.extend_from_slice(&self.mtime.to_le_bytes());
+        output.extend_from_slice(&self.size.to_le_bytes());
+        output.extend_from_slice(&self.checksum.to_le_bytes());
+        output.extend_from_slice(&self.data_offset.to_le_bytes());
+        output.extend_from_slice(&self.data_len.to_le_bytes());
+        output.extend_from_slice(&(self.path.len() as u16).to_le_bytes());
+        output.extend_from_slice(self.path.as_bytes());
+    }
+
+    // Returns the entry and the number of bytes it took up
+    fn read_from(input: &[u8]) -> Result<(ArchiveEntry, usize), LzwError> {
+        let truncated = || LzwError::Format("Archive directory is truncated".to_string());
+        let field = |start: usize, len: usize| input.get(start..start + len).ok_or_else(truncated);
+        let u32_at = |start| -> Result<u32, LzwError> {
+            Ok(u32::from_le_bytes(field(start, 4)?.try_into().unwrap()))
+        };
+        let u64_at = |start| -> Result<u64, LzwError> {
+            Ok(u64::from_le_bytes(field(start, 8)?.try_into().unwrap()))
+        };
+
+        let kind = match field(0, 1)?[0] {
+            0 => EntryKind::File,
+            1 => EntryKind::Directory,
+            kind => return Err(LzwError::Format(format!("Unknown entry kind {}", kind))),
+        };
+        let path_len = u16::from_le_bytes(field(41, 2)?.try_into().unwrap()) as usize;
+        let path = String::from_utf8(field(43, path_len)?.to_vec())
+            .map_err(|_| LzwError::Format("Entry path is not UTF-8".to_string()))?;
+        let entry = ArchiveEntry {
+            path: check_path(&path)?,
+            kind,
+            mode: u32_at(1)?,
+            mtime: u64_at(5)? as i64,
+            size: u64_at(13)?,
+            checksum: u32_at(21)?,
+            data_offset: u64_at(25)?,
+            data_len: u64_at(33)?,
+        };
+        Ok((entry, 43 + path_len))
+    }
+}
+
+// Paths must be relative and stay inside the archive, returned with / between components
+fn check_path(path: &str) -> Result<String, LzwError> {
+    let mut parts = Vec::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => parts
+                .push(part.to_str().ok_or_else(|| {
+                    LzwError::Format(format!("Entry path {} is not UTF-8", path))
+                })?),
+            Component::CurDir => {}
+            _ => {
+                return Err(LzwError::Format(format!(
+                    "Entry path {} is not inside the archive",
+                    path
+                )))
+            }
+        }
+    }
+    if parts.is_empty() || path.len() > u16::MAX as usize {
+        return Err(LzwError::Format(format!(
+            "Entry path {:?} is not a valid path",
+            path
+        )));
+    }
+    Ok(parts.join("/"))
+}
+
+fn io_error(context: &str) -> impl Fn(std::io::Error) -> LzwError + '_ {
+    move |e| LzwError::Io(format!("{}: {}", context, e))
+}
+
+fn mtime_of(metadata: &fs::Metadata) -> i64 {
+    match metadata.modified() {
+        Ok(time) => match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => after.as_secs() as i64,
+            Err(before) => -(before.duration().as_secs() as i64),
+        },
+        Err(_) => 0,
+    }
+}
+
+#[cfg(unix)]
+fn mode_of(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode_of(metadata: &fs::Metadata) -> u32 {
+    match (metadata.is_dir(), metadata.permissions().readonly()) {
+        (true, _) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o644,
+    }
+}
+
+pub struct ArchiveWriter<W: Write> {
+    output: W,
+    position: u64,
+    entries: Vec<ArchiveEntry>,
+    spec: LzwSpec,
+    block_size: usize,
+    threads: usize,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(
+        mut output: W,
+        spec: LzwSpec,
+        block_size: usize,
+        threads: usize,
+    ) -> Result<ArchiveWriter<W>, LzwError> {
+        spec.validate()?;
+        if !matches!(spec.alphabet, Alphabet::Bytes) {
+            return Err(LzwError::Spec(
+                "Archives need the bytes alphabet, as files can hold any byte".to_string(),
+            ));
+        }
+        output
+            .write_all(MAGIC)
+            .and_then(|_| output.write_all(&[VERSION]))
+            .map_err(io_error("Writing archive"))?;
+        Ok(ArchiveWriter {
+            output,
+            position: MAGIC.len() as u64 + 1,
+            entries: Vec::new(),
+            spec,
+            block_size,
+            threads,
+        })
+    }
+
+    pub fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    pub fn add_file(
+        &mut self,
+        path: &str,
+        mode: u32,
+        mtime: i64,
+        contents: &[u8],
+    ) -> Result<(), LzwError> {
+        let path = check_path(path)?;
+        let compressed = block_mode::compress_blocks::<TrieDictionary<u8>>(
+            self.spec,
+            contents,
+            self.block_size,
+            self.threads,
+        )?;
+        self.output
+            .write_all(&compressed)
+            .map_err(io_error("Writing archive"))?;
+        self.entries.push(ArchiveEntry {
+            path,
+            kind: EntryKind::File,
+            mode,
+            mtime,
+            size: contents.len() as u64,
+            checksum: crc32(contents),
+            data_offset: self.position,
+            data_len: compressed.len() as u64,
+        });
+        self.position += compressed.len() as u64;
+        Ok(())
+    }
+
+    pub fn add_directory(&mut self, path: &str, mode: u32, mtime: i64) -> Result<(), LzwError> {
+        self.entries.push(ArchiveEntry {
+            path: check_path(path)?,
+            kind: EntryKind::Directory,
+            mode,
+            mtime,
+            size: 0,
+            checksum: 0,
+            data_offset: 0,
+            data_len: 0,
+        });
+        Ok(())
+    }
+
+    // Add a file, or a directory and everything under it, named in the archive by its last component
+    // Directories are walked in name order, so the same tree always gives the same archive
+    // Anything that is neither a file nor a directory, such as a symlink, is skipped
+    pub fn add_tree(&mut self, root: &Path) -> Result<(), LzwError> {
+        let name = root
+            .canonicalize()
+            .ok()
+            .and_then(|root| root.file_name().map(PathBuf::from))
+            .ok_or_else(|| LzwError::Io(format!("{} has no name", root.display())))?;
+        let mut pending = vec![(root.to_path_buf(), name)];
+        while let Some((source, path)) = pending.pop() {
+            let context = source.display().to_string();
+            let metadata = fs::symlink_metadata(&source).map_err(io_error(&context))?;
+            let path_name = path
+                .to_str()
+                .ok_or_else(|| LzwError::Io(format!("{} is not a UTF-8 path", path.display())))?;
+            if metadata.is_file() {
+                let mut contents = Vec::new();
+                File::open(&source)
+                    .and_then(|mut file| file.read_to_end(&mut contents))
+                    .map_err(io_error(&context))?;
+                self.add_file(
+                    path_name,
+                    mode_of(&metadata),
+                    mtime_of(&metadata),
+                    &contents,
+                )?;
+            } else if metadata.is_dir() {
+                self.add_directory(path_name, mode_of(&metadata), mtime_of(&metadata))?;
+                let mut children = fs::read_dir(&source)
+                    .and_then(|children| children.collect::<Result<Vec<_>, _>>())
+                    .map_err(io_error(&context))?;
+                children.sort_by_key(|child| child.file_name());
+                // Reversed, so they come off the stack in order
+                for child in children.iter().rev() {
+                    pending.push((child.path(), path.join(child.file_name())));
+                }
+            } else {
+                tracing::warn!("Skipping {}, which is not a file or directory", context);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W, LzwError> {
+        let mut trailer = Vec::new();
+        for entry in self.entries.iter() {
+            entry.write_to(&mut trailer);
+        }
+        trailer.extend_from_slice(&self.position.to_le_bytes());
+        trailer.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        trailer.extend_from_slice(MAGIC);
+        self.output
+            .write_all(&trailer)
+            .and_then(|_| self.output.flush())
+            .map_err(io_error("Writing archive"))?;
+        Ok(self.output)
+    }
+}
+
+pub struct ArchiveReader<R: Read + Seek> {
+    source: R,
+    entries: Vec<ArchiveEntry>,
+    threads: usize,
+}
+
+impl<R: Read + Seek> ArchiveReader<R> {
+    // Only the start and the directory are read
+    pub fn new(mut source: R, threads: usize) -> Result<ArchiveReader<R>, LzwError> {
+        let mut start = [0; 5];
+        source
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| source.read_exact(&mut start))
+            .map_err(|_| LzwError::Format("Not an LZW archive".to_string()))?;
+        if &start[..4] != MAGIC {
+            return Err(LzwError::Format("Not an LZW archive".to_string()));
+        }
+        if start[4] != VERSION {
+            return Err(LzwError::Format(format!(
+                "Unsupported archive version {}",
+                start[4]
+            )));
+        }
+
+        let len = source
+            .seek(SeekFrom::End(0))
+            .map_err(io_error("Reading archive"))?;
+        let mut trailer = [0; TRAILER_LEN as usize];
+        source
+            .seek(SeekFrom::Start(len.saturating_sub(TRAILER_LEN)))
+            .and_then(|_| source.read_exact(&mut trailer))
+            .map_err(|_| LzwError::Format("Archive is truncated".to_string()))?;
+        let directory_offset = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+        let entry_count = u32::from_le_bytes(trailer[8..12].try_into().unwrap()) as usize;
+        if &trailer[12..] != MAGIC || directory_offset > len - TRAILER_LEN {
+            return Err(LzwError::Format("Archive is truncated".to_string()));
+        }
+
+        let mut directory = Vec::new();
+        source
+            .seek(SeekFrom::Start(directory_offset))
+            .and_then(|_| {
+                (&mut source)
+                    .take(len - TRAILER_LEN - directory_offset)
+                    .read_to_end(&mut directory)
+            })
+            .map_err(io_error("Reading archive directory"))?;
+        let mut entries = Vec::new();
+        let mut position = 0;
+        for _ in 0..entry_count {
+            let (entry, entry_len) = ArchiveEntry::read_from(&directory[position..])?;
+            if entry.data_offset.saturating_add(entry.data_len) > directory_offset {
+                return Err(LzwError::Format(format!(
+                    "Data for {} runs past the end of the archive",
+                    entry.path
+                )));
+            }
+            entries.push(entry);
+            position += entry_len;
+        }
+        Ok(ArchiveReader {
+            source,
+            entries,
+            threads,
+        })
+    }
+
+    pub fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    pub fn entry(&self, path: &str) -> Option<&ArchiveEntry> {
+        let path = check_path(path).ok()?;
+        self.entries.iter().find(|entry| entry.path == path)
+    }
+
+    // Decompress just this entry's data, checking it against its size and checksum
+    pub fn read(&mut self, entry: &ArchiveEntry) -> Result<Vec<u8>, LzwError> {
+        if entry.kind == EntryKind::Directory {
+            return Ok(Vec::new());
+        }
+        let mut compressed = vec![0; entry.data_len as usize];
+        self.source
+            .seek(SeekFrom::Start(entry.data_offset))
+            .and_then(|_| self.source.read_exact(&mut compressed))
+            .map_err(io_error(&entry.path))?;
+        let contents = block_mode::decompress_blocks(&compressed, self.threads)?;
+        if contents.len() as u64 != entry.size || crc32(&contents) != entry.checksum {
+            return Err(LzwError::Decode(format!(
+                "{} does not match its size and checksum",
+                entry.path
+            )));
+        }
+        Ok(contents)
+    }
+
+    // Write the entry under root, with its permissions and modification time
+    pub fn extract(&mut self, entry: &ArchiveEntry, root: &Path) -> Result<PathBuf, LzwError> {
+        let target = root.join(&entry.path);
+        let context = target.display().to_string();
+        match entry.kind {
+            EntryKind::Directory => fs::create_dir_all(&target).map_err(io_error(&context))?,
+            EntryKind::File => {
+                let contents = self.read(entry)?;
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent).map_err(io_error(&context))?;
+                }
+                fs::write(&target, contents).map_err(io_error(&context))?;
+            }
+        }
+        set_metadata(&target, entry).map_err(io_error(&context))?;
+        Ok(target)
+    }
+
+    // Extract the entries given, directories last so adding their contents doesn't change their times
+    pub fn extract_entries(
+        &mut self,
+        entries: &[ArchiveEntry],
+        root: &Path,
+    ) -> Result<Vec<PathBuf>, LzwError> {
+        let mut extracted = Vec::with_capacity(entries.len());
+        for entry in entries.iter().filter(|entry| entry.kind == EntryKind::File) {
+            extracted.push(self.extract(entry, root)?);
+        }
+        // Deepest first, so a directory is done after everything in it
+        for entry in entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.kind == EntryKind::Directory)
+        {
+            extracted.push(self.extract(entry, root)?);
+        }
+        Ok(extracted)
+    }
+
+    pub fn extract_all(&mut self, root: &Path) -> Result<Vec<PathBuf>, LzwError> {
+        let entries = self.entries.clone();
+        self.extract_entries(&entries, root)
+    }
+}
+
+fn set_metadata(target: &Path, entry: &ArchiveEntry) -> std::io::Result<()> {
+    let mtime = if entry.mtime >= 0 {
+        UNIX_EPOCH + Duration::from_secs(entry.mtime as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(entry.mtime.unsigned_abs())
+    };
+    File::open(target)?.set_modified(mtime)?;
+    set_mode(target, entry.mode)
+}
+
+#[cfg(unix)]
+fn set_mode(target: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(target, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(target: &Path, mode: u32) -> std::io::Result<()> {
+    let mut permissions = fs::metadata(target)?.permissions();
+    permissions.set_readonly(mode & 0o200 == 0);
+    fs::set_permissions(target, permissions)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{Coding, Growth};
+    use std::io::Cursor;
+
+    const SPEC: LzwSpec = LzwSpec {
+        alphabet: Alphabet::Bytes,
+        variable_width: true,
+        width: 12,
+        min_width: 9,
+        max_width: 16,
+        end_code: true,
+        clear_code: true,
+        pack_msb_first: true,
+        early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
+    };
+
+    fn sample_archive() -> Vec<u8> {
+        let mut writer = ArchiveWriter::new(Vec::new(), SPEC, 1000, 2).unwrap();
+        writer.add_directory("docs", 0o755, 1_600_000_000).unwrap();
+        writer
+            .add_file(
+                "docs/readme.txt",
+                0o644,
+                1_600_000_001,
+                &b"read me ".repeat(500),
+            )
+            .unwrap();
+        writer.add_file("./empty", 0o600, -5, b"").unwrap();
+        writer
+            .add_file("run.sh", 0o755, 0, b"#!/bin/sh\necho hello\n")
+            .unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn list_and_read() {
+        let archive = sample_archive();
+        let mut reader = ArchiveReader::new(Cursor::new(archive), 2).unwrap();
+        let paths: Vec<&str> = reader
+            .entries()
+            .iter()
+            .map(|entry| &entry.path[..])
+            .collect();
+        assert_eq!(paths, vec!["docs", "docs/readme.txt", "empty", "run.sh"]);
+
+        let entry = reader.entry("run.sh").unwrap().clone();
+        assert_eq!(entry.mode, 0o755);
+        assert_eq!(entry.size, 21);
+        assert_eq!(reader.read(&entry).unwrap(), b"#!/bin/sh\necho hello\n");
+        let entry = reader.entry("docs/readme.txt").unwrap().clone();
+        assert_eq!(reader.read(&entry).unwrap(), b"read me ".repeat(500));
+        assert!(entry.data_len < entry.size / 4);
+        assert_eq!(reader.entry("empty").unwrap().mtime, -5);
+        assert!(reader.entry("missing").is_none());
+    }
+
+    #[test]
+    fn damaged_archives() {
+        let archive = sample_archive();
+        assert!(ArchiveReader::new(Cursor::new(&archive[..archive.len() - 1]), 1).is_err());
+        assert!(ArchiveReader::new(Cursor::new(&b"LZWB"[..]), 1).is_err());
+
+        // Damage the compressed data of the first file, which the checksum catches
+        let mut damaged = archive.clone();
+        let entry = ArchiveReader::new(Cursor::new(&archive), 1)
+            .unwrap()
+            .entry("docs/readme.txt")
+            .unwrap()
+            .clone();
+        let last = (entry.data_offset + entry.data_len - 2) as usize;
+        damaged[last] ^= 0x10;
+        let mut reader = ArchiveReader::new(Cursor::new(damaged), 1).unwrap();
+        assert!(reader.read(&entry).is_err());
+        // The others are still fine
+        let other = reader.entry("run.sh").unwrap().clone();
+        assert!(reader.read(&other).is_ok());
+    }
+
+    #[test]
+    fn paths_stay_inside() {
+        assert_eq!(check_path("a/./b//c").unwrap(), "a/b/c");
+        for bad in ["", ".", "../etc/passwd", "a/../../b", "/etc/passwd"] {
+            assert!(check_path(bad).is_err(), "{}", bad);
+        }
+        let mut writer = ArchiveWriter::new(Vec::new(), SPEC, 1000, 1).unwrap();
+        assert!(writer.add_file("../escape", 0o644, 0, b"x").is_err());
+    }
+
+    #[test]
+    fn tree_round_trip() {
+        let scratch = std::env::temp_dir().join(format!("lzw-archive-test-{}", std::process::id()));
+        let source = scratch.join("tree");
+        fs::create_dir_all(source.join("sub/deeper")).unwrap();
+        fs::create_dir_all(source.join("empty")).unwrap();
+        fs::write(source.join("a.txt"), b"alpha alpha alpha").unwrap();
+        fs::write(source.join("sub/b.bin"), (0..=255u8).collect::<Vec<u8>>()).unwrap();
+        fs::write(source.join("sub/deeper/c.txt"), b"").unwrap();
+        set_mode(&source.join("a.txt"), 0o640).unwrap();
+
+        let mut writer = ArchiveWriter::new(Vec::new(), SPEC, 4096, 2).unwrap();
+        writer.add_tree(&source).unwrap();
+        let archive = writer.finish().unwrap();
+
+        let mut reader = ArchiveReader::new(Cursor::new(archive), 2).unwrap();
+        let paths: Vec<&str> = reader
+            .entries()
+            .iter()
+            .map(|entry| &entry.path[..])
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                "tree",
+                "tree/a.txt",
+                "tree/empty",
+                "tree/sub",
+                "tree/sub/b.bin",
+                "tree/sub/deeper",
+                "tree/sub/deeper/c.txt"
+            ]
+        );
+        let output = scratch.join("out");
+        reader.extract_all(&output).unwrap();
+        for file in ["a.txt", "sub/b.bin", "sub/deeper/c.txt"] {
+            let original = source.join(file);
+            let extracted = output.join("tree").join(file);
+            assert_eq!(fs::read(&extracted).unwrap(), fs::read(&original).unwrap());
+            let (original, extracted) = (
+                fs::metadata(original).unwrap(),
+                fs::metadata(extracted).unwrap(),
+            );
+            assert_eq!(mode_of(&extracted), mode_of(&original));
+            assert_eq!(mtime_of(&extracted), mtime_of(&original));
+        }
+        assert!(output.join("tree/empty").is_dir());
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+}
@@ -0,0 +1,382 @@
+use crate::lzw_error::LzwError;
+use crate::progress::OwnedMonitor;
+use crate::shared_dictionary::SharedDictionary;
+use crate::stream::{StreamDecoder, StreamEncoder, STREAM_CHUNK};
+use crate::LzwSpec;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/* ASYNC ADAPTERS
+    With the tokio feature, AsyncLzwWriter compresses what is written to it into another AsyncWrite,
+    and AsyncLzwReader decompresses what it reads from another AsyncRead. They run the same
+    StreamEncoder and StreamDecoder as the synchronous path, so the stream is the one compress would
+    give, see stream.rs.
+
+    Neither side lets data pile up. The writer passes compressed data on as it is written to, and
+    stops taking input while BUFFER_LIMIT bytes of it are waiting for the inner writer. The reader
+    reads READ_CHUNK bytes at a time from the inner reader, and only once everything decoded from
+    the last read has been read from it.
+    The encoder holds input back until it knows where its matches end, so flush only passes on what
+    is ready. Shutdown finishes the stream, with its End code, then shuts down the inner writer,
+    and writes after it fail.
+    Encoding errors are InvalidInput, and a damaged stream, or one cut short, is InvalidData.
+    With set_monitor, each write, or each read from the inner reader, reports the bytes taken and
+    given out so far. Once cancelled, the next one fails with an Other error, see progress.rs.
+*/
+
+pub const BUFFER_LIMIT: usize = STREAM_CHUNK;
+pub const READ_CHUNK: usize = 8 * 1024;
+
+fn invalid(kind: io::ErrorKind) -> impl Fn(LzwError) -> io::Error {
+    move |error| match error {
+        LzwError::Cancelled(_) => io::Error::other(error),
+        _ => io::Error::new(kind, error),
+    }
+}
+
+pub struct AsyncLzwWriter<W> {
+    inner: W,
+    // None once shut down
+    encoder: Option<StreamEncoder>,
+    // Compressed data, of which the inner writer has taken the first written bytes
+    output: Vec<u8>,
+    written: usize,
+    monitor: OwnedMonitor,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncLzwWriter<W> {
+    pub fn new(inner: W, spec: LzwSpec) -> Result<AsyncLzwWriter<W>, LzwError> {
+        AsyncLzwWriter::with_dictionary(inner, spec, None)
+    }
+
+    pub fn with_dictionary(
+        inner: W,
+        spec: LzwSpec,
+        shared: Option<&SharedDictionary>,
+    ) -> Result<AsyncLzwWriter<W>, LzwError> {
+        Ok(AsyncLzwWriter {
+            inner,
+            encoder: Some(StreamEncoder::with_dictionary(spec, shared)?),
+            output: Vec::new(),
+            written: 0,
+            monitor: OwnedMonitor::default(),
+        })
+    }
+
+    pub fn set_monitor(&mut self, monitor: OwnedMonitor) {
+        self.monitor = monitor;
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    // Compressed bytes waiting for the inner writer
+    pub fn waiting(&self) -> usize {
+        self.output.len() - self.written
+    }
+
+    // Pass on compressed data until it is all written, or the inner writer isn't ready for more
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.output.len() {
+            let written =
+                ready!(Pin::new(&mut self.inner).poll_write(cx, &self.output[self.written..]))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += written;
+        }
+        self.output.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncLzwWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.poll_drain(cx)?.is_pending() && this.waiting() >= BUFFER_LIMIT {
+            return Poll::Pending;
+        }
+        let Some(encoder) = this.encoder.as_mut() else {
+            return Poll::Ready(Err(io::Error::other("Write after shutdown")));
+        };
+        this.output.drain(..this.written);
+        this.written = 0;
+        // No more than a chunk at a time, so one write can't leave much more than the limit waiting
+        let len = buf.len().min(STREAM_CHUNK);
+        encoder
+            .update_with_progress(&buf[..len], &mut this.output, this.monitor.monitor())
+            .map_err(invalid(io::ErrorKind::InvalidInput))?;
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(encoder) = this.encoder.take() {
+            encoder
+                .finish(&mut this.output)
+                .map_err(invalid(io::ErrorKind::InvalidInput))?;
+        }
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+pub struct AsyncLzwReader<R> {
+    inner: R,
+    // None once the inner reader has ended
+    decoder: Option<StreamDecoder>,
+    // Decoded data, of which the first read bytes have been read
+    decoded: Vec<u8>,
+    read: usize,
+    input: Box<[u8]>,
+    monitor: OwnedMonitor,
+}
+
+impl<R: AsyncRead + Unpin> AsyncLzwReader<R> {
+    pub fn new(inner: R, spec: LzwSpec) -> Result<AsyncLzwReader<R>, LzwError> {
+        AsyncLzwReader::with_dictionary(inner, spec, None)
+    }
+
+    pub fn with_dictionary(
+        inner: R,
+        spec: LzwSpec,
+        shared: Option<&SharedDictionary>,
+    ) -> Result<AsyncLzwReader<R>, LzwError> {
+        Ok(AsyncLzwReader {
+            inner,
+            decoder: Some(StreamDecoder::with_dictionary(spec, shared)?),
+            decoded: Vec::new(),
+            read: 0,
+            input: vec![0; READ_CHUNK].into_boxed_slice(),
+            monitor: OwnedMonitor::default(),
+        })
+    }
+
+    pub fn set_monitor(&mut self, monitor: OwnedMonitor) {
+        self.monitor = monitor;
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncLzwReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.read < this.decoded.len() {
+                let len = buf.remaining().min(this.decoded.len() - this.read);
+                buf.put_slice(&this.decoded[this.read..this.read + len]);
+                this.read += len;
+                return Poll::Ready(Ok(()));
+            }
+            this.decoded.clear();
+            this.read = 0;
+
+            let Some(decoder) = this.decoder.as_mut() else {
+                return Poll::Ready(Ok(()));
+            };
+            let mut input = ReadBuf::new(&mut this.input);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut input))?;
+            if input.filled().is_empty() {
+                let decoder = this.decoder.take().unwrap();
+                decoder
+                    .finish()
+                    .map_err(invalid(io::ErrorKind::InvalidData))?;
+                return Poll::Ready(Ok(()));
+            }
+            decoder
+                .update_with_progress(input.filled(), &mut this.decoded, this.monitor.monitor())
+                .map_err(invalid(io::ErrorKind::InvalidData))?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::alphabets::Alphabet;
+    use crate::progress::{CancelToken, Progress};
+    use crate::stats::Operation;
+    use crate::trie_dictionary::TrieDictionary;
+    use crate::{Coding, Growth};
+    use std::sync::{Arc, Mutex};
+    use std::task::Waker;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const SPEC: LzwSpec = LzwSpec {
+        alphabet: Alphabet::Bytes,
+        variable_width: true,
+        width: 12,
+        min_width: 9,
+        max_width: 14,
+        end_code: true,
+        clear_code: true,
+        pack_msb_first: false,
+        early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
+    };
+
+    fn sample_input(len: usize, symbols: &[u8]) -> Vec<u8> {
+        let mut state: u32 = 11;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                symbols[(state >> 16) as usize % symbols.len()]
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn over_a_duplex() {
+        let input = sample_input(500_000, b"async stream ");
+        let whole = crate::compress::<TrieDictionary<u8>>(SPEC, &input).unwrap();
+
+        // Small enough that both sides keep having to wait for the other
+        let (client, mut server) = tokio::io::duplex(1000);
+        let mut writer = AsyncLzwWriter::new(client, SPEC).unwrap();
+        let write = async {
+            for piece in input.chunks(3000) {
+                writer.write_all(piece).await.unwrap();
+            }
+            writer.shutdown().await.unwrap();
+            assert!(writer.write(b"more").await.is_err());
+        };
+        let mut compressed = Vec::new();
+        let read = server.read_to_end(&mut compressed);
+        let (_, read) = tokio::join!(write, read);
+        read.unwrap();
+        assert_eq!(compressed, whole);
+
+        let (mut client, server) = tokio::io::duplex(777);
+        let mut reader = AsyncLzwReader::new(server, SPEC).unwrap();
+        let write = async {
+            client.write_all(&whole).await.unwrap();
+            client.shutdown().await.unwrap();
+        };
+        let read = async {
+            let mut output = Vec::new();
+            let mut piece = [0u8; 500];
+            loop {
+                let len = reader.read(&mut piece).await.unwrap();
+                if len == 0 {
+                    break output;
+                }
+                output.extend_from_slice(&piece[..len]);
+            }
+        };
+        let (_, output) = tokio::join!(write, read);
+        assert_eq!(output, input);
+    }
+
+    #[tokio::test]
+    async fn damaged_or_cut_short() {
+        let input = sample_input(20_000, b"abcd");
+        let whole = crate::compress::<TrieDictionary<u8>>(SPEC, &input).unwrap();
+
+        let mut reader = AsyncLzwReader::new(&whole[..whole.len() / 2], SPEC).unwrap();
+        let error = reader.read_to_end(&mut Vec::new()).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut damaged = whole.clone();
+        damaged[..100].fill(0xff);
+        let mut reader = AsyncLzwReader::new(&damaged[..], SPEC).unwrap();
+        let error = reader.read_to_end(&mut Vec::new()).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // Bytes outside the alphabet
+        let ascii = LzwSpec {
+            alphabet: Alphabet::Ascii,
+            ..SPEC
+        };
+        let mut writer = AsyncLzwWriter::new(Vec::new(), ascii).unwrap();
+        let error = writer.write_all(&[0u8; 100_000]).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn progress_and_cancel() {
+        let input = sample_input(100_000, b"watched ");
+        let whole = crate::compress::<TrieDictionary<u8>>(SPEC, &input).unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let cancel = CancelToken::new();
+        let observed = seen.clone();
+        let monitor = OwnedMonitor {
+            observer: Some(Arc::new(move |progress: Progress| {
+                observed.lock().unwrap().push(progress)
+            })),
+            cancel: Some(cancel.clone()),
+        };
+
+        let mut writer = AsyncLzwWriter::new(Vec::new(), SPEC).unwrap();
+        writer.set_monitor(monitor.clone());
+        writer.write_all(&input).await.unwrap();
+        writer.shutdown().await.unwrap();
+        assert_eq!(writer.get_ref(), &whole);
+        let last = *seen.lock().unwrap().last().unwrap();
+        assert_eq!(last.operation, Operation::Compress);
+        assert_eq!(last.bytes_in, input.len() as u64);
+
+        let mut reader = AsyncLzwReader::new(&whole[..], SPEC).unwrap();
+        reader.set_monitor(monitor);
+        let mut output = vec![0; 10];
+        reader.read_exact(&mut output).await.unwrap();
+        let last = *seen.lock().unwrap().last().unwrap();
+        assert_eq!(last.operation, Operation::Decompress);
+        assert_eq!(last.bytes_in, READ_CHUNK as u64);
+        cancel.cancel();
+        let error = reader.read_to_end(&mut output).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn holds_back_when_not_read() {
+        // Random bytes barely compress, so the output soon outgrows what the duplex holds
+        let input: Vec<u8> = (0..=255).collect();
+        let input = sample_input(4 << 20, &input);
+        let (client, _server) = tokio::io::duplex(1000);
+        let mut writer = AsyncLzwWriter::new(client, SPEC).unwrap();
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut taken = 0;
+        while let Poll::Ready(written) = Pin::new(&mut writer).poll_write(&mut cx, &input[taken..])
+        {
+            taken += written.unwrap();
+            assert!(taken < input.len());
+        }
+        assert!(writer.waiting() >= BUFFER_LIMIT);
+        assert!(writer.waiting() < BUFFER_LIMIT + 2 * STREAM_CHUNK);
+        assert!(taken < 4 * STREAM_CHUNK);
+        assert!(Pin::new(&mut writer).poll_flush(&mut cx).is_pending());
+    }
+}
@@ -0,0 +1,329 @@
+use crate::alphabets::Alphabet;
+use crate::block_mode::{self, run_in_parallel};
+use crate::filters::Filters;
+use crate::lzw_dictionary::LzwDictionary;
+use crate::lzw_error::LzwError;
+use crate::shared_dictionary::SharedDictionary;
+use crate::{Growth, LzwSpec};
+
+/* AUTO TUNING
+    Which spec suits an input depends on the input, so candidates are tried on a sample of it and
+    ranked by how small they make it. The sample is the whole input when it is small, and otherwise
+    SAMPLE_PIECES pieces spread evenly across it, each compressed on its own as a block would be.
+    Pieces are shorter than a default block, so the widest codes may do a little better on the
+    whole input than on the sample.
+
+    Trying every combination would take too long, so one setting is tuned at a time, in the order
+        alphabet        bytes, words, or 16 and 32 bit samples of either endianness
+        code widths     variable width, from the narrowest the alphabet allows up to one of a few maximums
+        growth          Lzw, Lzap or Lzmw
+        when full       clear the dictionary, evict the least recently used entry, or freeze it
+        early change
+    keeping whichever option did best before moving on to the next. Ties keep the earlier option.
+    Options the spec or dictionary can't support are skipped, as are sample alphabets the input or
+    block size is not a whole number of samples of.
+    The rest of the spec (end code, packing, coding and memory budget) is kept from the base spec.
+    Words can fit the sample's vocabulary and still not the whole input's, so every candidate tried
+    is returned, best first, for the caller to fall back on.
+*/
+
+pub const SAMPLE_BYTES: usize = 1024 * 1024;
+pub const SAMPLE_PIECES: usize = 4;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Candidate {
+    pub spec: LzwSpec,
+    pub sample_bytes: usize,
+    pub compressed_bytes: usize,
+}
+
+impl Candidate {
+    // Uncompressed over compressed size of the sample
+    pub fn ratio(&self) -> f64 {
+        self.sample_bytes as f64 / self.compressed_bytes.max(1) as f64
+    }
+}
+
+// The pieces of input to try the candidates on, each no longer than a block
+pub fn sample(input: &[u8], block_size: usize) -> Vec<&[u8]> {
+    let block_size = block_size.max(1);
+    if input.len() <= SAMPLE_BYTES {
+        return input.chunks(block_size).collect();
+    }
+    // A multiple of 4 bytes, and starting on one, so no sample alphabet is split
+    let piece_len = ((SAMPLE_BYTES / SAMPLE_PIECES).min(block_size) & !3).max(4);
+    (0..SAMPLE_PIECES)
+        .map(|i| {
+            let start = ((input.len() - piece_len) * i / (SAMPLE_PIECES - 1)) & !3;
+            &input[start..start + piece_len]
+        })
+        .collect()
+}
+
+// Narrowest code width that the alphabet fits in, and the widest codes worth trying with it
+fn widths_for(alphabet: Alphabet) -> (u8, &'static [u8]) {
+    match alphabet {
+        Alphabet::U16Le | Alphabet::U16Be => (17, &[18, 20]),
+        _ => (9, &[12, 14, 16]),
+    }
+}
+
+fn with_alphabet(spec: LzwSpec, alphabet: Alphabet) -> LzwSpec {
+    let (min_width, max_widths) = widths_for(alphabet);
+    let max_width = max_widths[max_widths.len() - 1];
+    LzwSpec {
+        alphabet,
+        variable_width: true,
+        width: max_width,
+        min_width,
+        max_width,
+        ..spec
+    }
+}
+
+// Try every candidate with D, and return the ones that worked, best first
+pub fn rank_specs<D: LzwDictionary<u8>>(
+    base: LzwSpec,
+    filters: Filters,
+    shared: Option<&SharedDictionary>,
+    input: &[u8],
+    block_size: usize,
+    threads: usize,
+) -> Result<Vec<Candidate>, LzwError> {
+    let pieces = sample(input, block_size);
+    let sample_bytes = pieces.iter().map(|piece| piece.len()).sum();
+    let mut tried: Vec<Candidate> = Vec::new();
+
+    // The compressed size of the sample with each spec, None for those that fail
+    let try_specs = |specs: Vec<LzwSpec>| -> Result<Vec<Option<Candidate>>, LzwError> {
+        run_in_parallel(specs, threads, |spec| {
+            let mut compressed_bytes = 0;
+            for piece in pieces.iter() {
+                match block_mode::compress_block::<D>(*spec, filters, shared, piece) {
+                    Ok(compressed) => compressed_bytes += compressed.len(),
+                    Err(e) => {
+                        tracing::debug!("Skipping {:?}: {}", spec, e);
+                        return Ok(None);
+                    }
+                }
+            }
+            Ok(Some(Candidate {
+                spec: *spec,
+                sample_bytes,
+                compressed_bytes,
+            }))
+        })
+    };
+    // Keep the best of the options, adding them all to tried
+    let mut tune = |best: Option<Candidate>, specs: Vec<LzwSpec>| -> Result<_, LzwError> {
+        let mut best = best;
+        for candidate in try_specs(specs)?.into_iter().flatten() {
+            tried.push(candidate);
+            if best.is_none_or(|best| candidate.compressed_bytes < best.compressed_bytes) {
+                best = Some(candidate);
+            }
+        }
+        Ok(best)
+    };
+
+    let alphabets = [
+        Alphabet::Bytes,
+        Alphabet::Words,
+        Alphabet::U16Le,
+        Alphabet::U16Be,
+        Alphabet::U32Le,
+        Alphabet::U32Be,
+    ];
+    let specs = alphabets
+        .into_iter()
+        .filter(|alphabet| {
+            input.len().is_multiple_of(alphabet.sample_bytes())
+                && block_size.is_multiple_of(alphabet.sample_bytes())
+        })
+        .map(|alphabet| with_alphabet(base, alphabet))
+        .filter(|spec| spec.validate().is_ok())
+        .collect();
+    let Some(mut best) = tune(None, specs)? else {
+        return Err(LzwError::Spec(
+            "None of the candidate specs could compress the input".to_string(),
+        ));
+    };
+
+    // Each setting in turn, leaving out the option best already has
+    let spec = best.spec;
+    let (_, max_widths) = widths_for(spec.alphabet);
+    let specs = max_widths
+        .iter()
+        .filter(|max_width| **max_width != spec.max_width)
+        .map(|max_width| LzwSpec {
+            width: *max_width,
+            max_width: *max_width,
+            ..spec
+        })
+        .collect();
+    best = tune(Some(best), specs)?.unwrap_or(best);
+
+    let spec = best.spec;
+    let specs = [Growth::Lzw, Growth::Lzap, Growth::Lzmw]
+        .into_iter()
+        .filter(|growth| *growth != spec.growth)
+        .map(|growth| LzwSpec { growth, ..spec })
+        .filter(|spec| spec.validate().is_ok())
+        .collect();
+    best = tune(Some(best), specs)?.unwrap_or(best);
+
+    let spec = best.spec;
+    let specs = [(true, false), (false, true), (false, false)]
+        .into_iter()
+        .filter(|(clear_code, evict_lru)| {
+            (*clear_code, *evict_lru) != (spec.clear_code, spec.evict_lru)
+        })
+        .map(|(clear_code, evict_lru)| LzwSpec {
+            clear_code,
+            evict_lru,
+            ..spec
+        })
+        .filter(|spec| spec.validate().is_ok())
+        .collect();
+    best = tune(Some(best), specs)?.unwrap_or(best);
+
+    let spec = best.spec;
+    tune(
+        Some(best),
+        vec![LzwSpec {
+            early_change: !spec.early_change,
+            ..spec
+        }],
+    )?;
+
+    // Stable, so ties keep the order they were tried in
+    tried.sort_by_key(|candidate| candidate.compressed_bytes);
+    Ok(tried)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::filters::NO_FILTERS;
+    use crate::trie_dictionary::TrieDictionary;
+    use crate::{Coding, Growth};
+
+    const BASE_SPEC: LzwSpec = LzwSpec {
+        alphabet: Alphabet::Bytes,
+        variable_width: false,
+        width: 12,
+        min_width: 9,
+        max_width: 16,
+        end_code: true,
+        clear_code: false,
+        pack_msb_first: true,
+        early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
+    };
+
+    // Log lines with a counter and some noise
+    fn logs(lines: u32) -> Vec<u8> {
+        let mut state: u32 = 5;
+        let mut input = Vec::new();
+        for i in 0..lines {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            let level = ["INFO", "WARN", "INFO", "DEBUG"][(state >> 30) as usize];
+            input.extend_from_slice(
+                format!(
+                    "{} request {} served in {} ms\n",
+                    level,
+                    i,
+                    (state >> 20) % 300
+                )
+                .as_bytes(),
+            );
+        }
+        input
+    }
+
+    #[test]
+    fn sample_pieces() {
+        let input = vec![7u8; 100];
+        assert_eq!(sample(&input, 30).len(), 4);
+        assert_eq!(sample(&input, 1000), vec![&input[..]]);
+        assert!(sample(&[], 1000).is_empty());
+
+        let input = vec![0u8; 3 * SAMPLE_BYTES + 3];
+        let pieces = sample(&input, block_mode::DEFAULT_BLOCK_SIZE);
+        assert_eq!(pieces.len(), SAMPLE_PIECES);
+        for piece in pieces.iter() {
+            assert_eq!(piece.len(), SAMPLE_BYTES / SAMPLE_PIECES);
+            let start = piece.as_ptr() as usize - input.as_ptr() as usize;
+            assert_eq!(start % 4, 0);
+        }
+        assert_eq!(sample(&input, 1000)[0].len(), 1000);
+    }
+
+    #[test]
+    fn picks_the_smallest() {
+        let input = logs(20000);
+        let ranked =
+            rank_specs::<TrieDictionary<u8>>(BASE_SPEC, NO_FILTERS, None, &input, 1 << 20, 4)
+                .unwrap();
+        assert!(ranked
+            .windows(2)
+            .all(|pair| pair[0].compressed_bytes <= pair[1].compressed_bytes));
+        // Every setting got a try
+        assert!(ranked.len() >= 8);
+
+        let best = ranked[0];
+        assert!(best.spec.validate().is_ok());
+        assert_eq!(best.sample_bytes, input.len());
+        let base_len =
+            block_mode::compress_block::<TrieDictionary<u8>>(BASE_SPEC, NO_FILTERS, None, &input)
+                .unwrap()
+                .len();
+        assert!(best.compressed_bytes < base_len);
+        assert!(best.ratio() > 1.0);
+
+        let compressed =
+            block_mode::compress_blocks::<TrieDictionary<u8>>(best.spec, &input, 1 << 20, 4)
+                .unwrap();
+        assert_eq!(
+            block_mode::decompress_blocks(&compressed, 4).unwrap(),
+            input
+        );
+    }
+
+    #[test]
+    fn skips_what_cant_work() {
+        // An odd length can't be samples
+        let mut input = logs(500);
+        if input.len().is_multiple_of(2) {
+            input.pop();
+        }
+        let ranked =
+            rank_specs::<TrieDictionary<u8>>(BASE_SPEC, NO_FILTERS, None, &input, 1 << 20, 2)
+                .unwrap();
+        assert!(ranked
+            .iter()
+            .all(|candidate| candidate.spec.alphabet.sample_bytes() == 1));
+
+        // No Lzmw with a budget, and nothing with so little of one
+        let budget = LzwSpec {
+            memory_budget: Some(1 << 20),
+            ..BASE_SPEC
+        };
+        let ranked =
+            rank_specs::<TrieDictionary<u8>>(budget, NO_FILTERS, None, &input, 1 << 20, 2).unwrap();
+        assert!(ranked
+            .iter()
+            .all(|candidate| candidate.spec.growth != Growth::Lzmw));
+        let tiny = LzwSpec {
+            memory_budget: Some(1000),
+            ..BASE_SPEC
+        };
+        assert!(
+            rank_specs::<TrieDictionary<u8>>(tiny, NO_FILTERS, None, &input, 1 << 20, 2).is_err()
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use crate::checkpoint::{self, StateReader};
+use crate::lzw_error::LzwError;
+
+// Codes are rarely a whole number of bytes wide, so they are packed across byte boundaries
+// MSB first: each code fills from the high bits of the current byte (TIFF, PDF)
+// LSB first: each code fills from the low bits of the current byte (GIF)
+// The last byte is padded with zeros
+
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    buffered_bits: u8,
+    pack_msb_first: bool,
+}
+
+impl BitWriter {
+    pub fn new(pack_msb_first: bool) -> BitWriter {
+        BitWriter {
+            bytes: Vec::new(),
+            buffer: 0,
+            buffered_bits: 0,
+            pack_msb_first,
+        }
+    }
+
+    pub fn write(&mut self, code: u32, width: u8) {
+        let code = code as u64 & ((1 << width) - 1);
+        if self.pack_msb_first {
+            self.buffer = (self.buffer << width) | code;
+            self.buffered_bits += width;
+            while self.buffered_bits >= 8 {
+                self.buffered_bits -= 8;
+                self.bytes.push((self.buffer >> self.buffered_bits) as u8);
+            }
+            self.buffer &= (1 << self.buffered_bits) - 1;
+        } else {
+            self.buffer |= code << self.buffered_bits;
+            self.buffered_bits += width;
+            while self.buffered_bits >= 8 {
+                self.bytes.push(self.buffer as u8);
+                self.buffer >>= 8;
+                self.buffered_bits -= 8;
+            }
+        }
+    }
+
+    // The whole bytes written so far, leaving the last partial byte to be written with the next codes
+    pub fn take_bytes(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.bytes)
+    }
+
+    // The partial byte, for a checkpoint, see checkpoint.rs
+    pub fn write_state(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&[self.buffer as u8, self.buffered_bits]);
+    }
+
+    // The bytes already taken are not part of the state
+    pub fn read_state(
+        pack_msb_first: bool,
+        reader: &mut StateReader,
+    ) -> Result<BitWriter, LzwError> {
+        let (buffer, buffered_bits) = (reader.read_u8()?, reader.read_u8()?);
+        if buffered_bits >= 8 || buffer as u32 >> buffered_bits != 0 {
+            return Err(checkpoint::invalid(format!(
+                "{} pending bits can't be {:#x}",
+                buffered_bits, buffer
+            )));
+        }
+        Ok(BitWriter {
+            bytes: Vec::new(),
+            buffer: buffer as u64,
+            buffered_bits,
+            pack_msb_first,
+        })
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.buffered_bits > 0 {
+            let pad = 8 - self.buffered_bits;
+            self.write(0, pad);
+        }
+        self.bytes
+    }
+}
+
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    buffer: u64,
+    buffered_bits: u8,
+    pack_msb_first: bool,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(bytes: &'a [u8], pack_msb_first: bool) -> BitReader<'a> {
+        BitReader {
+            bytes,
+            position: 0,
+            buffer: 0,
+            buffered_bits: 0,
+            pack_msb_first,
+        }
+    }
+
+    // Carry on from the bits an earlier reader was left with, for input that arrives in pieces
+    pub fn resume(bytes: &'a [u8], pack_msb_first: bool, leftover: (u64, u8)) -> BitReader<'a> {
+        let (buffer, buffered_bits) = leftover;
+        BitReader {
+            buffer,
+            buffered_bits,
+            ..BitReader::new(bytes, pack_msb_first)
+        }
+    }
+
+    // Bits read from bytes but not returned yet, all that is left once read has returned None
+    pub fn leftover(&self) -> (u64, u8) {
+        (self.buffer, self.buffered_bits)
+    }
+
+    // Bytes read so far, counting the one holding the last bits read
+    pub fn bytes_read(&self) -> usize {
+        self.position
+    }
+
+    // Returns None if fewer than width bits are left, which is just the padding at the end
+    pub fn read(&mut self, width: u8) -> Option<u32> {
+        while self.buffered_bits < width {
+            let byte = *self.bytes.get(self.position)? as u64;
+            self.position += 1;
+            if self.pack_msb_first {
+                self.buffer = (self.buffer << 8) | byte;
+            } else {
+                self.buffer |= byte << self.buffered_bits;
+            }
+            self.buffered_bits += 8;
+        }
+
+        let mask = (1 << width) - 1;
+        if self.pack_msb_first {
+            self.buffered_bits -= width;
+            let code = (self.buffer >> self.buffered_bits) & mask;
+            self.buffer &= (1 << self.buffered_bits) - 1;
+            Some(code as u32)
+        } else {
+            let code = self.buffer & mask;
+            self.buffer >>= width;
+            self.buffered_bits -= width;
+            Some(code as u32)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn msb_first() {
+        let mut writer = BitWriter::new(true);
+        writer.write(0b101, 3);
+        writer.write(0b1_1111_0000, 9);
+        let bytes = writer.finish();
+        assert_eq!(bytes, vec![0b1011_1111, 0b0000_0000]);
+
+        let mut reader = BitReader::new(&bytes, true);
+        assert_eq!(reader.read(3), Some(0b101));
+        assert_eq!(reader.read(9), Some(0b1_1111_0000));
+        // Only the 4 bits of padding left
+        assert_eq!(reader.read(9), None);
+    }
+
+    #[test]
+    fn lsb_first() {
+        let mut writer = BitWriter::new(false);
+        writer.write(0b101, 3);
+        writer.write(0b1_1111_0000, 9);
+        let bytes = writer.finish();
+        assert_eq!(bytes, vec![0b1000_0101, 0b0000_1111]);
+
+        let mut reader = BitReader::new(&bytes, false);
+        assert_eq!(reader.read(3), Some(0b101));
+        assert_eq!(reader.read(9), Some(0b1_1111_0000));
+        assert_eq!(reader.read(9), None);
+    }
+
+    #[test]
+    fn mixed_widths_round_trip() {
+        for pack_msb_first in [true, false] {
+            let mut writer = BitWriter::new(pack_msb_first);
+            let codes: Vec<(u32, u8)> = (0..1000)
+                .map(|i| (i * 7 % 4096, 12 - (i % 4) as u8))
+                .collect();
+            for (code, width) in codes.iter() {
+                writer.write(*code, *width);
+            }
+            let bytes = writer.finish();
+
+            let mut reader = BitReader::new(&bytes, pack_msb_first);
+            for (code, width) in codes.iter() {
+                assert_eq!(reader.read(*width), Some(code & ((1 << width) - 1)));
+            }
+        }
+    }
+}
@@ -0,0 +1,826 @@
+use crate::container::{BlockEntry, ContainerHeader};
+use crate::filters::{Filters, NO_FILTERS};
+use crate::lzw_dictionary::LzwDictionary;
+use crate::lzw_error::LzwError;
+use crate::progress::{Monitor, Tracker, UNMONITORED};
+use crate::recovery::{self, LostRange};
+use crate::shared_dictionary::SharedDictionary;
+use crate::stats::{CodeStats, Operation};
+use crate::LzwSpec;
+use std::thread;
+
+/* BLOCK MODE
+    The input is split into blocks of block_size bytes, and each block is compressed on its own,
+    starting from a fresh dictionary. Blocks don't depend on each other, so they can be compressed
+    and decompressed on as many threads as are available.
+    The blocks are always written in input order, so the output is the same whatever the thread count.
+    With a shared dictionary every block starts from it, and its ID goes in the header.
+    Filters are run over each block on its own too, see filters.rs.
+    With a sync interval each block is a resilient stream, see recovery.rs, and a damaged container can
+    still be recovered, but for its header and index.
+    The _with_stats versions also return the stats of every block merged, see stats.rs.
+    Resilient blocks are decoded a segment at a time, and their codes are not counted.
+    A container is appended to by decompressing its last block, unless that is full, and compressing
+    it again followed by the new input, with the same settings. The other blocks are copied as they are,
+    so the result is what compressing all of the input in one go would have given.
+    The _with_progress versions report each block to the Monitor's observer as it is done, and stop
+    before the next block once its token is cancelled, see progress.rs.
+*/
+
+pub const DEFAULT_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+// Run job on every item, spread over up to threads threads, keeping the results in order
+pub(crate) fn run_in_parallel<I, R, F>(
+    items: Vec<I>,
+    threads: usize,
+    job: F,
+) -> Result<Vec<R>, LzwError>
+where
+    I: Sync,
+    R: Send,
+    F: Fn(&I) -> Result<R, LzwError> + Sync,
+{
+    if items.is_empty() {
+        return Ok(Vec::new());
+    }
+    let per_thread = items.len().div_ceil(threads.max(1));
+    let job = &job;
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = items
+            .chunks(per_thread)
+            .map(|chunk| {
+                scope.spawn(move || chunk.iter().map(job).collect::<Result<Vec<_>, LzwError>>())
+            })
+            .collect();
+
+        let mut results = Vec::with_capacity(items.len());
+        for handle in handles {
+            let chunk_results = handle
+                .join()
+                .map_err(|_| LzwError::Worker("Block worker thread panicked".to_string()))?;
+            results.extend(chunk_results?);
+        }
+        Ok(results)
+    })
+}
+
+pub fn compress_blocks<D: LzwDictionary<u8>>(
+    spec: LzwSpec,
+    input: &[u8],
+    block_size: usize,
+    threads: usize,
+) -> Result<Vec<u8>, LzwError> {
+    compress_blocks_with_dictionary::<D>(spec, None, input, block_size, threads)
+}
+
+pub fn compress_blocks_with_dictionary<D: LzwDictionary<u8>>(
+    spec: LzwSpec,
+    shared: Option<&SharedDictionary>,
+    input: &[u8],
+    block_size: usize,
+    threads: usize,
+) -> Result<Vec<u8>, LzwError> {
+    compress_blocks_with_filters::<D>(spec, NO_FILTERS, shared, input, block_size, threads)
+}
+
+pub fn compress_blocks_with_filters<D: LzwDictionary<u8>>(
+    spec: LzwSpec,
+    filters: Filters,
+    shared: Option<&SharedDictionary>,
+    input: &[u8],
+    block_size: usize,
+    threads: usize,
+) -> Result<Vec<u8>, LzwError> {
+    compress_blocks_with_stats::<D>(spec, filters, shared, input, block_size, threads)
+        .map(|(output, _)| output)
+}
+
+pub fn compress_blocks_with_stats<D: LzwDictionary<u8>>(
+    spec: LzwSpec,
+    filters: Filters,
+    shared: Option<&SharedDictionary>,
+    input: &[u8],
+    block_size: usize,
+    threads: usize,
+) -> Result<(Vec<u8>, CodeStats), LzwError> {
+    compress_blocks_with_progress::<D>(
+        spec,
+        filters,
+        shared,
+        input,
+        block_size,
+        threads,
+        UNMONITORED,
+    )
+}
+
+pub fn compress_blocks_with_progress<D: LzwDictionary<u8>>(
+    spec: LzwSpec,
+    filters: Filters,
+    shared: Option<&SharedDictionary>,
+    input: &[u8],
+    block_size: usize,
+    threads: usize,
+    monitor: Monitor,
+) -> Result<(Vec<u8>, CodeStats), LzwError> {
+    spec.validate()?;
+    filters.check_alphabet(spec.alphabet)?;
+    check_block_size(spec, block_size)?;
+
+    let blocks: Vec<&[u8]> = input.chunks(block_size).collect();
+    let tracker = Tracker::new(monitor, Operation::Compress, input.len());
+    let results = run_in_parallel(blocks, threads, |block| {
+        tracker.check()?;
+        let result = compress_block_with_stats::<D>(spec, filters, shared, block)?;
+        tracker.add(block.len(), result.0.len());
+        Ok(result)
+    })?;
+    let (compressed, stats) = merge_stats(results);
+
+    let lengths = block_lengths(&compressed, input, block_size)?;
+    let dictionary_id = shared.map_or(0, |shared| shared.id());
+    let header = ContainerHeader::new(spec, filters, dictionary_id, block_size as u32, &lengths);
+    Ok((write_container(&header, compressed), stats))
+}
+
+fn merge_stats(results: Vec<(Vec<u8>, CodeStats)>) -> (Vec<Vec<u8>>, CodeStats) {
+    let mut merged = CodeStats::default();
+    let blocks = results
+        .into_iter()
+        .map(|(block, stats)| {
+            merged.merge(&stats);
+            block
+        })
+        .collect();
+    (blocks, merged)
+}
+
+// Every block is a resilient stream with a sync point every sync_interval bytes, see recovery.rs
+pub fn compress_blocks_resilient(
+    spec: LzwSpec,
+    input: &[u8],
+    block_size: usize,
+    sync_interval: usize,
+    threads: usize,
+) -> Result<Vec<u8>, LzwError> {
+    compress_blocks_resilient_with_progress(
+        spec,
+        input,
+        block_size,
+        sync_interval,
+        threads,
+        UNMONITORED,
+    )
+}
+
+pub fn compress_blocks_resilient_with_progress(
+    spec: LzwSpec,
+    input: &[u8],
+    block_size: usize,
+    sync_interval: usize,
+    threads: usize,
+    monitor: Monitor,
+) -> Result<Vec<u8>, LzwError> {
+    spec.validate()?;
+    recovery::check_spec(spec)?;
+    check_block_size(spec, block_size)?;
+    // Sync points past the end of a block would never be reached
+    let sync_interval = sync_interval.min(block_size);
+
+    let blocks: Vec<&[u8]> = input.chunks(block_size).collect();
+    let tracker = Tracker::new(monitor, Operation::Compress, input.len());
+    let compressed = run_in_parallel(blocks, threads, |block| {
+        tracker.check()?;
+        let compressed = recovery::compress_resilient(spec, block, sync_interval)?;
+        tracker.add(block.len(), compressed.len());
+        Ok(compressed)
+    })?;
+
+    let lengths = block_lengths(&compressed, input, block_size)?;
+    let header = ContainerHeader {
+        sync_interval: sync_interval as u32,
+        ..ContainerHeader::new(spec, NO_FILTERS, 0, block_size as u32, &lengths)
+    };
+    Ok(write_container(&header, compressed))
+}
+
+pub(crate) fn check_block_size(spec: LzwSpec, block_size: usize) -> Result<(), LzwError> {
+    if block_size == 0 || block_size > u32::MAX as usize {
+        return Err(LzwError::Spec(format!(
+            "Block size {} is out of range",
+            block_size
+        )));
+    }
+    // Blocks must not split a sample
+    if !block_size.is_multiple_of(spec.alphabet.sample_bytes()) {
+        return Err(LzwError::Spec(format!(
+            "Block size {} is not a whole number of {} byte samples",
+            block_size,
+            spec.alphabet.sample_bytes()
+        )));
+    }
+    Ok(())
+}
+
+// Compressed and uncompressed length of every block, for the index
+fn block_lengths(
+    compressed: &[Vec<u8>],
+    input: &[u8],
+    block_size: usize,
+) -> Result<Vec<(u32, u32)>, LzwError> {
+    let mut lengths = Vec::with_capacity(compressed.len());
+    for (block, uncompressed) in compressed.iter().zip(input.chunks(block_size)) {
+        let length = u32::try_from(block.len()).map_err(|_| {
+            LzwError::Encode("Compressed block is too large for the index".to_string())
+        })?;
+        lengths.push((length, uncompressed.len() as u32));
+    }
+    Ok(lengths)
+}
+
+fn write_container(header: &ContainerHeader, compressed: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut output = Vec::new();
+    header.write_to(&mut output);
+    for block in compressed {
+        output.extend_from_slice(&block);
+    }
+    output
+}
+
+// One block's output: the primary index if there is one, then the codes
+pub fn compress_block<D: LzwDictionary<u8>>(
+    spec: LzwSpec,
+    filters: Filters,
+    shared: Option<&SharedDictionary>,
+    block: &[u8],
+) -> Result<Vec<u8>, LzwError> {
+    compress_block_with_stats::<D>(spec, filters, shared, block).map(|(output, _)| output)
+}
+
+pub fn compress_block_with_stats<D: LzwDictionary<u8>>(
+    spec: LzwSpec,
+    filters: Filters,
+    shared: Option<&SharedDictionary>,
+    block: &[u8],
+) -> Result<(Vec<u8>, CodeStats), LzwError> {
+    if filters.is_empty() {
+        return crate::compress_with_stats::<D>(spec, shared, block);
+    }
+    let (filtered, primary) = filters.apply(block);
+    let mut output = Vec::new();
+    if let Some(primary) = primary {
+        output.extend_from_slice(&primary.to_le_bytes());
+    }
+    let (codes, stats) = crate::compress_with_stats::<D>(spec, shared, &filtered)?;
+    output.extend(codes);
+    Ok((output, stats))
+}
+
+pub fn decompress_block(
+    header: &ContainerHeader,
+    shared: Option<&SharedDictionary>,
+    block: &[u8],
+) -> Result<Vec<u8>, LzwError> {
+    decompress_block_with_stats(header, shared, block).map(|(output, _)| output)
+}
+
+pub fn decompress_block_with_stats(
+    header: &ContainerHeader,
+    shared: Option<&SharedDictionary>,
+    block: &[u8],
+) -> Result<(Vec<u8>, CodeStats), LzwError> {
+    let (spec, filters) = (header.spec, header.filters);
+    if header.sync_interval > 0 {
+        let (output, lost, stats) =
+            recovery::decompress_resilient_with_stats(spec, block, header.block_size as u64)?;
+        if let Some(range) = lost.first() {
+            return Err(LzwError::Decode(format!(
+                "Block is damaged from byte {} of its compressed data",
+                range.compressed.start
+            )));
+        }
+        return Ok((output, stats));
+    }
+    let (primary, codes) = if filters.bwt {
+        let (primary, codes) = block.split_at_checked(4).ok_or(LzwError::Format(
+            "Block is too short for its primary index".to_string(),
+        ))?;
+        (Some(u32::from_le_bytes(primary.try_into().unwrap())), codes)
+    } else {
+        (None, block)
+    };
+    let (decompressed, stats) = crate::decompress_with_stats(spec, shared, codes)?;
+    Ok((filters.undo(decompressed, primary)?, stats))
+}
+
+fn block_data<'a>(
+    input: &'a [u8],
+    header_len: usize,
+    block: &BlockEntry,
+) -> Result<&'a [u8], LzwError> {
+    let start = usize::try_from(block.compressed_offset)
+        .ok()
+        .and_then(|offset| offset.checked_add(header_len));
+    let end = start.and_then(|start| start.checked_add(block.compressed_len as usize));
+    start
+        .zip(end)
+        .and_then(|(start, end)| input.get(start..end))
+        .ok_or(LzwError::Format(
+            "Block index runs past the end of the input".to_string(),
+        ))
+}
+
+pub fn decompress_blocks(input: &[u8], threads: usize) -> Result<Vec<u8>, LzwError> {
+    decompress_blocks_with_dictionary(input, None, threads)
+}
+
+pub fn decompress_blocks_with_dictionary(
+    input: &[u8],
+    shared: Option<&SharedDictionary>,
+    threads: usize,
+) -> Result<Vec<u8>, LzwError> {
+    decompress_blocks_with_stats(input, shared, threads).map(|(output, _)| output)
+}
+
+pub fn decompress_blocks_with_stats(
+    input: &[u8],
+    shared: Option<&SharedDictionary>,
+    threads: usize,
+) -> Result<(Vec<u8>, CodeStats), LzwError> {
+    decompress_blocks_with_progress(input, shared, threads, UNMONITORED)
+}
+
+pub fn decompress_blocks_with_progress(
+    input: &[u8],
+    shared: Option<&SharedDictionary>,
+    threads: usize,
+    monitor: Monitor,
+) -> Result<(Vec<u8>, CodeStats), LzwError> {
+    let (header, header_len) = ContainerHeader::read_from(input)?;
+    header.check_dictionary(shared)?;
+
+    let blocks = header
+        .blocks
+        .iter()
+        .map(|block| block_data(input, header_len, block))
+        .collect::<Result<Vec<&[u8]>, LzwError>>()?;
+
+    let total_in = blocks.iter().map(|block| block.len()).sum();
+    let tracker = Tracker::new(monitor, Operation::Decompress, total_in);
+    let results = run_in_parallel(blocks, threads, |block| {
+        tracker.check()?;
+        let result = decompress_block_with_stats(&header, shared, block)?;
+        tracker.add(block.len(), result.0.len());
+        Ok(result)
+    })?;
+    let (decompressed, stats) = merge_stats(results);
+    Ok((decompressed.concat(), stats))
+}
+
+// Decompress what can be read of a damaged container, with zeros in place of what was lost
+// Blocks of a resilient container are recovered from their next sync point on, others are lost whole
+pub fn recover_blocks(
+    input: &[u8],
+    shared: Option<&SharedDictionary>,
+    threads: usize,
+) -> Result<(Vec<u8>, Vec<LostRange>), LzwError> {
+    let (header, header_len) = ContainerHeader::read_from(input)?;
+    header.check_dictionary(shared)?;
+
+    // A truncated container still has the start of the block it stops in
+    let blocks: Vec<(BlockEntry, &[u8])> = header
+        .blocks
+        .iter()
+        .map(|block| {
+            let start = usize::try_from(block.compressed_offset)
+                .ok()
+                .and_then(|offset| offset.checked_add(header_len))
+                .map_or(input.len(), |start| start.min(input.len()));
+            let end = start
+                .saturating_add(block.compressed_len as usize)
+                .min(input.len());
+            (*block, &input[start..end])
+        })
+        .collect();
+
+    let recovered = run_in_parallel(blocks, threads, |(block, data)| {
+        recover_block(&header, shared, block, data)
+    })?;
+    let mut output = Vec::new();
+    let mut lost = Vec::new();
+    for (block, (data, block_lost)) in header.blocks.iter().zip(recovered) {
+        output.extend(data);
+        let compressed_offset = (header_len as u64).saturating_add(block.compressed_offset);
+        lost.extend(block_lost.into_iter().map(|range| {
+            LostRange {
+                compressed: range.compressed.start.saturating_add(compressed_offset)
+                    ..range.compressed.end.saturating_add(compressed_offset),
+                uncompressed_start: range.uncompressed_start + block.uncompressed_offset,
+                uncompressed_end: range
+                    .uncompressed_end
+                    .map(|end| end + block.uncompressed_offset),
+            }
+        }));
+    }
+    Ok((output, lost))
+}
+
+// Positions in the ranges are relative to the block, which always comes back at its full length
+fn recover_block(
+    header: &ContainerHeader,
+    shared: Option<&SharedDictionary>,
+    block: &BlockEntry,
+    data: &[u8],
+) -> Result<(Vec<u8>, Vec<LostRange>), LzwError> {
+    let uncompressed_len = block.uncompressed_len as usize;
+    let (mut output, mut lost) = if header.sync_interval > 0 {
+        recovery::decompress_resilient(header.spec, data, uncompressed_len as u64)?
+    } else {
+        match decompress_block(header, shared, data) {
+            Ok(output) if output.len() == uncompressed_len => (output, Vec::new()),
+            _ => (Vec::new(), Vec::new()),
+        }
+    };
+    output.truncate(uncompressed_len);
+
+    if output.len() < uncompressed_len {
+        match lost.last_mut() {
+            Some(range) if range.uncompressed_end.is_none() => {
+                range.compressed.end = block.compressed_len as u64;
+                range.uncompressed_end = Some(uncompressed_len as u64);
+            }
+            _ => {
+                let start = if output.is_empty() {
+                    0
+                } else {
+                    data.len() as u64
+                };
+                lost.push(LostRange {
+                    compressed: start..block.compressed_len as u64,
+                    uncompressed_start: output.len() as u64,
+                    uncompressed_end: Some(uncompressed_len as u64),
+                });
+            }
+        }
+        output.resize(uncompressed_len, 0);
+    }
+    Ok((output, lost))
+}
+
+// A container with input added to the end of what it holds, see above
+pub fn append_blocks<D: LzwDictionary<u8>>(
+    container: &[u8],
+    shared: Option<&SharedDictionary>,
+    input: &[u8],
+    threads: usize,
+) -> Result<Vec<u8>, LzwError> {
+    let (header, header_len) = ContainerHeader::read_from(container)?;
+    header.check_dictionary(shared)?;
+    let block_size = header.block_size as usize;
+
+    // The blocks kept as they are, and the input to compress after them
+    let (kept, tail) = match header.blocks.split_last() {
+        Some((last, rest)) if (last.uncompressed_len as usize) < block_size => {
+            let mut tail =
+                decompress_block(&header, shared, block_data(container, header_len, last)?)?;
+            if tail.len() != last.uncompressed_len as usize {
+                return Err(LzwError::Format(
+                    "Last block's length doesn't match the index".to_string(),
+                ));
+            }
+            tail.extend_from_slice(input);
+            (rest, tail)
+        }
+        _ => (&header.blocks[..], input.to_vec()),
+    };
+
+    let compressed = if header.sync_interval > 0 {
+        compress_blocks_resilient(
+            header.spec,
+            &tail,
+            block_size,
+            header.sync_interval as usize,
+            threads,
+        )?
+    } else {
+        compress_blocks_with_filters::<D>(
+            header.spec,
+            header.filters,
+            shared,
+            &tail,
+            block_size,
+            threads,
+        )?
+    };
+    let (tail_header, tail_header_len) = ContainerHeader::read_from(&compressed)?;
+
+    let lengths: Vec<(u32, u32)> = kept
+        .iter()
+        .chain(tail_header.blocks.iter())
+        .map(|block| (block.compressed_len, block.uncompressed_len))
+        .collect();
+    let appended = ContainerHeader {
+        sync_interval: header.sync_interval,
+        ..ContainerHeader::new(
+            header.spec,
+            header.filters,
+            header.dictionary_id,
+            header.block_size,
+            &lengths,
+        )
+    };
+    let kept_len: usize = kept.iter().map(|block| block.compressed_len as usize).sum();
+    let kept_data = container
+        .get(header_len..header_len + kept_len)
+        .ok_or(LzwError::Format(
+            "Block index runs past the end of the input".to_string(),
+        ))?;
+
+    let mut output = Vec::new();
+    appended.write_to(&mut output);
+    output.extend_from_slice(kept_data);
+    output.extend_from_slice(&compressed[tail_header_len..]);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::alphabets::Alphabet;
+    use crate::trie_dictionary::TrieDictionary;
+    use crate::{Coding, Growth};
+
+    const SPEC: LzwSpec = LzwSpec {
+        alphabet: Alphabet::Bytes,
+        variable_width: true,
+        width: 12,
+        min_width: 9,
+        max_width: 12,
+        end_code: true,
+        clear_code: true,
+        pack_msb_first: false,
+        early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
+    };
+
+    fn sample_input() -> Vec<u8> {
+        let mut state: u32 = 5;
+        (0..50000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                b"the quick brown fox "[(state >> 16) as usize % 20]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn blocks_round_trip() {
+        let input = sample_input();
+        for block_size in [100, 1000, 4096, input.len(), input.len() * 2] {
+            let compressed =
+                compress_blocks::<TrieDictionary<u8>>(SPEC, &input, block_size, 4).unwrap();
+            assert_eq!(decompress_blocks(&compressed, 3).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn empty_input() {
+        let compressed = compress_blocks::<TrieDictionary<u8>>(SPEC, b"", 100, 4).unwrap();
+        assert_eq!(decompress_blocks(&compressed, 4).unwrap(), b"");
+    }
+
+    #[test]
+    fn same_output_for_any_thread_count() {
+        let input = sample_input();
+        let single = compress_blocks::<TrieDictionary<u8>>(SPEC, &input, 3000, 1).unwrap();
+        for threads in [2, 3, 8, 64] {
+            let multi = compress_blocks::<TrieDictionary<u8>>(SPEC, &input, 3000, threads).unwrap();
+            assert_eq!(single, multi);
+        }
+    }
+
+    #[test]
+    fn truncated_blocks() {
+        let input = sample_input();
+        let compressed = compress_blocks::<TrieDictionary<u8>>(SPEC, &input, 3000, 2).unwrap();
+        assert!(decompress_blocks(&compressed[..compressed.len() - 1], 2).is_err());
+        assert!(compress_blocks::<TrieDictionary<u8>>(SPEC, &input, 0, 2).is_err());
+
+        // An index entry pointing far past the end fails rather than overflowing
+        let mut bad_offset = compressed.clone();
+        let first = crate::container::FIXED_HEADER_LEN;
+        bad_offset[first..first + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            decompress_blocks(&bad_offset, 2),
+            Err(LzwError::Format(_))
+        ));
+        assert!(recover_blocks(&bad_offset, None, 2).is_err());
+    }
+
+    #[test]
+    fn shared_dictionary_blocks() {
+        let input = sample_input();
+        let samples: Vec<&[u8]> = input.chunks(200).collect();
+        let shared = SharedDictionary::train(&samples, 1000);
+        let other = SharedDictionary::train(&samples[..10], 1000);
+
+        let compressed = compress_blocks_with_dictionary::<TrieDictionary<u8>>(
+            SPEC,
+            Some(&shared),
+            &input,
+            3000,
+            2,
+        )
+        .unwrap();
+        assert_eq!(
+            decompress_blocks_with_dictionary(&compressed, Some(&shared), 2).unwrap(),
+            input
+        );
+        assert!(decompress_blocks(&compressed, 2).is_err());
+        assert!(decompress_blocks_with_dictionary(&compressed, Some(&other), 2).is_err());
+
+        let plain = compress_blocks::<TrieDictionary<u8>>(SPEC, &input, 3000, 2).unwrap();
+        assert!(decompress_blocks_with_dictionary(&plain, Some(&shared), 2).is_err());
+    }
+
+    #[test]
+    fn blocks_of_samples() {
+        let spec = LzwSpec {
+            alphabet: Alphabet::U16Be,
+            max_width: 18,
+            ..SPEC
+        };
+        let input = sample_input();
+        let compressed = compress_blocks::<TrieDictionary<u8>>(spec, &input, 3000, 2).unwrap();
+        assert_eq!(decompress_blocks(&compressed, 2).unwrap(), input);
+        // A block would end half way through a sample
+        assert!(compress_blocks::<TrieDictionary<u8>>(spec, &input, 3001, 2).is_err());
+        assert!(compress_blocks::<TrieDictionary<u8>>(spec, &input[1..], 3000, 2).is_err());
+    }
+
+    #[test]
+    fn filtered_blocks() {
+        // A slowly rising 16 bit ramp, which only compresses well once delta filtered
+        let ramp: Vec<u8> = (0..20000u16).flat_map(|i| (i * 3).to_le_bytes()).collect();
+        let plain = compress_blocks::<TrieDictionary<u8>>(SPEC, &ramp, 8192, 2).unwrap();
+        let delta = Filters {
+            delta: 2,
+            ..NO_FILTERS
+        };
+        let filtered =
+            compress_blocks_with_filters::<TrieDictionary<u8>>(SPEC, delta, None, &ramp, 8192, 2)
+                .unwrap();
+        assert!(filtered.len() * 4 < plain.len());
+        assert_eq!(decompress_blocks(&filtered, 2).unwrap(), ramp);
+
+        let input = sample_input();
+        let all = Filters {
+            delta: 1,
+            bwt: true,
+            move_to_front: true,
+        };
+        let compressed =
+            compress_blocks_with_filters::<TrieDictionary<u8>>(SPEC, all, None, &input, 3000, 2)
+                .unwrap();
+        assert_eq!(decompress_blocks(&compressed, 2).unwrap(), input);
+        let mut reader =
+            crate::seekable_reader::SeekableReader::new(std::io::Cursor::new(compressed)).unwrap();
+        let mut output = Vec::new();
+        std::io::Read::read_to_end(&mut reader, &mut output).unwrap();
+        assert_eq!(output, input);
+
+        // Filtered bytes aren't all printable
+        let ascii = LzwSpec {
+            alphabet: Alphabet::Ascii,
+            ..SPEC
+        };
+        assert!(compress_blocks_with_filters::<TrieDictionary<u8>>(
+            ascii, all, None, b"ab", 100, 1
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn recover_damaged_blocks() {
+        let input = sample_input();
+        let resilient = compress_blocks_resilient(SPEC, &input, 10000, 1000, 2).unwrap();
+        assert_eq!(decompress_blocks(&resilient, 2).unwrap(), input);
+        let (output, lost) = recover_blocks(&resilient, None, 2).unwrap();
+        assert_eq!(output, input);
+        assert!(lost.is_empty());
+
+        // Damage part of the second block
+        let (header, header_len) = ContainerHeader::read_from(&resilient).unwrap();
+        let second = header_len + header.blocks[1].compressed_offset as usize;
+        let mut damaged = resilient.clone();
+        damaged[second + 2000..second + 2010].fill(0);
+        assert!(decompress_blocks(&damaged, 2).is_err());
+
+        let (output, lost) = recover_blocks(&damaged, None, 2).unwrap();
+        assert_eq!(output.len(), input.len());
+        assert_eq!(lost.len(), 1);
+        // Only the segment holding the damage, no more than a sync interval, is lost
+        let start = lost[0].uncompressed_start as usize;
+        let end = lost[0].uncompressed_end.unwrap() as usize;
+        assert!(start >= 10000 && end <= 20000 && end - start <= 1000);
+        assert!(lost[0].compressed.contains(&(second as u64 + 2000)));
+        assert_eq!(output[..start], input[..start]);
+        assert_eq!(output[end..], input[end..]);
+
+        // Without sync points the whole block is lost, and a truncated last block too
+        let plain = compress_blocks::<TrieDictionary<u8>>(SPEC, &input, 10000, 2).unwrap();
+        let (header, header_len) = ContainerHeader::read_from(&plain).unwrap();
+        let mut damaged = plain.clone();
+        damaged[header_len + header.blocks[1].compressed_offset as usize + 100] ^= 0xff;
+        damaged.truncate(damaged.len() - 10);
+        let (output, lost) = recover_blocks(&damaged, None, 2).unwrap();
+        assert_eq!(output.len(), input.len());
+        assert_eq!(lost.len(), 2);
+        assert_eq!(lost[0].uncompressed_start, 10000);
+        assert_eq!(lost[0].uncompressed_end, Some(20000));
+        assert_eq!(lost[1].uncompressed_end, Some(input.len() as u64));
+        assert_eq!(output[..10000], input[..10000]);
+    }
+
+    #[test]
+    fn append_to_blocks() {
+        let input = sample_input();
+        // Empty, part way through a block, at the end of one, and everything
+        for split in [0, 4500, 6000, input.len()] {
+            let (start, rest) = input.split_at(split);
+            let whole = compress_blocks::<TrieDictionary<u8>>(SPEC, &input, 3000, 2).unwrap();
+            let first = compress_blocks::<TrieDictionary<u8>>(SPEC, start, 3000, 2).unwrap();
+            let appended = append_blocks::<TrieDictionary<u8>>(&first, None, rest, 2).unwrap();
+            assert_eq!(appended, whole);
+        }
+
+        // A few appends in a row, as a log is rotated into it
+        let mut container = compress_blocks::<TrieDictionary<u8>>(SPEC, b"", 3000, 2).unwrap();
+        for piece in input.chunks(7000) {
+            container = append_blocks::<TrieDictionary<u8>>(&container, None, piece, 2).unwrap();
+        }
+        assert_eq!(decompress_blocks(&container, 2).unwrap(), input);
+    }
+
+    #[test]
+    fn append_keeps_settings() {
+        let input = sample_input();
+        let (start, rest) = input.split_at(10500);
+
+        let all = Filters {
+            delta: 1,
+            bwt: true,
+            move_to_front: true,
+        };
+        let whole =
+            compress_blocks_with_filters::<TrieDictionary<u8>>(SPEC, all, None, &input, 4000, 2)
+                .unwrap();
+        let first =
+            compress_blocks_with_filters::<TrieDictionary<u8>>(SPEC, all, None, start, 4000, 2)
+                .unwrap();
+        assert_eq!(
+            append_blocks::<TrieDictionary<u8>>(&first, None, rest, 2).unwrap(),
+            whole
+        );
+
+        let samples: Vec<&[u8]> = input.chunks(200).collect();
+        let shared = SharedDictionary::train(&samples, 1000);
+        let whole = compress_blocks_with_dictionary::<TrieDictionary<u8>>(
+            SPEC,
+            Some(&shared),
+            &input,
+            4000,
+            2,
+        )
+        .unwrap();
+        let first = compress_blocks_with_dictionary::<TrieDictionary<u8>>(
+            SPEC,
+            Some(&shared),
+            start,
+            4000,
+            2,
+        )
+        .unwrap();
+        assert!(append_blocks::<TrieDictionary<u8>>(&first, None, rest, 2).is_err());
+        assert_eq!(
+            append_blocks::<TrieDictionary<u8>>(&first, Some(&shared), rest, 2).unwrap(),
+            whole
+        );
+
+        let whole = compress_blocks_resilient(SPEC, &input, 4000, 1000, 2).unwrap();
+        let first = compress_blocks_resilient(SPEC, start, 4000, 1000, 2).unwrap();
+        let appended = append_blocks::<TrieDictionary<u8>>(&first, None, rest, 2).unwrap();
+        assert_eq!(appended, whole);
+        assert!(recover_blocks(&appended, None, 2).unwrap().1.is_empty());
+
+        // A damaged last block can't be appended to
+        let mut damaged = first.clone();
+        damaged.truncate(damaged.len() - 10);
+        assert!(append_blocks::<TrieDictionary<u8>>(&damaged, None, rest, 2).is_err());
+    }
+}
@@ -0,0 +1,685 @@
+use crate::alphabets;
+use crate::bit_packing::BitWriter;
+use crate::block_mode;
+use crate::checksum::{crc32, crc32_update};
+use crate::container::ContainerHeader;
+use crate::filters::Filters;
+use crate::lzw_code::Code;
+use crate::lzw_encoder::LzwEncoder;
+use crate::lzw_error::LzwError;
+use crate::lzw_token::{ControlToken, Token};
+use crate::shared_dictionary::SharedDictionary;
+use crate::trie_dictionary::TrieDictionary;
+use crate::{Coding, LzwSpec, MAX_CODE_WIDTH};
+
+/* CHECKPOINTS
+    A long compression can be stopped part way and picked up again later, giving exactly the container
+    an uninterrupted run would have. The blocks are compressed one after another, on one thread,
+    and the compressed data is handed out as it is made, while a checkpoint holds everything else needed
+    to carry on. All integers are little endian.
+
+    magic           4 bytes     "LZWK"
+    version         1 byte
+    input_len       8 bytes     of the whole input, which must be the same when resuming
+    input_crc       4 bytes     CRC-32 of the input compressed so far
+    header                      the container header (see container.rs) indexing the blocks finished so far,
+                                which also records the spec, filters, shared dictionary and block size
+    in_block        1 byte      1 if a block is part way through, and if so
+        consumed        8 bytes     tokens of the filtered block read so far
+        compressed_len  8 bytes     of the block's data handed out so far
+        encoder                     code generator, stats and dictionary, see LzwEncoder::write_state
+        pending bits    2 bytes     the partial byte of codes not handed out yet, see BitWriter
+    checksum        4 bytes     CRC-32 of everything before it
+
+    A damaged checkpoint could otherwise still parse and quietly resume to the wrong container, so the
+    checksum is checked before anything else is read. The dictionary is still checked against itself,
+    so that a checkpoint made to pass the checksum fails rather than panics.
+    Data handed out after a checkpoint was made is made again when resuming from it, so anything
+    past compressed_len, the finished blocks plus the current one's part, must be dropped first.
+    Only byte alphabets, packed codes and the trie dictionary can be checkpointed.
+*/
+
+pub const MAGIC: &[u8; 4] = b"LZWK";
+pub const VERSION: u8 = 3;
+
+struct BlockInProgress {
+    // The block after filters
+    tokens: Vec<u8>,
+    consumed: usize,
+    encoder: LzwEncoder<u8, TrieDictionary<u8>>,
+    writer: BitWriter,
+    compressed_len: u64,
+}
+
+pub struct CheckpointedCompressor<'a> {
+    spec: LzwSpec,
+    filters: Filters,
+    shared: Option<&'a SharedDictionary>,
+    input: &'a [u8],
+    block_size: usize,
+    // Compressed and uncompressed length of every block finished, for the index
+    lengths: Vec<(u32, u32)>,
+    block: Option<BlockInProgress>,
+    // CRC-32 of the input up to position
+    input_crc: u32,
+}
+
+impl<'a> CheckpointedCompressor<'a> {
+    pub fn new(
+        spec: LzwSpec,
+        filters: Filters,
+        shared: Option<&'a SharedDictionary>,
+        input: &'a [u8],
+        block_size: usize,
+    ) -> Result<CheckpointedCompressor<'a>, LzwError> {
+        spec.validate()?;
+        if !spec.alphabet.is_bytes() || spec.coding != Coding::Packed {
+            return Err(LzwError::Spec(format!(
+                "Only byte alphabets and packed codes can be checkpointed, not {:?} with {:?} coding",
+                spec.alphabet, spec.coding
+            )));
+        }
+        filters.check_alphabet(spec.alphabet)?;
+        block_mode::check_block_size(spec, block_size)?;
+        Ok(CheckpointedCompressor {
+            spec,
+            filters,
+            shared,
+            input,
+            block_size,
+            lengths: Vec::new(),
+            block: None,
+            input_crc: 0,
+        })
+    }
+
+    // Carry on from a checkpoint, which must have been made with the same settings and input
+    pub fn resume(
+        checkpoint: &[u8],
+        spec: LzwSpec,
+        filters: Filters,
+        shared: Option<&'a SharedDictionary>,
+        input: &'a [u8],
+        block_size: usize,
+    ) -> Result<CheckpointedCompressor<'a>, LzwError> {
+        let mut compressor = CheckpointedCompressor::new(spec, filters, shared, input, block_size)?;
+        let mut reader = StateReader::new(checkpoint);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(LzwError::Format("Not a checkpoint".to_string()));
+        }
+        let version = reader.read_u8()?;
+        if version != VERSION {
+            return Err(LzwError::Format(format!(
+                "Unsupported checkpoint version {}",
+                version
+            )));
+        }
+        let Some(body_len) = checkpoint.len().checked_sub(4) else {
+            return Err(LzwError::Format("Checkpoint is truncated".to_string()));
+        };
+        let (body, checksum) = checkpoint.split_at(body_len);
+        if crc32(body) != u32::from_le_bytes(checksum.try_into().unwrap()) {
+            return Err(LzwError::Format(
+                "Checkpoint is damaged, its checksum doesn't match".to_string(),
+            ));
+        }
+        let checkpoint = body;
+        let mut reader = StateReader::new(checkpoint);
+        reader.take(MAGIC.len() + 1)?;
+        let input_len = reader.read_u64()?;
+        if input_len != input.len() as u64 {
+            return Err(LzwError::Spec(format!(
+                "Checkpoint is for an input of {} bytes, not {}",
+                input_len,
+                input.len()
+            )));
+        }
+        let input_crc = reader.read_u32()?;
+
+        let (header, header_len) = ContainerHeader::read_from(&checkpoint[reader.position()..])?;
+        reader.take(header_len)?;
+        if header.spec != spec
+            || header.filters != filters
+            || header.block_size as usize != block_size
+            || header.sync_interval != 0
+        {
+            return Err(LzwError::Spec(
+                "Checkpoint was made with a different spec, filters or block size".to_string(),
+            ));
+        }
+        header.check_dictionary(shared)?;
+        for block in header.blocks.iter() {
+            let start = block.uncompressed_offset as usize;
+            if block.uncompressed_len as usize != block_size.min(input.len().saturating_sub(start))
+            {
+                return Err(invalid(format!("Block at {} is not a whole block", start)));
+            }
+            compressor
+                .lengths
+                .push((block.compressed_len, block.uncompressed_len));
+        }
+
+        if reader.read_bool()? {
+            if compressor.is_finished() {
+                return Err(invalid("Block past the end of the input".to_string()));
+            }
+            let tokens = compressor.filtered_block();
+            let consumed = reader.read_u64()?;
+            if consumed > tokens.len() as u64 {
+                return Err(invalid(format!(
+                    "{} tokens read of a block of {}",
+                    consumed,
+                    tokens.len()
+                )));
+            }
+            let compressed_len = reader.read_u64()?;
+            let encoder = LzwEncoder::read_state(
+                spec,
+                alphabets::produce_byte_alphabet(spec.alphabet),
+                shared.map(|shared| shared.tokens()).unwrap_or_default(),
+                &mut reader,
+            )?;
+            let writer = BitWriter::read_state(spec.pack_msb_first, &mut reader)?;
+            compressor.block = Some(BlockInProgress {
+                tokens,
+                consumed: consumed as usize,
+                encoder,
+                writer,
+                compressed_len,
+            });
+        }
+        if reader.position() != checkpoint.len() {
+            return Err(invalid("Trailing bytes".to_string()));
+        }
+        compressor.input_crc = crc32(&input[..compressor.position() as usize]);
+        if compressor.input_crc != input_crc {
+            return Err(LzwError::Spec(
+                "Checkpoint is for a different input".to_string(),
+            ));
+        }
+        Ok(compressor)
+    }
+
+    fn block_start(&self) -> usize {
+        (self.lengths.len() * self.block_size).min(self.input.len())
+    }
+
+    // Input bytes compressed so far
+    pub fn position(&self) -> u64 {
+        let consumed = self.block.as_ref().map_or(0, |block| block.consumed);
+        (self.block_start() + consumed) as u64
+    }
+
+    // Compressed bytes handed out so far
+    pub fn compressed_len(&self) -> u64 {
+        let finished: u64 = self.lengths.iter().map(|(len, _)| *len as u64).sum();
+        finished + self.block.as_ref().map_or(0, |block| block.compressed_len)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.block.is_none() && self.block_start() >= self.input.len()
+    }
+
+    // The index of the blocks finished, which once every block is, goes in front of the data
+    pub fn header(&self) -> ContainerHeader {
+        ContainerHeader::new(
+            self.spec,
+            self.filters,
+            self.shared.map_or(0, |shared| shared.id()),
+            self.block_size as u32,
+            &self.lengths,
+        )
+    }
+
+    fn filtered_block(&self) -> Vec<u8> {
+        let start = self.block_start();
+        let block = &self.input[start..(start + self.block_size).min(self.input.len())];
+        self.filters.apply(block).0
+    }
+
+    fn start_block(&self, output: &mut Vec<u8>) -> Result<BlockInProgress, LzwError> {
+        let start = self.block_start();
+        let block = &self.input[start..(start + self.block_size).min(self.input.len())];
+        let (tokens, primary) = self.filters.apply(block);
+        let mut compressed_len = 0;
+        if let Some(primary) = primary {
+            output.extend_from_slice(&primary.to_le_bytes());
+            compressed_len += 4;
+        }
+        let encoder = LzwEncoder::with_dictionary(
+            self.spec,
+            alphabets::produce_byte_alphabet(self.spec.alphabet),
+            self.shared
+                .map(|shared| shared.tokens())
+                .unwrap_or_default(),
+        )?;
+        Ok(BlockInProgress {
+            tokens,
+            consumed: 0,
+            encoder,
+            writer: BitWriter::new(self.spec.pack_msb_first),
+            compressed_len,
+        })
+    }
+
+    // Compress at least max_input more bytes of input, or up to the end of it, adding the data to output
+    pub fn compress_some(
+        &mut self,
+        max_input: usize,
+        output: &mut Vec<u8>,
+    ) -> Result<(), LzwError> {
+        let position = self.position();
+        let target = position + max_input.max(1) as u64;
+        while !self.is_finished() && self.position() < target {
+            if self.block.is_none() {
+                self.block = Some(self.start_block(output)?);
+            }
+            let goal = (target - self.position()) as usize;
+            let block = self.block.as_mut().unwrap();
+            let start = output.len();
+
+            let mut tokens = block.tokens[block.consumed..]
+                .iter()
+                .map(|byte| Token::new(*byte))
+                .peekable();
+            let mut codes = Vec::new();
+            let mut more = true;
+            // Peeked tokens are still counted as left, so stopping anywhere leaves consumed right
+            while more && block.tokens.len() - block.consumed - tokens.len() < goal {
+                more = block.encoder.encode_step(&mut tokens, &mut codes)?;
+                for code in codes.drain(..) {
+                    block.writer.write(code.get_code(), code.get_used_bits());
+                }
+            }
+            block.consumed = block.tokens.len() - tokens.len();
+            output.extend(block.writer.take_bytes());
+
+            if more {
+                block.compressed_len += (output.len() - start) as u64;
+                continue;
+            }
+            let mut block = self.block.take().unwrap();
+            block.encoder.encode_end(&mut codes)?;
+            for code in codes {
+                block.writer.write(code.get_code(), code.get_used_bits());
+            }
+            output.extend(block.writer.finish());
+            let compressed_len = block.compressed_len + (output.len() - start) as u64;
+            let compressed_len = u32::try_from(compressed_len).map_err(|_| {
+                LzwError::Encode("Compressed block is too large for the index".to_string())
+            })?;
+            self.lengths
+                .push((compressed_len, block.tokens.len() as u32));
+        }
+        self.input_crc = crc32_update(
+            self.input_crc,
+            &self.input[position as usize..self.position() as usize],
+        );
+        Ok(())
+    }
+
+    pub fn checkpoint(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        output.extend_from_slice(MAGIC);
+        output.push(VERSION);
+        output.extend_from_slice(&(self.input.len() as u64).to_le_bytes());
+        output.extend_from_slice(&self.input_crc.to_le_bytes());
+        self.header().write_to(&mut output);
+        output.push(self.block.is_some() as u8);
+        if let Some(block) = &self.block {
+            output.extend_from_slice(&(block.consumed as u64).to_le_bytes());
+            output.extend_from_slice(&block.compressed_len.to_le_bytes());
+            block.encoder.write_state(&mut output);
+            block.writer.write_state(&mut output);
+        }
+        let checksum = crc32(&output);
+        output.extend_from_slice(&checksum.to_le_bytes());
+        output
+    }
+}
+
+// Reads through a checkpoint, failing rather than panicking if it is cut short
+pub struct StateReader<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(input: &'a [u8]) -> StateReader<'a> {
+        StateReader { input, position: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], LzwError> {
+        let bytes = self
+            .position
+            .checked_add(len)
+            .and_then(|end| self.input.get(self.position..end))
+            .ok_or(LzwError::Format("Checkpoint is truncated".to_string()))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, LzwError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, LzwError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(invalid(format!("{} is not a flag", other))),
+        }
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, LzwError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, LzwError> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    // A count of items at least min_bytes long each, checked against what is left
+    // so a bad count can't cause a huge allocation
+    pub fn read_count(&mut self, min_bytes: usize) -> Result<usize, LzwError> {
+        let count = self.read_u64()?;
+        let left = (self.input.len() - self.position) as u64;
+        if count.saturating_mul(min_bytes as u64) > left {
+            return Err(LzwError::Format("Checkpoint is truncated".to_string()));
+        }
+        Ok(count as usize)
+    }
+}
+
+pub fn invalid(message: String) -> LzwError {
+    LzwError::Format(format!("Checkpoint is invalid: {}", message))
+}
+
+// A byte token as a tag, 0 for a value, 1 for End and 2 for Clear, then the value
+pub fn write_token(token: Token<u8>, output: &mut Vec<u8>) {
+    match token.get_control() {
+        None => output.extend_from_slice(&[0, token.get_value().unwrap_or(0)]),
+        Some(ControlToken::End) => output.extend_from_slice(&[1, 0]),
+        Some(ControlToken::Clear) => output.extend_from_slice(&[2, 0]),
+    }
+}
+
+pub fn read_token(reader: &mut StateReader) -> Result<Token<u8>, LzwError> {
+    let (tag, value) = (reader.read_u8()?, reader.read_u8()?);
+    match tag {
+        0 => Ok(Token::new(value)),
+        1 => Ok(Token::new_control(ControlToken::End)),
+        2 => Ok(Token::new_control(ControlToken::Clear)),
+        _ => Err(invalid(format!("Unknown token tag {}", tag))),
+    }
+}
+
+// A code and the width it was handed out with
+pub fn write_code(code: Code, output: &mut Vec<u8>) {
+    output.extend_from_slice(&code.get_code().to_le_bytes());
+    output.push(code.get_used_bits());
+}
+
+pub fn read_code(reader: &mut StateReader) -> Result<Code, LzwError> {
+    let (code, used_bits) = (reader.read_u32()?, reader.read_u8()?);
+    if used_bits == 0 || used_bits > MAX_CODE_WIDTH || code >> used_bits != 0 {
+        return Err(invalid(format!(
+            "Code {} doesn't fit in {} bits",
+            code, used_bits
+        )));
+    }
+    Ok(Code::new(code, used_bits))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::alphabets::Alphabet;
+    use crate::filters::NO_FILTERS;
+    use crate::Growth;
+
+    // Narrow enough that the dictionary fills up within a block
+    const BYTES_SPEC: LzwSpec = LzwSpec {
+        alphabet: Alphabet::Bytes,
+        variable_width: true,
+        width: 10,
+        min_width: 9,
+        max_width: 10,
+        end_code: true,
+        clear_code: true,
+        pack_msb_first: true,
+        early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
+    };
+
+    const BLOCK_SIZE: usize = 8192;
+
+    fn sample_input() -> Vec<u8> {
+        let mut state: u32 = 11;
+        let mut input = Vec::new();
+        for i in 0..30000u32 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            if state >> 28 == 0 {
+                input.push((state >> 16) as u8);
+            } else {
+                input.push(b"the cat sat on the mat "[(i % 23) as usize]);
+            }
+        }
+        input
+    }
+
+    // Stop after every interval bytes, resuming from the checkpoint with some stray data to drop
+    fn compress_interrupted(
+        spec: LzwSpec,
+        filters: Filters,
+        shared: Option<&SharedDictionary>,
+        input: &[u8],
+        interval: usize,
+    ) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut compressor =
+            CheckpointedCompressor::new(spec, filters, shared, input, BLOCK_SIZE).unwrap();
+        while !compressor.is_finished() {
+            compressor.compress_some(interval, &mut data).unwrap();
+            let checkpoint = compressor.checkpoint();
+            data.extend_from_slice(b"lost");
+            compressor = CheckpointedCompressor::resume(
+                &checkpoint,
+                spec,
+                filters,
+                shared,
+                input,
+                BLOCK_SIZE,
+            )
+            .unwrap();
+            data.truncate(compressor.compressed_len() as usize);
+        }
+        let mut output = Vec::new();
+        compressor.header().write_to(&mut output);
+        output.extend(data);
+        output
+    }
+
+    #[test]
+    fn resumes_to_the_same_output() {
+        let input = sample_input();
+        let specs = [
+            BYTES_SPEC,
+            LzwSpec {
+                clear_code: false,
+                pack_msb_first: false,
+                early_change: true,
+                ..BYTES_SPEC
+            },
+            LzwSpec {
+                clear_code: false,
+                evict_lru: true,
+                ..BYTES_SPEC
+            },
+            LzwSpec {
+                growth: Growth::Lzmw,
+                ..BYTES_SPEC
+            },
+            LzwSpec {
+                growth: Growth::Lzap,
+                memory_budget: Some(300 * crate::DICTIONARY_ENTRY_BYTES),
+                ..BYTES_SPEC
+            },
+        ];
+        let filters = Filters {
+            delta: 1,
+            bwt: true,
+            move_to_front: true,
+        };
+        for spec in specs {
+            for filters in [NO_FILTERS, filters] {
+                let expected = block_mode::compress_blocks_with_filters::<TrieDictionary<u8>>(
+                    spec, filters, None, &input, BLOCK_SIZE, 2,
+                )
+                .unwrap();
+                for interval in [1000, 5000, BLOCK_SIZE, input.len()] {
+                    let output = compress_interrupted(spec, filters, None, &input, interval);
+                    assert_eq!(output, expected, "{:?} {:?} {}", spec, filters, interval);
+                }
+            }
+        }
+
+        let shared = SharedDictionary::train(&[b"the cat sat on the mat "], 100);
+        let expected = block_mode::compress_blocks_with_dictionary::<TrieDictionary<u8>>(
+            BYTES_SPEC,
+            Some(&shared),
+            &input,
+            BLOCK_SIZE,
+            2,
+        )
+        .unwrap();
+        let output = compress_interrupted(BYTES_SPEC, NO_FILTERS, Some(&shared), &input, 3000);
+        assert_eq!(output, expected);
+
+        assert_eq!(
+            compress_interrupted(BYTES_SPEC, NO_FILTERS, None, b"", 100),
+            block_mode::compress_blocks::<TrieDictionary<u8>>(BYTES_SPEC, b"", BLOCK_SIZE, 1)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn checks_what_it_resumes() {
+        let input = sample_input();
+        let mut compressor =
+            CheckpointedCompressor::new(BYTES_SPEC, NO_FILTERS, None, &input, BLOCK_SIZE).unwrap();
+        compressor.compress_some(10000, &mut Vec::new()).unwrap();
+        let checkpoint = compressor.checkpoint();
+        let resumes = |spec: LzwSpec, input: &[u8], block_size: usize| {
+            CheckpointedCompressor::resume(&checkpoint, spec, NO_FILTERS, None, input, block_size)
+                .is_ok()
+        };
+        assert!(resumes(BYTES_SPEC, &input, BLOCK_SIZE));
+
+        let mut changed = input.clone();
+        changed[500] ^= 1;
+        assert!(!resumes(BYTES_SPEC, &changed, BLOCK_SIZE));
+        assert!(!resumes(BYTES_SPEC, &input[1..], BLOCK_SIZE));
+        let wider = LzwSpec {
+            max_width: 12,
+            ..BYTES_SPEC
+        };
+        assert!(!resumes(wider, &input, BLOCK_SIZE));
+        assert!(!resumes(BYTES_SPEC, &input, 4096));
+        let shared = SharedDictionary::train(&[b"the cat sat on the mat "], 100);
+        assert!(CheckpointedCompressor::resume(
+            &checkpoint,
+            BYTES_SPEC,
+            NO_FILTERS,
+            Some(&shared),
+            &input,
+            BLOCK_SIZE
+        )
+        .is_err());
+
+        // Cut short anywhere, it fails rather than panics
+        for len in (0..checkpoint.len()).step_by(97) {
+            assert!(CheckpointedCompressor::resume(
+                &checkpoint[..len],
+                BYTES_SPEC,
+                NO_FILTERS,
+                None,
+                &input,
+                BLOCK_SIZE
+            )
+            .is_err());
+        }
+
+        for spec in [
+            LzwSpec {
+                coding: Coding::Range,
+                ..BYTES_SPEC
+            },
+            LzwSpec {
+                alphabet: Alphabet::Words,
+                ..BYTES_SPEC
+            },
+        ] {
+            assert!(
+                CheckpointedCompressor::new(spec, NO_FILTERS, None, &input, BLOCK_SIZE).is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_damaged_checkpoints() {
+        let input = sample_input();
+        let lru = LzwSpec {
+            clear_code: false,
+            evict_lru: true,
+            ..BYTES_SPEC
+        };
+        let lzmw = LzwSpec {
+            growth: Growth::Lzmw,
+            ..BYTES_SPEC
+        };
+        for spec in [BYTES_SPEC, lru, lzmw] {
+            let mut compressor =
+                CheckpointedCompressor::new(spec, NO_FILTERS, None, &input, BLOCK_SIZE).unwrap();
+            compressor.compress_some(12000, &mut Vec::new()).unwrap();
+            let checkpoint = compressor.checkpoint();
+            let resume = |checkpoint: &[u8]| {
+                CheckpointedCompressor::resume(
+                    checkpoint, spec, NO_FILTERS, None, &input, BLOCK_SIZE,
+                )
+            };
+            assert!(resume(&checkpoint).is_ok());
+
+            for i in (0..checkpoint.len()).step_by(61) {
+                let mut damaged = checkpoint.clone();
+                damaged[i] ^= 1 << (i % 8);
+                assert!(resume(&damaged).is_err(), "{:?} byte {}", spec, i);
+
+                // Even made to pass the checksum, it fails or carries on without panicking
+                let body = damaged.len() - 4;
+                let checksum = crc32(&damaged[..body]);
+                damaged[body..].copy_from_slice(&checksum.to_le_bytes());
+                if let Ok(mut compressor) = resume(&damaged) {
+                    while !compressor.is_finished() {
+                        if compressor
+                            .compress_some(input.len(), &mut Vec::new())
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,501 @@
+use crate::alphabets::Alphabet;
+use crate::filters::Filters;
+use crate::lzw_error::LzwError;
+use crate::shared_dictionary::SharedDictionary;
+use crate::{Coding, Growth, LzwSpec};
+use std::io::Read;
+
+/* CONTAINER FORMAT
+    Everything needed to decompress is stored up front, so no flags are needed when decompressing.
+    All integers are little endian.
+
+    magic           4 bytes     "LZWB"
+    version         1 byte
+    alphabet        1 byte
+    growth          1 byte      lzw, lzmw, lzap
+    coding          1 byte      packed, range
+    flags           1 byte      variable width, end code, clear code, msb first, early change, evict lru
+    width           1 byte
+    min_width       1 byte
+    max_width       1 byte
+    filters         1 byte      bwt, move to front
+    delta           1 byte      delta filter distance, 0 if none
+    memory_budget   8 bytes     dictionary memory budget in bytes, 0 if none
+    sync_interval   4 bytes     uncompressed bytes between sync points, 0 if the blocks aren't resilient
+    dictionary_id   4 bytes     shared dictionary the blocks were compressed with, 0 if none
+    block_size      4 bytes     uncompressed size of every block but the last
+    block_count     4 bytes
+    block index     24 bytes per block
+        compressed_offset       8 bytes     from the end of the header
+        compressed_len          4 bytes
+        uncompressed_offset     8 bytes
+        uncompressed_len        4 bytes
+    blocks          each an independent LZW stream, after the 4 byte primary index if the bwt filter is on
+
+    The uncompressed offsets let a reader find the block holding any position without decoding the others.
+    Both kinds of offset must follow on from the block before, so an index read from a damaged or
+    crafted file can't point anywhere the blocks couldn't be.
+*/
+
+pub const MAGIC: &[u8; 4] = b"LZWB";
+pub const VERSION: u8 = 8;
+
+// Everything up to and including block_count
+pub const FIXED_HEADER_LEN: usize = 38;
+const INDEX_ENTRY_LEN: usize = 24;
+
+const VARIABLE_WIDTH: u8 = 1;
+const END_CODE: u8 = 1 << 1;
+const CLEAR_CODE: u8 = 1 << 2;
+const PACK_MSB_FIRST: u8 = 1 << 3;
+const EARLY_CHANGE: u8 = 1 << 4;
+const EVICT_LRU: u8 = 1 << 5;
+
+const BWT: u8 = 1;
+const MOVE_TO_FRONT: u8 = 1 << 1;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BlockEntry {
+    pub compressed_offset: u64,
+    pub compressed_len: u32,
+    pub uncompressed_offset: u64,
+    pub uncompressed_len: u32,
+}
+
+#[derive(Debug)]
+pub struct ContainerHeader {
+    pub spec: LzwSpec,
+    pub filters: Filters,
+    pub sync_interval: u32,
+    pub dictionary_id: u32,
+    pub block_size: u32,
+    pub blocks: Vec<BlockEntry>,
+}
+
+fn alphabet_id(alphabet: Alphabet) -> u8 {
+    match alphabet {
+        Alphabet::_Test => 0,
+        Alphabet::Ascii => 1,
+        Alphabet::Bytes => 2,
+        Alphabet::Words => 3,
+        Alphabet::U16Le => 4,
+        Alphabet::U16Be => 5,
+        Alphabet::U32Le => 6,
+        Alphabet::U32Be => 7,
+    }
+}
+
+fn alphabet_from_id(id: u8) -> Result<Alphabet, LzwError> {
+    match id {
+        0 => Ok(Alphabet::_Test),
+        1 => Ok(Alphabet::Ascii),
+        2 => Ok(Alphabet::Bytes),
+        3 => Ok(Alphabet::Words),
+        4 => Ok(Alphabet::U16Le),
+        5 => Ok(Alphabet::U16Be),
+        6 => Ok(Alphabet::U32Le),
+        7 => Ok(Alphabet::U32Be),
+        _ => Err(LzwError::Format(format!("Unknown alphabet {}", id))),
+    }
+}
+
+fn growth_id(growth: Growth) -> u8 {
+    match growth {
+        Growth::Lzw => 0,
+        Growth::Lzmw => 1,
+        Growth::Lzap => 2,
+    }
+}
+
+fn coding_id(coding: Coding) -> u8 {
+    match coding {
+        Coding::Packed => 0,
+        Coding::Range => 1,
+    }
+}
+
+fn coding_from_id(id: u8) -> Result<Coding, LzwError> {
+    match id {
+        0 => Ok(Coding::Packed),
+        1 => Ok(Coding::Range),
+        _ => Err(LzwError::Format(format!("Unknown coding {}", id))),
+    }
+}
+
+fn growth_from_id(id: u8) -> Result<Growth, LzwError> {
+    match id {
+        0 => Ok(Growth::Lzw),
+        1 => Ok(Growth::Lzmw),
+        2 => Ok(Growth::Lzap),
+        _ => Err(LzwError::Format(format!("Unknown growth {}", id))),
+    }
+}
+
+// Reads through the header, failing rather than panicking if it is cut short
+struct HeaderReader<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> HeaderReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], LzwError> {
+        let end = self.position + len;
+        let bytes = self.input.get(self.position..end).ok_or(LzwError::Format(
+            "Container header is truncated".to_string(),
+        ))?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, LzwError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Result<u32, LzwError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, LzwError> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+impl ContainerHeader {
+    pub fn write_to(&self, output: &mut Vec<u8>) {
+        let spec = &self.spec;
+        let mut flags = 0;
+        for (set, flag) in [
+            (spec.variable_width, VARIABLE_WIDTH),
+            (spec.end_code, END_CODE),
+            (spec.clear_code, CLEAR_CODE),
+            (spec.pack_msb_first, PACK_MSB_FIRST),
+            (spec.early_change, EARLY_CHANGE),
+            (spec.evict_lru, EVICT_LRU),
+        ] {
+            if set {
+                flags |= flag;
+            }
+        }
+
+        output.extend_from_slice(MAGIC);
+        output.push(VERSION);
+        output.push(alphabet_id(spec.alphabet));
+        output.push(growth_id(spec.growth));
+        output.push(coding_id(spec.coding));
+        output.push(flags);
+        output.extend_from_slice(&[spec.width, spec.min_width, spec.max_width]);
+        let mut filters = 0;
+        if self.filters.bwt {
+            filters |= BWT;
+        }
+        if self.filters.move_to_front {
+            filters |= MOVE_TO_FRONT;
+        }
+        output.push(filters);
+        output.push(self.filters.delta);
+        output.extend_from_slice(&(spec.memory_budget.unwrap_or(0) as u64).to_le_bytes());
+        output.extend_from_slice(&self.sync_interval.to_le_bytes());
+        output.extend_from_slice(&self.dictionary_id.to_le_bytes());
+        output.extend_from_slice(&self.block_size.to_le_bytes());
+        output.extend_from_slice(&(self.blocks.len() as u32).to_le_bytes());
+        for block in self.blocks.iter() {
+            output.extend_from_slice(&block.compressed_offset.to_le_bytes());
+            output.extend_from_slice(&block.compressed_len.to_le_bytes());
+            output.extend_from_slice(&block.uncompressed_offset.to_le_bytes());
+            output.extend_from_slice(&block.uncompressed_len.to_le_bytes());
+        }
+    }
+
+    // Build the index for blocks laid out one after another, in order
+    pub fn new(
+        spec: LzwSpec,
+        filters: Filters,
+        dictionary_id: u32,
+        block_size: u32,
+        lengths: &[(u32, u32)],
+    ) -> ContainerHeader {
+        let mut blocks = Vec::with_capacity(lengths.len());
+        let mut compressed_offset = 0;
+        let mut uncompressed_offset = 0;
+        for (compressed_len, uncompressed_len) in lengths.iter() {
+            blocks.push(BlockEntry {
+                compressed_offset,
+                compressed_len: *compressed_len,
+                uncompressed_offset,
+                uncompressed_len: *uncompressed_len,
+            });
+            compressed_offset += *compressed_len as u64;
+            uncompressed_offset += *uncompressed_len as u64;
+        }
+        ContainerHeader {
+            spec,
+            filters,
+            sync_interval: 0,
+            dictionary_id,
+            block_size,
+            blocks,
+        }
+    }
+
+    // The blocks can only be decompressed with the shared dictionary they were compressed with
+    pub fn check_dictionary(&self, shared: Option<&SharedDictionary>) -> Result<(), LzwError> {
+        let id = shared.map_or(0, |shared| shared.id());
+        match (self.dictionary_id, id) {
+            (expected, id) if expected == id => Ok(()),
+            (0, _) => Err(LzwError::Format(
+                "Container was compressed without a shared dictionary".to_string(),
+            )),
+            (expected, 0) => Err(LzwError::Format(format!(
+                "Container needs shared dictionary {:08x}",
+                expected
+            ))),
+            (expected, id) => Err(LzwError::Format(format!(
+                "Container needs shared dictionary {:08x}, not {:08x}",
+                expected, id
+            ))),
+        }
+    }
+
+    pub fn uncompressed_len(&self) -> u64 {
+        self.blocks.last().map_or(0, |block| {
+            block.uncompressed_offset + block.uncompressed_len as u64
+        })
+    }
+
+    // Index of the block holding the byte at uncompressed position, if there is one
+    pub fn block_containing(&self, position: u64) -> Option<usize> {
+        if position >= self.uncompressed_len() {
+            return None;
+        }
+        let after = self
+            .blocks
+            .partition_point(|block| block.uncompressed_offset <= position);
+        Some(after - 1)
+    }
+
+    // Read just the header from the start of a stream, leaving the stream at the first block
+    pub fn read_from_reader<R: Read>(reader: &mut R) -> Result<(ContainerHeader, usize), LzwError> {
+        let mut bytes = vec![0; FIXED_HEADER_LEN];
+        reader
+            .read_exact(&mut bytes)
+            .map_err(|e| LzwError::Format(format!("Reading container header: {}", e)))?;
+        let count_bytes = &bytes[FIXED_HEADER_LEN - 4..];
+        let block_count = u32::from_le_bytes(count_bytes.try_into().unwrap()) as usize;
+
+        let mut index = Vec::new();
+        reader
+            .take((block_count * INDEX_ENTRY_LEN) as u64)
+            .read_to_end(&mut index)
+            .map_err(|e| LzwError::Format(format!("Reading block index: {}", e)))?;
+        bytes.extend_from_slice(&index);
+        ContainerHeader::read_from(&bytes)
+    }
+
+    // Returns the header and the number of bytes it took up
+    pub fn read_from(input: &[u8]) -> Result<(ContainerHeader, usize), LzwError> {
+        let mut reader = HeaderReader { input, position: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(LzwError::Format("Not an LZW container".to_string()));
+        }
+        let version = reader.read_u8()?;
+        if version != VERSION {
+            return Err(LzwError::Format(format!(
+                "Unsupported container version {}",
+                version
+            )));
+        }
+
+        let alphabet = alphabet_from_id(reader.read_u8()?)?;
+        let growth = growth_from_id(reader.read_u8()?)?;
+        let coding = coding_from_id(reader.read_u8()?)?;
+        let flags = reader.read_u8()?;
+        let mut spec = LzwSpec {
+            alphabet,
+            variable_width: flags & VARIABLE_WIDTH != 0,
+            width: reader.read_u8()?,
+            min_width: reader.read_u8()?,
+            max_width: reader.read_u8()?,
+            end_code: flags & END_CODE != 0,
+            clear_code: flags & CLEAR_CODE != 0,
+            pack_msb_first: flags & PACK_MSB_FIRST != 0,
+            early_change: flags & EARLY_CHANGE != 0,
+            evict_lru: flags & EVICT_LRU != 0,
+            growth,
+            coding,
+            memory_budget: None,
+        };
+        let filter_flags = reader.read_u8()?;
+        let filters = Filters {
+            delta: reader.read_u8()?,
+            bwt: filter_flags & BWT != 0,
+            move_to_front: filter_flags & MOVE_TO_FRONT != 0,
+        };
+        spec.memory_budget = match reader.read_u64()? {
+            0 => None,
+            budget => Some(usize::try_from(budget).map_err(|_| {
+                LzwError::Format(format!("Memory budget of {} bytes is too large", budget))
+            })?),
+        };
+        spec.validate()?;
+        let sync_interval = reader.read_u32()?;
+        if sync_interval > 0 && !filters.is_empty() {
+            return Err(LzwError::Format(
+                "Resilient blocks can't be filtered".to_string(),
+            ));
+        }
+
+        let dictionary_id = reader.read_u32()?;
+        let block_size = reader.read_u32()?;
+        let block_count = reader.read_u32()?;
+        let mut blocks: Vec<BlockEntry> = Vec::new();
+        for _ in 0..block_count {
+            let block = BlockEntry {
+                compressed_offset: reader.read_u64()?,
+                compressed_len: reader.read_u32()?,
+                uncompressed_offset: reader.read_u64()?,
+                uncompressed_len: reader.read_u32()?,
+            };
+            // Blocks must follow on from each other, so positions can be searched for
+            let (expected_compressed, expected_uncompressed) =
+                blocks.last().map_or((0, 0), |previous| {
+                    (
+                        previous.compressed_offset + previous.compressed_len as u64,
+                        previous.uncompressed_offset + previous.uncompressed_len as u64,
+                    )
+                });
+            if block.uncompressed_offset != expected_uncompressed {
+                return Err(LzwError::Format(format!(
+                    "Block {} starts at {} rather than {}",
+                    blocks.len(),
+                    block.uncompressed_offset,
+                    expected_uncompressed
+                )));
+            }
+            if block.compressed_offset != expected_compressed {
+                return Err(LzwError::Format(format!(
+                    "Block {} data starts at {} rather than {}",
+                    blocks.len(),
+                    block.compressed_offset,
+                    expected_compressed
+                )));
+            }
+            blocks.push(block);
+        }
+
+        let header = ContainerHeader {
+            spec,
+            filters,
+            sync_interval,
+            dictionary_id,
+            block_size,
+            blocks,
+        };
+        Ok((header, reader.position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::filters::NO_FILTERS;
+
+    const FILTERS: Filters = Filters {
+        delta: 2,
+        bwt: false,
+        move_to_front: true,
+    };
+
+    const SPEC: LzwSpec = LzwSpec {
+        alphabet: Alphabet::Bytes,
+        variable_width: true,
+        width: 12,
+        min_width: 9,
+        max_width: 16,
+        end_code: true,
+        clear_code: false,
+        pack_msb_first: true,
+        early_change: true,
+        evict_lru: false,
+        growth: Growth::Lzap,
+        coding: Coding::Range,
+        memory_budget: Some(1 << 20),
+    };
+
+    #[test]
+    fn header_round_trip() {
+        let header = ContainerHeader::new(
+            SPEC,
+            FILTERS,
+            0x1234,
+            100,
+            &[(10, 100), (20, 100), (30, 50)],
+        );
+        let mut bytes = Vec::new();
+        header.write_to(&mut bytes);
+        bytes.extend_from_slice(b"block data");
+
+        let (read, header_len) = ContainerHeader::read_from(&bytes).unwrap();
+        assert_eq!(header_len, bytes.len() - b"block data".len());
+        assert_eq!(read.filters, FILTERS);
+        assert_eq!(read.dictionary_id, 0x1234);
+        assert_eq!(read.block_size, header.block_size);
+        assert_eq!(read.blocks, header.blocks);
+        assert_eq!(read.blocks[2].compressed_offset, 30);
+        assert_eq!(read.blocks[2].uncompressed_offset, 200);
+        assert_eq!(read.spec.max_width, 16);
+        assert!(read.spec.early_change);
+        assert!(!read.spec.clear_code);
+        assert_eq!(read.spec.growth, Growth::Lzap);
+        assert_eq!(read.spec.coding, Coding::Range);
+        assert_eq!(read.spec.memory_budget, Some(1 << 20));
+
+        let (from_reader, reader_len) =
+            ContainerHeader::read_from_reader(&mut bytes.as_slice()).unwrap();
+        assert_eq!(reader_len, header_len);
+        assert_eq!(from_reader.blocks, header.blocks);
+    }
+
+    #[test]
+    fn find_block() {
+        let header =
+            ContainerHeader::new(SPEC, NO_FILTERS, 0, 100, &[(10, 100), (20, 100), (30, 50)]);
+        assert_eq!(header.uncompressed_len(), 250);
+        assert_eq!(header.block_containing(0), Some(0));
+        assert_eq!(header.block_containing(99), Some(0));
+        assert_eq!(header.block_containing(100), Some(1));
+        assert_eq!(header.block_containing(249), Some(2));
+        assert_eq!(header.block_containing(250), None);
+    }
+
+    #[test]
+    fn bad_headers() {
+        assert!(ContainerHeader::read_from(b"").is_err());
+        assert!(ContainerHeader::read_from(b"GIF89a").is_err());
+
+        let header = ContainerHeader::new(SPEC, NO_FILTERS, 0, 100, &[(1, 100), (2, 100)]);
+        let mut bytes = Vec::new();
+        header.write_to(&mut bytes);
+        // Cut off part of the block index
+        assert!(ContainerHeader::read_from(&bytes[..bytes.len() - 2]).is_err());
+        assert!(ContainerHeader::read_from_reader(&mut &bytes[..bytes.len() - 2]).is_err());
+
+        // Second block no longer follows on from the first
+        let mut moved = bytes.clone();
+        let last = moved.len() - 5;
+        moved[last] = 0xff;
+        assert!(ContainerHeader::read_from(&moved).is_err());
+
+        // Second block's data no longer follows on from the first's, or is far past the end
+        let second = bytes.len() - INDEX_ENTRY_LEN;
+        for offset in [0, u64::MAX] {
+            bytes[second..second + 8].copy_from_slice(&offset.to_le_bytes());
+            assert!(matches!(
+                ContainerHeader::read_from(&bytes),
+                Err(LzwError::Format(_))
+            ));
+        }
+    }
+}
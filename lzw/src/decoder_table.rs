@@ -0,0 +1,337 @@
+use crate::lzw_code::CodeGenerator;
+use crate::lzw_error::LzwError;
+use crate::lzw_token::{ControlToken, HashableToken, Token};
+use crate::LzwSpec;
+use std::collections::HashMap;
+
+/* WHY A FLAT TABLE FOR DECODING?
+    The decoder only ever asks "what sequence does this code stand for?", and codes are handed out in order.
+    So a Vec indexed by code is enough, no trie needed.
+    Every sequence in the dictionary is an earlier sequence plus one token, so each entry only stores
+        the code of that earlier sequence (its prefix)
+        the token added to it
+        the length of the whole sequence
+    Expanding a code walks the prefixes back to the start, writing tokens from the back of a buffer
+    that is already the right length. Nothing is allocated per entry, and the buffer is reused between codes.
+
+    LZMW entries join two earlier entries, so their suffix is a code rather than a token.
+    Expanding one writes the suffix's sequence first, then carries on with the prefix.
+    The longest length is kept as entries are added, so the stats never have to look through them.
+    Entries replaced by LRU are still counted.
+*/
+
+#[derive(Copy, Clone, Debug)]
+enum Suffix<T: HashableToken> {
+    Token(Token<T>),
+    Code(u32),
+}
+
+#[derive(Copy, Clone, Debug)]
+struct TableEntry<T: HashableToken> {
+    prefix_code: Option<u32>,
+    suffix: Suffix<T>,
+    length: u32,
+}
+
+pub struct DecoderTable<T: HashableToken> {
+    entries: Vec<TableEntry<T>>,
+    initial_size: usize,
+    // Longest entry added since the last reset, and the longest of the initial table
+    longest: u32,
+    initial_longest: u32,
+}
+
+impl<T: HashableToken> DecoderTable<T> {
+    // Seeded in the same order as the encoder's dictionaries: alphabet, then clear, then end
+    pub fn new(
+        lzw_spec: LzwSpec,
+        code_gen: &mut CodeGenerator,
+        alphabet: Vec<Token<T>>,
+    ) -> DecoderTable<T> {
+        let mut initial = alphabet;
+        if lzw_spec.clear_code {
+            initial.push(Token::new_control(ControlToken::Clear));
+        }
+        if lzw_spec.end_code {
+            initial.push(Token::new_control(ControlToken::End));
+        }
+
+        let mut entries = Vec::with_capacity(initial.len());
+        for token in initial {
+            if code_gen.get_next_code().is_none() {
+                panic!("Base alphabet too large for starting bit width");
+            }
+            entries.push(TableEntry {
+                prefix_code: None,
+                suffix: Suffix::Token(token),
+                length: 1,
+            });
+        }
+        let initial_size = entries.len();
+        let longest = (initial_size > 0) as u32;
+        DecoderTable {
+            entries,
+            initial_size,
+            longest,
+            initial_longest: longest,
+        }
+    }
+
+    // Add the entries of a shared dictionary, in the same order as LzwDictionary::seed
+    // They become part of the initial table, so a reset keeps them
+    pub fn seed(
+        &mut self,
+        entries: &[Vec<Token<T>>],
+        code_gen: &mut CodeGenerator,
+    ) -> Result<(), LzwError> {
+        let initial: HashMap<Token<T>, u32> = (0..self.next_code())
+            .filter_map(|code| match self.entries[code as usize].suffix {
+                Suffix::Token(token) => Some((token, code)),
+                Suffix::Code(_) => None,
+            })
+            .collect();
+        let mut seeded: HashMap<&[Token<T>], u32> = HashMap::new();
+        for entry in entries.iter() {
+            let prefix_code = match entry.split_last() {
+                Some((_, [token])) => initial.get(token),
+                Some((_, prefix)) => seeded.get(prefix),
+                None => None,
+            };
+            let Some(&prefix_code) = prefix_code else {
+                return Err(LzwError::Decode(format!(
+                    "Shared dictionary entry {:?} is not built on an earlier entry",
+                    entry
+                )));
+            };
+            if seeded.contains_key(&entry[..]) {
+                return Err(LzwError::Decode(format!(
+                    "Shared dictionary entry {:?} is already in the dictionary",
+                    entry
+                )));
+            }
+            if code_gen.get_next_code().is_none() {
+                return Err(LzwError::Decode(format!(
+                    "Shared dictionary of {} entries does not fit in the code space",
+                    entries.len()
+                )));
+            }
+            self.add(prefix_code, entry[entry.len() - 1]);
+            seeded.insert(entry, self.next_code() - 1);
+        }
+        self.initial_size = self.entries.len();
+        self.initial_longest = self.longest;
+        Ok(())
+    }
+
+    // Tokens in the longest entry
+    pub fn longest_entry(&self) -> usize {
+        self.longest as usize
+    }
+
+    // The code the next added entry will get
+    pub fn next_code(&self) -> u32 {
+        self.entries.len() as u32
+    }
+
+    pub fn contains(&self, code: u32) -> bool {
+        (code as usize) < self.entries.len()
+    }
+
+    pub fn control(&self, code: u32) -> Option<ControlToken> {
+        let entry = self.entries.get(code as usize)?;
+        match (entry.prefix_code, entry.suffix) {
+            (None, Suffix::Token(token)) => token.get_control(),
+            _ => None,
+        }
+    }
+
+    pub fn length(&self, code: u32) -> u32 {
+        self.entries[code as usize].length
+    }
+
+    pub fn add(&mut self, prefix_code: u32, last_token: Token<T>) {
+        let length = self.length(prefix_code) + 1;
+        self.longest = self.longest.max(length);
+        self.entries.push(TableEntry {
+            prefix_code: Some(prefix_code),
+            suffix: Suffix::Token(last_token),
+            length,
+        });
+    }
+
+    // Add the sequence for prefix_code followed by the sequence for suffix_code
+    pub fn add_joined(&mut self, prefix_code: u32, suffix_code: u32) {
+        let length = self.length(prefix_code) + self.length(suffix_code);
+        self.longest = self.longest.max(length);
+        self.entries.push(TableEntry {
+            prefix_code: Some(prefix_code),
+            suffix: Suffix::Code(suffix_code),
+            length,
+        });
+    }
+
+    // Reuse the code of an evicted entry, nothing can be built on it as only leaves are evicted
+    pub fn replace(&mut self, code: u32, prefix_code: u32, last_token: Token<T>) {
+        let length = self.length(prefix_code) + 1;
+        self.longest = self.longest.max(length);
+        self.entries[code as usize] = TableEntry {
+            prefix_code: Some(prefix_code),
+            suffix: Suffix::Token(last_token),
+            length,
+        };
+    }
+
+    // Forget everything added since the table was created
+    pub fn reset(&mut self) {
+        self.entries.truncate(self.initial_size);
+        self.longest = self.initial_longest;
+    }
+
+    fn last_token(&self, code: u32) -> Token<T> {
+        let mut entry = self.entries[code as usize];
+        loop {
+            match entry.suffix {
+                Suffix::Token(token) => return token,
+                Suffix::Code(suffix_code) => entry = self.entries[suffix_code as usize],
+            }
+        }
+    }
+
+    // Replace the contents of buffer with the sequence for code
+    pub fn expand_into(&self, code: u32, buffer: &mut Vec<Token<T>>) {
+        let entry = self.entries[code as usize];
+        buffer.clear();
+        let mut position = entry.length as usize;
+        buffer.resize(position, self.last_token(code));
+
+        // Prefixes still to be written, in front of what is being written now
+        let mut prefixes: Vec<u32> = Vec::new();
+        let mut next = Some(code);
+        while let Some(code) = next.take().or_else(|| prefixes.pop()) {
+            let current = self.entries[code as usize];
+            match current.suffix {
+                Suffix::Token(token) => {
+                    position -= 1;
+                    buffer[position] = token;
+                    next = current.prefix_code;
+                }
+                Suffix::Code(suffix_code) => {
+                    prefixes.extend(current.prefix_code);
+                    next = Some(suffix_code);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::alphabets;
+    use crate::{Coding, Growth};
+
+    const ASCII_SPEC: LzwSpec = LzwSpec {
+        alphabet: alphabets::Alphabet::Ascii,
+        variable_width: false,
+        width: 12,
+        min_width: 12,
+        max_width: 12,
+        end_code: true,
+        clear_code: true,
+        pack_msb_first: true,
+        early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
+    };
+
+    fn code_of(c: char) -> u32 {
+        alphabets::generate_ascii()
+            .iter()
+            .position(|t| *t == Token::new(c))
+            .unwrap() as u32
+    }
+
+    #[test]
+    fn initial_table() {
+        let alphabet = alphabets::generate_ascii();
+        let alpha_len = alphabet.len() as u32;
+        let mut code_gen = CodeGenerator::new(ASCII_SPEC);
+        let table = DecoderTable::new(ASCII_SPEC, &mut code_gen, alphabet);
+
+        assert_eq!(table.next_code(), alpha_len + 2);
+        assert_eq!(table.control(alpha_len), Some(ControlToken::Clear));
+        assert_eq!(table.control(alpha_len + 1), Some(ControlToken::End));
+        assert_eq!(table.control(0), None);
+
+        let mut buffer = Vec::new();
+        table.expand_into(code_of('A'), &mut buffer);
+        assert_eq!(buffer, vec![Token::new('A')]);
+    }
+
+    #[test]
+    fn expand_added_entries() {
+        let mut code_gen = CodeGenerator::new(ASCII_SPEC);
+        let mut table = DecoderTable::new(ASCII_SPEC, &mut code_gen, alphabets::generate_ascii());
+
+        let ab = table.next_code();
+        table.add(code_of('a'), Token::new('b'));
+        let abc = table.next_code();
+        table.add(ab, Token::new('c'));
+
+        let mut buffer = vec![Token::new('x'); 10];
+        table.expand_into(abc, &mut buffer);
+        assert_eq!(buffer, "abc".chars().map(Token::new).collect::<Vec<_>>());
+
+        table.expand_into(ab, &mut buffer);
+        assert_eq!(buffer, "ab".chars().map(Token::new).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn expand_joined_entries() {
+        let mut code_gen = CodeGenerator::new(ASCII_SPEC);
+        let mut table = DecoderTable::new(ASCII_SPEC, &mut code_gen, alphabets::generate_ascii());
+
+        let ab = table.next_code();
+        table.add(code_of('a'), Token::new('b'));
+        let xab = table.next_code();
+        table.add_joined(code_of('x'), ab);
+        let abxab = table.next_code();
+        table.add_joined(ab, xab);
+        let abxabc = table.next_code();
+        table.add(abxab, Token::new('c'));
+        let abxabcabxab = table.next_code();
+        table.add_joined(abxabc, abxab);
+
+        let mut buffer = Vec::new();
+        for (code, expected) in [
+            (xab, "xab"),
+            (abxab, "abxab"),
+            (abxabc, "abxabc"),
+            (abxabcabxab, "abxabcabxab"),
+        ] {
+            table.expand_into(code, &mut buffer);
+            assert_eq!(buffer, expected.chars().map(Token::new).collect::<Vec<_>>());
+            assert_eq!(table.length(code) as usize, expected.len());
+        }
+        assert_eq!(table.longest_entry(), 11);
+    }
+
+    #[test]
+    fn reset_table() {
+        let mut code_gen = CodeGenerator::new(ASCII_SPEC);
+        let mut table = DecoderTable::new(ASCII_SPEC, &mut code_gen, alphabets::generate_ascii());
+        let initial_next = table.next_code();
+
+        table.add(code_of('a'), Token::new('b'));
+        assert!(table.contains(initial_next));
+        assert_eq!(table.longest_entry(), 2);
+
+        table.reset();
+        assert!(!table.contains(initial_next));
+        assert_eq!(table.longest_entry(), 1);
+        assert_eq!(table.next_code(), initial_next);
+    }
+}
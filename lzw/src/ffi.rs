@@ -0,0 +1,1036 @@
+use crate::alphabets::Alphabet;
+use crate::block_mode;
+use crate::filters::NO_FILTERS;
+use crate::lzw_error::LzwError;
+use crate::progress::{CancelToken, Monitor, Progress, ProgressObserver};
+use crate::stream::{StreamDecoder, StreamEncoder};
+use crate::trie_dictionary::TrieDictionary;
+use crate::{Coding, Growth, LzwSpec};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::{ptr, slice, thread};
+
+/* C API
+    Compression and decompression for C and C++ callers, built into the cdylib, with the header
+    generated into include/lzw.h by cbindgen (see cbindgen.toml). ffi/lzw_test.c drives it from C.
+
+    Every function returns an LzwStatus, and on failure a message for the calling thread can be had
+    from lzw_last_error. Panics are caught and returned as LZW_STATUS_PANIC.
+    Output goes into an LzwBuffer owned by the library: it is appended to, can be emptied by setting
+    len to 0, and is freed with lzw_buffer_free. A zeroed LzwBuffer is an empty one.
+    Specs are passed as an LzwCSpec, with the alphabet, growth and coding as the LZW_* constants.
+
+    lzw_compress and lzw_decompress work on a bare stream, and need the same spec on both sides.
+    lzw_compress_container and lzw_decompress_container work on containers, see block_mode.rs,
+    which record their spec.
+    Their _with_progress versions call an LzwProgressCallback as each block is done, from the
+    worker threads but one call at a time, and stop with LZW_STATUS_CANCELLED once it returns
+    false, see progress.rs.
+    Streams are compressed a piece at a time with lzw_encoder_init, lzw_encoder_update and
+    lzw_encoder_finish, and decompressed likewise with the lzw_decoder_ functions, see stream.rs.
+    finish always frees the stream, and lzw_encoder_free or lzw_decoder_free drop one part way.
+    After a failed update a stream can only be freed.
+    lzw_encoder_set_progress and lzw_decoder_set_progress have each later update call back, on the
+    calling thread, with the bytes taken and given out so far and a total_in of 0. Once it returns
+    false, the next update fails with LZW_STATUS_CANCELLED.
+
+    Changes that would break existing callers bump LZW_ABI_VERSION.
+*/
+
+pub const LZW_ABI_VERSION: u32 = 1;
+
+pub const LZW_ALPHABET_ASCII: u32 = 0;
+pub const LZW_ALPHABET_BYTES: u32 = 1;
+pub const LZW_ALPHABET_WORDS: u32 = 2;
+pub const LZW_ALPHABET_U16_LE: u32 = 3;
+pub const LZW_ALPHABET_U16_BE: u32 = 4;
+pub const LZW_ALPHABET_U32_LE: u32 = 5;
+pub const LZW_ALPHABET_U32_BE: u32 = 6;
+
+pub const LZW_GROWTH_LZW: u32 = 0;
+pub const LZW_GROWTH_LZMW: u32 = 1;
+pub const LZW_GROWTH_LZAP: u32 = 2;
+
+pub const LZW_CODING_PACKED: u32 = 0;
+pub const LZW_CODING_RANGE: u32 = 1;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LzwStatus {
+    Ok = 0,
+    NullPointer = 1,
+    Spec = 2,
+    Dictionary = 3,
+    Encode = 4,
+    Decode = 5,
+    Format = 6,
+    Worker = 7,
+    Io = 8,
+    StreamFailed = 9,
+    Panic = 10,
+    Cancelled = 11,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct LzwCSpec {
+    pub alphabet: u32,
+    pub variable_width: bool,
+    pub width: u8,
+    pub min_width: u8,
+    pub max_width: u8,
+    pub end_code: bool,
+    pub clear_code: bool,
+    pub pack_msb_first: bool,
+    pub early_change: bool,
+    pub evict_lru: bool,
+    pub growth: u32,
+    pub coding: u32,
+    // 0 for no budget
+    pub memory_budget: u64,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct LzwBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct LzwProgress {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub total_in: u64,
+    // Uncompressed over compressed size so far
+    pub ratio: f64,
+}
+
+/// Told how far a container or stream has got, and given back the user_data passed with it.
+/// Return true to carry on, or false to cancel.
+pub type LzwProgressCallback =
+    Option<unsafe extern "C" fn(progress: *const LzwProgress, user_data: *mut c_void) -> bool>;
+
+// What C holds, with the stream emptied by a failed update
+pub struct LzwEncodeStream {
+    encoder: Option<StreamEncoder>,
+    observer: CallbackObserver,
+}
+
+pub struct LzwDecodeStream {
+    decoder: Option<StreamDecoder>,
+    observer: CallbackObserver,
+}
+
+struct Failure {
+    status: LzwStatus,
+    message: String,
+}
+
+impl From<LzwError> for Failure {
+    fn from(error: LzwError) -> Failure {
+        let status = match error {
+            LzwError::Spec(_) => LzwStatus::Spec,
+            LzwError::Dictionary(_) => LzwStatus::Dictionary,
+            LzwError::Encode(_) => LzwStatus::Encode,
+            LzwError::Decode(_) => LzwStatus::Decode,
+            LzwError::Format(_) => LzwStatus::Format,
+            LzwError::Worker(_) => LzwStatus::Worker,
+            LzwError::Io(_) => LzwStatus::Io,
+            LzwError::Cancelled(_) => LzwStatus::Cancelled,
+        };
+        Failure {
+            status,
+            message: error.to_string(),
+        }
+    }
+}
+
+fn null(argument: &str) -> Failure {
+    Failure {
+        status: LzwStatus::NullPointer,
+        message: format!("{} is null", argument),
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+// Run an entry point, turning failures and panics into a status with the message kept for lzw_last_error
+fn call(job: impl FnOnce() -> Result<(), Failure>) -> LzwStatus {
+    let failure = match panic::catch_unwind(AssertUnwindSafe(job)) {
+        Ok(Ok(())) => return LzwStatus::Ok,
+        Ok(Err(failure)) => failure,
+        Err(_) => Failure {
+            status: LzwStatus::Panic,
+            message: "Panicked".to_string(),
+        },
+    };
+    let message = CString::new(failure.message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    failure.status
+}
+
+impl LzwCSpec {
+    fn to_spec(self) -> Result<LzwSpec, Failure> {
+        let alphabet = match self.alphabet {
+            LZW_ALPHABET_ASCII => Alphabet::Ascii,
+            LZW_ALPHABET_BYTES => Alphabet::Bytes,
+            LZW_ALPHABET_WORDS => Alphabet::Words,
+            LZW_ALPHABET_U16_LE => Alphabet::U16Le,
+            LZW_ALPHABET_U16_BE => Alphabet::U16Be,
+            LZW_ALPHABET_U32_LE => Alphabet::U32Le,
+            LZW_ALPHABET_U32_BE => Alphabet::U32Be,
+            other => return Err(unknown("alphabet", other)),
+        };
+        let growth = match self.growth {
+            LZW_GROWTH_LZW => Growth::Lzw,
+            LZW_GROWTH_LZMW => Growth::Lzmw,
+            LZW_GROWTH_LZAP => Growth::Lzap,
+            other => return Err(unknown("growth", other)),
+        };
+        let coding = match self.coding {
+            LZW_CODING_PACKED => Coding::Packed,
+            LZW_CODING_RANGE => Coding::Range,
+            other => return Err(unknown("coding", other)),
+        };
+        let memory_budget = match self.memory_budget {
+            0 => None,
+            budget => Some(usize::try_from(budget).map_err(|_| {
+                LzwError::Spec(format!("Memory budget {} is out of range", budget))
+            })?),
+        };
+        Ok(LzwSpec {
+            alphabet,
+            variable_width: self.variable_width,
+            width: self.width,
+            min_width: self.min_width,
+            max_width: self.max_width,
+            end_code: self.end_code,
+            clear_code: self.clear_code,
+            pack_msb_first: self.pack_msb_first,
+            early_change: self.early_change,
+            evict_lru: self.evict_lru,
+            growth,
+            coding,
+            memory_budget,
+        })
+    }
+}
+
+fn unknown(setting: &str, value: u32) -> Failure {
+    LzwError::Spec(format!("Unknown {} {}", setting, value)).into()
+}
+
+unsafe fn read_spec(spec: *const LzwCSpec) -> Result<LzwSpec, Failure> {
+    spec.as_ref().ok_or_else(|| null("spec"))?.to_spec()
+}
+
+unsafe fn input_slice<'a>(input: *const u8, len: usize) -> Result<&'a [u8], Failure> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if input.is_null() {
+        return Err(null("input"));
+    }
+    Ok(slice::from_raw_parts(input, len))
+}
+
+// Run job on the buffer's bytes as a Vec, putting it back whether job succeeds, fails or panics
+unsafe fn with_buffer(
+    buffer: *mut LzwBuffer,
+    job: impl FnOnce(&mut Vec<u8>) -> Result<(), Failure>,
+) -> Result<(), Failure> {
+    let buffer = buffer.as_mut().ok_or_else(|| null("output"))?;
+    let bytes = if buffer.data.is_null() {
+        Vec::new()
+    } else {
+        Vec::from_raw_parts(buffer.data, buffer.len, buffer.capacity)
+    };
+    let mut guard = BufferGuard { buffer, bytes };
+    job(&mut guard.bytes)
+}
+
+// Owns a buffer's bytes while they are a Vec, and hands them back to it when dropped, so that
+// a panic part way through, even after the Vec has reallocated, can't leave C a dangling pointer
+struct BufferGuard<'a> {
+    buffer: &'a mut LzwBuffer,
+    bytes: Vec<u8>,
+}
+
+impl Drop for BufferGuard<'_> {
+    fn drop(&mut self) {
+        let mut bytes = std::mem::ManuallyDrop::new(std::mem::take(&mut self.bytes));
+        self.buffer.data = bytes.as_mut_ptr();
+        self.buffer.len = bytes.len();
+        self.buffer.capacity = bytes.capacity();
+    }
+}
+
+// A C callback as an observer, cancelling the run once it returns false
+struct CallbackObserver {
+    callback: LzwProgressCallback,
+    user_data: *mut c_void,
+    cancel: CancelToken,
+}
+
+// The caller is told the callback is called from the worker threads, and it is only called
+// one call at a time
+unsafe impl Sync for CallbackObserver {}
+
+impl ProgressObserver for CallbackObserver {
+    fn update(&self, progress: Progress) {
+        let Some(callback) = self.callback else {
+            return;
+        };
+        let c_progress = LzwProgress {
+            bytes_in: progress.bytes_in,
+            bytes_out: progress.bytes_out,
+            total_in: progress.total_in,
+            ratio: progress.ratio(),
+        };
+        if !unsafe { callback(&c_progress, self.user_data) } {
+            self.cancel.cancel();
+        }
+    }
+}
+
+impl CallbackObserver {
+    fn new(callback: LzwProgressCallback, user_data: *mut c_void) -> CallbackObserver {
+        CallbackObserver {
+            callback,
+            user_data,
+            cancel: CancelToken::new(),
+        }
+    }
+
+    fn monitor(&self) -> Monitor<'_> {
+        Monitor {
+            observer: Some(self),
+            cancel: Some(&self.cancel),
+        }
+    }
+}
+
+fn thread_count(threads: usize) -> usize {
+    match threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        threads => threads,
+    }
+}
+
+/// The version of this API, LZW_ABI_VERSION when the header was generated.
+#[no_mangle]
+pub extern "C" fn lzw_abi_version() -> u32 {
+    LZW_ABI_VERSION
+}
+
+/// The message for the last failure on this thread, valid until the next call on it.
+#[no_mangle]
+pub extern "C" fn lzw_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+/// Fill in the command line's default spec: bytes in fixed 12 bit codes, LZW growth, no End or
+/// Clear code.
+///
+/// # Safety
+/// spec must point to an LzwCSpec.
+#[no_mangle]
+pub unsafe extern "C" fn lzw_spec_default(spec: *mut LzwCSpec) -> LzwStatus {
+    call(|| {
+        *spec.as_mut().ok_or_else(|| null("spec"))? = LzwCSpec {
+            alphabet: LZW_ALPHABET_BYTES,
+            variable_width: false,
+            width: 12,
+            min_width: 9,
+            max_width: 16,
+            end_code: false,
+            clear_code: false,
+            pack_msb_first: false,
+            early_change: false,
+            evict_lru: false,
+            growth: LZW_GROWTH_LZW,
+            coding: LZW_CODING_PACKED,
+            memory_budget: 0,
+        };
+        Ok(())
+    })
+}
+
+/// Free the bytes of a buffer, leaving it empty.
+///
+/// # Safety
+/// buffer must be null or point to a buffer filled in by this library, or zeroed.
+#[no_mangle]
+pub unsafe extern "C" fn lzw_buffer_free(buffer: *mut LzwBuffer) {
+    if let Some(buffer) = buffer.as_mut() {
+        if !buffer.data.is_null() {
+            drop(Vec::from_raw_parts(
+                buffer.data,
+                buffer.len,
+                buffer.capacity,
+            ));
+        }
+        *buffer = LzwBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+            capacity: 0,
+        };
+    }
+}
+
+/// Compress input into a bare stream, appended to output.
+///
+/// # Safety
+/// spec and output must be valid, and input must hold input_len bytes.
+#[no_mangle]
+pub unsafe extern "C" fn lzw_compress(
+    spec: *const LzwCSpec,
+    input: *const u8,
+    input_len: usize,
+    output: *mut LzwBuffer,
+) -> LzwStatus {
+    call(|| {
+        let spec = read_spec(spec)?;
+        let input = input_slice(input, input_len)?;
+        with_buffer(output, |output| {
+            output.extend(crate::compress::<TrieDictionary<u8>>(spec, input)?);
+            Ok(())
+        })
+    })
+}
+
+/// Decompress a bare stream compressed with the same spec, appended to output.
+///
+/// # Safety
+/// spec and output must be valid, and input must hold input_len bytes.
+#[no_mangle]
+pub unsafe extern "C" fn lzw_decompress(
+    spec: *const LzwCSpec,
+    input: *const u8,
+    input_len: usize,
+    output: *mut LzwBuffer,
+) -> LzwStatus {
+    call(|| {
+        let spec = read_spec(spec)?;
+        let input = input_slice(input, input_len)?;
+        with_buffer(output, |output| {
+            output.extend(crate::decompress(spec, input)?);
+            Ok(())
+        })
+    })
+}
+
+/// Compress input into a container of independent blocks, appended to output.
+/// A block_size of 0 is the default of 4 MiB, and threads of 0 uses every core.
+///
+/// # Safety
+/// spec and output must be valid, and input must hold input_len bytes.
+#[no_mangle]
+pub unsafe extern "C" fn lzw_compress_container(
+    spec: *const LzwCSpec,
+    input: *const u8,
+    input_len: usize,
+    block_size: usize,
+    threads: usize,
+    output: *mut LzwBuffer,
+) -> LzwStatus {
+    lzw_compress_container_with_progress(
+        spec,
+        input,
+        input_len,
+        block_size,
+        threads,
+        None,
+        ptr::null_mut(),
+        output,
+    )
+}
+
+/// As lzw_compress_container, calling callback with user_data as each block is done.
+/// callback may be NULL, and is called from the worker threads, one call at a time.
+///
+/// # Safety
+/// spec and output must be valid, input must hold input_len bytes, and callback must be safe to
+/// call from other threads with user_data.
+#[no_mangle]
+pub unsafe extern "C" fn lzw_compress_container_with_progress(
+    spec: *const LzwCSpec,
+    input: *const u8,
+    input_len: usize,
+    block_size: usize,
+    threads: usize,
+    callback: LzwProgressCallback,
+    user_data: *mut c_void,
+    output: *mut LzwBuffer,
+) -> LzwStatus {
+    call(|| {
+        let spec = read_spec(spec)?;
+        let input = input_slice(input, input_len)?;
+        let block_size = match block_size {
+            0 => block_mode::DEFAULT_BLOCK_SIZE,
+            block_size => block_size,
+        };
+        let observer = CallbackObserver::new(callback, user_data);
+        with_buffer(output, |output| {
+            let (compressed, _) = block_mode::compress_blocks_with_progress::<TrieDictionary<u8>>(
+                spec,
+                NO_FILTERS,
+                None,
+                input,
+                block_size,
+                thread_count(threads),
+                observer.monitor(),
+            )?;
+            output.extend(compressed);
+            Ok(())
+        })
+    })
+}
+
+/// Decompress a container, appended to output. threads of 0 uses every core.
+///
+/// # Safety
+/// output must be valid, and input must hold input_len bytes.
+#[no_mangle]
+pub unsafe extern "C" fn lzw_decompress_container(
+    input: *const u8,
+    input_len: usize,
+    threads: usize,
+    output: *mut LzwBuffer,
+) -> LzwStatus {
+    lzw_decompress_container_with_progress(input, input_len, threads, None, ptr::null_mut(), output)
+}
+
+/// As lzw_decompress_container, calling callback with user_data as each block is done.
+/// callback may be NULL, and is called from the worker threads, one call at a time.
+///
+/// # Safety
+/// output must be valid, input must hold input_len bytes, and callback must be safe to call
+/// from other threads with user_data.
+#[no_mangle]
+pub unsafe extern "C" fn lzw_decompress_container_with_progress(
+    input: *const u8,
+    input_len: usize,
+    threads: usize,
+    callback: LzwProgressCallback,
+    user_data: *mut c_void,
+    output: *mut LzwBuffer,
+) -> LzwStatus {
+    call(|| {
+        let input = input_slice(input, input_len)?;
+        let observer = CallbackObserver::new(callback, user_data);
+        with_buffer(output, |output| {
+            let (decompressed, _) = block_mode::decompress_blocks_with_progress(
+                input,
+                None,
+                thread_count(threads),
+                observer.monitor(),
+            )?;
+            output.extend(decompressed);
+            Ok(())
+        })
+    })
+}
+
+/// Start compressing a bare stream a piece at a time. Only byte alphabets and packed codes
+/// can be streamed.
+///
+/// # Safety
+/// spec must be valid, and stream must point to where to put the new stream.
+#[no_mangle]
+pub unsafe extern "C" fn lzw_encoder_init(
+    spec: *const LzwCSpec,
+    stream: *mut *mut LzwEncodeStream,
+) -> LzwStatus {
+    call(|| {
+        let stream = stream.as_mut().ok_or_else(|| null("stream"))?;
+        let encoder = StreamEncoder::new(read_spec(spec)?)?;
+        *stream = Box::into_raw(Box::new(LzwEncodeStream {
+            encoder: Some(encoder),
+            observer: CallbackObserver::new(None, ptr::null_mut()),
+        }));
+        Ok(())
+    })
+}
+
+/// Take more input, appending whatever compressed data is ready to output.
+///
+/// # Safety
+/// stream must be from lzw_encoder_init, output must be valid, and input must hold input_len bytes.
+#[no_mangle]
+pub unsafe extern "C" fn lzw_encoder_update(
+    stream: *mut LzwEncodeStream,
+    input: *const u8,
+    input_len: usize,
+    output: *mut LzwBuffer,
+) -> LzwStatus {
+    call(|| {
+        let stream = stream.as_mut().ok_or_else(|| null("stream"))?;
+        let input = input_slice(input, input_len)?;
+        let encoder = stream.encoder.as_mut().ok_or_else(stream_failed)?;
+        let monitor = stream.observer.monitor();
+        let result = with_buffer(output, |output| {
+            Ok(encoder.update_with_progress(input, output, monitor)?)
+        });
+        if result.is_err() {
+            stream.encoder = None;
+        }
+        result
+    })
+}
+
+/// Call callback with user_data after each later update, in place of any callback set before.
+/// callback may be NULL to stop the calls. Returning false from it cancels the stream.
+///
+/// # Safety
+/// stream must be from lzw_encoder_init.
+#[no_mangle]
+pub unsafe extern "C" fn lzw_encoder_set_progress(
+    stream: *mut LzwEncodeStream,
+    callback: LzwProgressCallback,
+    user_data: *mut c_void,
+) -> LzwStatus {
+    call(|| {
+        let stream = stream.as_mut().ok_or_else(|| null("stream"))?;
+        stream.observer = CallbackObserver::new(callback, user_data);
+        Ok(())
+    })
+}
+
+/// Compress the rest of the input, appended to output, and free the stream.
+///
+/// # Safety
+/// stream must be from lzw_encoder_init and not used again, and output must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn lzw_encoder_finish(
+    stream: *mut LzwEncodeStream,
+    output: *mut LzwBuffer,
+) -> LzwStatus {
+    call(|| {
+        if stream.is_null() {
+            return Err(null("stream"));
+        }
+        let stream = Box::from_raw(stream);
+        let encoder = stream.encoder.ok_or_else(stream_failed)?;
+        with_buffer(output, |output| Ok(encoder.finish(output)?))
+    })
+}
+
+/// Free a stream without finishing it.
+///
+/// # Safety
+/// stream must be null or from lzw_encoder_init, and not used again.
+#[no_mangle]
+pub unsafe extern "C" fn lzw_encoder_free(stream: *mut LzwEncodeStream) {
+    if !stream.is_null() {
+        drop(Box::from_raw(stream));
+    }
+}
+
+/// Start decompressing a bare stream a piece at a time.
+///
+/// # Safety
+/// spec must be valid, and stream must point to where to put the new stream.
+#[no_mangle]
+pub unsafe extern "C" fn lzw_decoder_init(
+    spec: *const LzwCSpec,
+    stream: *mut *mut LzwDecodeStream,
+) -> LzwStatus {
+    call(|| {
+        let stream = stream.as_mut().ok_or_else(|| null("stream"))?;
+        let decoder = StreamDecoder::new(read_spec(spec)?)?;
+        *stream = Box::into_raw(Box::new(LzwDecodeStream {
+            decoder: Some(decoder),
+            observer: CallbackObserver::new(None, ptr::null_mut()),
+        }));
+        Ok(())
+    })
+}
+
+/// Take more compressed data, appending what it decodes to output.
+///
+/// # Safety
+/// stream must be from lzw_decoder_init, output must be valid, and input must hold input_len bytes.
+#[no_mangle]
+pub unsafe extern "C" fn lzw_decoder_update(
+    stream: *mut LzwDecodeStream,
+    input: *const u8,
+    input_len: usize,
+    output: *mut LzwBuffer,
+) -> LzwStatus {
+    call(|| {
+        let stream = stream.as_mut().ok_or_else(|| null("stream"))?;
+        let input = input_slice(input, input_len)?;
+        let decoder = stream.decoder.as_mut().ok_or_else(stream_failed)?;
+        let monitor = stream.observer.monitor();
+        let result = with_buffer(output, |output| {
+            Ok(decoder.update_with_progress(input, output, monitor)?)
+        });
+        if result.is_err() {
+            stream.decoder = None;
+        }
+        result
+    })
+}
+
+/// Call callback with user_data after each later update, in place of any callback set before.
+/// callback may be NULL to stop the calls. Returning false from it cancels the stream.
+///
+/// # Safety
+/// stream must be from lzw_decoder_init.
+#[no_mangle]
+pub unsafe extern "C" fn lzw_decoder_set_progress(
+    stream: *mut LzwDecodeStream,
+    callback: LzwProgressCallback,
+    user_data: *mut c_void,
+) -> LzwStatus {
+    call(|| {
+        let stream = stream.as_mut().ok_or_else(|| null("stream"))?;
+        stream.observer = CallbackObserver::new(callback, user_data);
+        Ok(())
+    })
+}
+
+/// Check the stream wasn't cut short, and free it. Fails with LZW_STATUS_DECODE if the spec has
+/// an End code and it wasn't reached.
+///
+/// # Safety
+/// stream must be from lzw_decoder_init and not used again.
+#[no_mangle]
+pub unsafe extern "C" fn lzw_decoder_finish(stream: *mut LzwDecodeStream) -> LzwStatus {
+    call(|| {
+        if stream.is_null() {
+            return Err(null("stream"));
+        }
+        let stream = Box::from_raw(stream);
+        Ok(stream.decoder.ok_or_else(stream_failed)?.finish()?)
+    })
+}
+
+/// Free a stream without finishing it.
+///
+/// # Safety
+/// stream must be null or from lzw_decoder_init, and not used again.
+#[no_mangle]
+pub unsafe extern "C" fn lzw_decoder_free(stream: *mut LzwDecodeStream) {
+    if !stream.is_null() {
+        drop(Box::from_raw(stream));
+    }
+}
+
+fn stream_failed() -> Failure {
+    Failure {
+        status: LzwStatus::StreamFailed,
+        message: "The stream failed earlier and can only be freed".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::ffi::CStr;
+
+    fn c_spec() -> LzwCSpec {
+        let mut spec = LzwCSpec {
+            alphabet: 99,
+            variable_width: false,
+            width: 0,
+            min_width: 0,
+            max_width: 0,
+            end_code: false,
+            clear_code: false,
+            pack_msb_first: false,
+            early_change: false,
+            evict_lru: false,
+            growth: 0,
+            coding: 0,
+            memory_budget: 0,
+        };
+        assert_eq!(unsafe { lzw_spec_default(&mut spec) }, LzwStatus::Ok);
+        LzwCSpec {
+            variable_width: true,
+            end_code: true,
+            clear_code: true,
+            ..spec
+        }
+    }
+
+    fn empty() -> LzwBuffer {
+        LzwBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+            capacity: 0,
+        }
+    }
+
+    fn bytes(buffer: &LzwBuffer) -> &[u8] {
+        unsafe { input_slice(buffer.data, buffer.len).ok().unwrap() }
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(lzw_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn sample_input() -> Vec<u8> {
+        let mut state: u32 = 3;
+        (0..200_000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                b"pixel row "[(state >> 16) as usize % 10]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trips() {
+        let spec = c_spec();
+        let input = sample_input();
+        unsafe {
+            let mut compressed = empty();
+            let status = lzw_compress(&spec, input.as_ptr(), input.len(), &mut compressed);
+            assert_eq!(status, LzwStatus::Ok);
+            assert_eq!(
+                bytes(&compressed),
+                crate::compress::<TrieDictionary<u8>>(spec.to_spec().ok().unwrap(), &input)
+                    .unwrap()
+            );
+            let mut output = empty();
+            let status = lzw_decompress(&spec, compressed.data, compressed.len, &mut output);
+            assert_eq!(status, LzwStatus::Ok);
+            assert_eq!(bytes(&output), input);
+
+            // Appended to what is there
+            let mut container = empty();
+            let status = lzw_compress_container(
+                &spec,
+                input.as_ptr(),
+                input.len(),
+                50_000,
+                2,
+                &mut container,
+            );
+            assert_eq!(status, LzwStatus::Ok);
+            let status = lzw_decompress_container(container.data, container.len, 0, &mut output);
+            assert_eq!(status, LzwStatus::Ok);
+            assert_eq!(bytes(&output)[..input.len()], input);
+            assert_eq!(bytes(&output)[input.len()..], input);
+
+            // Streamed a piece at a time, with the output emptied as it goes
+            output.len = 0;
+            let mut encoder = ptr::null_mut();
+            assert_eq!(lzw_encoder_init(&spec, &mut encoder), LzwStatus::Ok);
+            let mut streamed = Vec::new();
+            for piece in input.chunks(7000) {
+                let status = lzw_encoder_update(encoder, piece.as_ptr(), piece.len(), &mut output);
+                assert_eq!(status, LzwStatus::Ok);
+                streamed.extend_from_slice(bytes(&output));
+                output.len = 0;
+            }
+            assert_eq!(lzw_encoder_finish(encoder, &mut output), LzwStatus::Ok);
+            streamed.extend_from_slice(bytes(&output));
+            assert_eq!(streamed, bytes(&compressed));
+
+            output.len = 0;
+            let mut decoder = ptr::null_mut();
+            assert_eq!(lzw_decoder_init(&spec, &mut decoder), LzwStatus::Ok);
+            for piece in streamed.chunks(999) {
+                let status = lzw_decoder_update(decoder, piece.as_ptr(), piece.len(), &mut output);
+                assert_eq!(status, LzwStatus::Ok);
+            }
+            assert_eq!(lzw_decoder_finish(decoder), LzwStatus::Ok);
+            assert_eq!(bytes(&output), input);
+
+            lzw_buffer_free(&mut compressed);
+            lzw_buffer_free(&mut container);
+            lzw_buffer_free(&mut output);
+            assert!(output.data.is_null());
+        }
+    }
+
+    #[test]
+    fn buffer_kept_through_a_panic() {
+        let mut buffer = empty();
+        unsafe {
+            with_buffer(&mut buffer, |bytes| {
+                bytes.extend_from_slice(b"kept");
+                Ok(())
+            })
+            .ok()
+            .unwrap();
+            // Grown well past its capacity first, so the Vec has moved
+            let status = call(|| {
+                with_buffer(&mut buffer, |bytes| {
+                    bytes.extend(std::iter::repeat_n(b'x', 100_000));
+                    panic!("part way through");
+                })
+            });
+            assert_eq!(status, LzwStatus::Panic);
+            assert_eq!(buffer.len, 100_004);
+            assert!(buffer.capacity >= buffer.len);
+            assert_eq!(&bytes(&buffer)[..4], b"kept");
+            lzw_buffer_free(&mut buffer);
+        }
+        assert!(buffer.data.is_null());
+    }
+
+    // Counts its calls in user_data, and cancels at the third
+    unsafe extern "C" fn count_calls(progress: *const LzwProgress, user_data: *mut c_void) -> bool {
+        let calls = &mut *(user_data as *mut Vec<LzwProgress>);
+        calls.push(*progress);
+        calls.len() < 3
+    }
+
+    #[test]
+    fn progress_and_cancel() {
+        let spec = c_spec();
+        let input = sample_input();
+        unsafe {
+            let mut container = empty();
+            let mut calls: Vec<LzwProgress> = Vec::new();
+            let status = lzw_compress_container_with_progress(
+                &spec,
+                input.as_ptr(),
+                input.len(),
+                100_000,
+                1,
+                Some(count_calls),
+                &mut calls as *mut _ as *mut c_void,
+                &mut container,
+            );
+            assert_eq!(status, LzwStatus::Ok);
+            assert_eq!(calls.len(), 3);
+            assert_eq!(calls[2].bytes_in, input.len() as u64);
+            assert!(calls[2].ratio > 1.0);
+
+            let mut output = empty();
+            calls.clear();
+            let status = lzw_decompress_container_with_progress(
+                container.data,
+                container.len,
+                1,
+                None,
+                ptr::null_mut(),
+                &mut output,
+            );
+            assert_eq!(status, LzwStatus::Ok);
+            assert_eq!(bytes(&output), input);
+
+            // The third call is after the second of four blocks, and the rest aren't started
+            output.len = 0;
+            let status = lzw_compress_container_with_progress(
+                &spec,
+                input.as_ptr(),
+                input.len(),
+                50_000,
+                1,
+                Some(count_calls),
+                &mut calls as *mut _ as *mut c_void,
+                &mut output,
+            );
+            assert_eq!(status, LzwStatus::Cancelled);
+            assert_eq!(calls.len(), 3);
+            assert!(last_error().contains("Stopped after 100000 of 200000 bytes"));
+            assert_eq!(output.len, 0);
+
+            // A stream calls back after each update, and fails the one after it is cancelled
+            output.len = 0;
+            calls.clear();
+            let mut encoder = ptr::null_mut();
+            assert_eq!(lzw_encoder_init(&spec, &mut encoder), LzwStatus::Ok);
+            let status =
+                lzw_encoder_set_progress(encoder, Some(count_calls), &mut calls as *mut _ as _);
+            assert_eq!(status, LzwStatus::Ok);
+            for piece in input.chunks(50_000).take(3) {
+                let status = lzw_encoder_update(encoder, piece.as_ptr(), piece.len(), &mut output);
+                assert_eq!(status, LzwStatus::Ok);
+            }
+            assert_eq!(calls.len(), 3);
+            assert_eq!(calls[2].bytes_in, 150_000);
+            assert_eq!(calls[2].bytes_out, output.len as u64);
+            assert_eq!(calls[2].total_in, 0);
+            let status = lzw_encoder_update(encoder, input.as_ptr(), 10, &mut output);
+            assert_eq!(status, LzwStatus::Cancelled);
+            assert_eq!(calls.len(), 3);
+            assert_eq!(
+                lzw_encoder_finish(encoder, &mut output),
+                LzwStatus::StreamFailed
+            );
+
+            let mut decoder = ptr::null_mut();
+            assert_eq!(lzw_decoder_init(&spec, &mut decoder), LzwStatus::Ok);
+            let status = lzw_decoder_set_progress(decoder, None, ptr::null_mut());
+            assert_eq!(status, LzwStatus::Ok);
+            let status = lzw_decoder_update(decoder, container.data, 0, &mut output);
+            assert_eq!(status, LzwStatus::Ok);
+            lzw_decoder_free(decoder);
+
+            lzw_buffer_free(&mut container);
+            lzw_buffer_free(&mut output);
+        }
+    }
+
+    #[test]
+    fn failures() {
+        let spec = c_spec();
+        let input = sample_input();
+        unsafe {
+            let mut output = empty();
+            let bad_width = LzwCSpec {
+                max_width: 40,
+                ..spec
+            };
+            let status = lzw_compress(&bad_width, input.as_ptr(), input.len(), &mut output);
+            assert_eq!(status, LzwStatus::Spec);
+            assert!(last_error().contains("wider than the maximum"));
+            let bad_growth = LzwCSpec { growth: 7, ..spec };
+            let status = lzw_compress(&bad_growth, input.as_ptr(), input.len(), &mut output);
+            assert_eq!(status, LzwStatus::Spec);
+            assert!(last_error().contains("Unknown growth 7"));
+
+            let status = lzw_compress(ptr::null(), input.as_ptr(), input.len(), &mut output);
+            assert_eq!(status, LzwStatus::NullPointer);
+            let status = lzw_compress(&spec, ptr::null(), 10, &mut output);
+            assert_eq!(status, LzwStatus::NullPointer);
+            let status = lzw_compress(&spec, ptr::null(), 0, ptr::null_mut());
+            assert_eq!(status, LzwStatus::NullPointer);
+            assert_eq!(last_error(), "output is null");
+
+            let status = lzw_decompress_container(input.as_ptr(), 100, 1, &mut output);
+            assert_eq!(status, LzwStatus::Format);
+
+            // A stream that has failed can only be freed
+            let mut decoder = ptr::null_mut();
+            assert_eq!(lzw_decoder_init(&spec, &mut decoder), LzwStatus::Ok);
+            let garbage = [0xffu8; 100];
+            let status = lzw_decoder_update(decoder, garbage.as_ptr(), 100, &mut output);
+            assert_eq!(status, LzwStatus::Decode);
+            let status = lzw_decoder_update(decoder, garbage.as_ptr(), 100, &mut output);
+            assert_eq!(status, LzwStatus::StreamFailed);
+            assert_eq!(lzw_decoder_finish(decoder), LzwStatus::StreamFailed);
+
+            // Cut short
+            let mut decoder = ptr::null_mut();
+            assert_eq!(lzw_decoder_init(&spec, &mut decoder), LzwStatus::Ok);
+            assert_eq!(lzw_decoder_finish(decoder), LzwStatus::Decode);
+
+            let range = LzwCSpec {
+                coding: LZW_CODING_RANGE,
+                ..spec
+            };
+            let mut encoder = ptr::null_mut();
+            assert_eq!(lzw_encoder_init(&range, &mut encoder), LzwStatus::Spec);
+            assert!(encoder.is_null());
+            lzw_encoder_free(encoder);
+            lzw_buffer_free(&mut output);
+        }
+    }
+}
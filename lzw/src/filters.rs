@@ -0,0 +1,264 @@
+use crate::alphabets::Alphabet;
+use crate::lzw_error::LzwError;
+
+/* FILTERS
+    Reversible transforms run over each block before it is compressed, and undone after it is decompressed.
+        Delta           each byte minus the byte distance bytes before it (the TIFF predictor).
+                        Smoothly varying samples and image rows become runs of small differences.
+                        Use the sample or pixel size as the distance.
+        Bwt             Burrows-Wheeler transform, the last column of the sorted rotations of the block.
+                        Bytes that come before similar contexts end up next to each other.
+                        Undoing it needs the row the block itself ended up in, the primary index.
+        MoveToFront     each byte becomes its position in a list of recently seen bytes, which it is then
+                        moved to the front of. Runs of a few distinct bytes, like Bwt output, become mostly 0s.
+    They are always run in that order, delta first, and undone in the reverse order.
+    The filters are recorded in the container header, and the primary index goes in front of each block's codes.
+*/
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Filters {
+    // 0 for no delta filter
+    pub delta: u8,
+    pub bwt: bool,
+    pub move_to_front: bool,
+}
+
+pub const NO_FILTERS: Filters = Filters {
+    delta: 0,
+    bwt: false,
+    move_to_front: false,
+};
+
+impl Filters {
+    pub fn is_empty(&self) -> bool {
+        *self == NO_FILTERS
+    }
+
+    // Filtered bytes can be anything, so the alphabet must take any byte
+    pub fn check_alphabet(&self, alphabet: Alphabet) -> Result<(), LzwError> {
+        if !self.is_empty() && matches!(alphabet, Alphabet::_Test | Alphabet::Ascii) {
+            return Err(LzwError::Spec(format!(
+                "Filters can't be used with the {:?} alphabet",
+                alphabet
+            )));
+        }
+        Ok(())
+    }
+
+    // The filtered block, and the primary index if Bwt is on
+    pub fn apply(&self, block: &[u8]) -> (Vec<u8>, Option<u32>) {
+        let mut data = block.to_vec();
+        if self.delta > 0 {
+            delta_encode(&mut data, self.delta as usize);
+        }
+        let mut primary = None;
+        if self.bwt {
+            let (transformed, index) = bwt_encode(&data);
+            data = transformed;
+            primary = Some(index);
+        }
+        if self.move_to_front {
+            move_to_front_encode(&mut data);
+        }
+        (data, primary)
+    }
+
+    pub fn undo(&self, mut data: Vec<u8>, primary: Option<u32>) -> Result<Vec<u8>, LzwError> {
+        if self.move_to_front {
+            move_to_front_decode(&mut data);
+        }
+        if self.bwt {
+            let primary = primary.ok_or(LzwError::Format(
+                "Block has no Burrows-Wheeler primary index".to_string(),
+            ))?;
+            data = bwt_decode(&data, primary)?;
+        }
+        if self.delta > 0 {
+            delta_decode(&mut data, self.delta as usize);
+        }
+        Ok(data)
+    }
+}
+
+pub fn delta_encode(data: &mut [u8], distance: usize) {
+    for i in (distance..data.len()).rev() {
+        data[i] = data[i].wrapping_sub(data[i - distance]);
+    }
+}
+
+pub fn delta_decode(data: &mut [u8], distance: usize) {
+    for i in distance..data.len() {
+        data[i] = data[i].wrapping_add(data[i - distance]);
+    }
+}
+
+pub fn move_to_front_encode(data: &mut [u8]) {
+    let mut recent: Vec<u8> = (0..=u8::MAX).collect();
+    for byte in data.iter_mut() {
+        let position = recent.iter().position(|b| b == byte).unwrap();
+        recent[..=position].rotate_right(1);
+        *byte = position as u8;
+    }
+}
+
+pub fn move_to_front_decode(data: &mut [u8]) {
+    let mut recent: Vec<u8> = (0..=u8::MAX).collect();
+    for byte in data.iter_mut() {
+        let position = *byte as usize;
+        *byte = recent[position];
+        recent[..=position].rotate_right(1);
+    }
+}
+
+/* SORTING ROTATIONS
+    Rotations are sorted by prefix doubling: once they are ranked by their first k bytes,
+    ranking by the first 2k is a sort on the pair (rank at i, rank at i + k).
+    Both sorts are counting sorts, so a block takes O(n log n) however repetitive it is.
+    Equal rotations (a block made of a repeated pattern) keep the same rank, which does no harm.
+*/
+fn sort_rotations(data: &[u8]) -> Vec<usize> {
+    let n = data.len();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by_key(|&i| data[i]);
+    let mut rank = vec![0; n];
+    for j in 1..n {
+        let (previous, current) = (order[j - 1], order[j]);
+        rank[current] = rank[previous] + (data[current] != data[previous]) as usize;
+    }
+
+    let mut by_second = vec![0; n];
+    let mut counts = vec![0; n];
+    let mut new_rank = vec![0; n];
+    let mut k = 1;
+    while k < n && rank[order[n - 1]] < n - 1 {
+        // Ordered by rank at i + k, as order is sorted by rank at i
+        for (j, &i) in order.iter().enumerate() {
+            by_second[j] = (i + n - k) % n;
+        }
+        // Stable counting sort on the rank at i
+        counts.iter_mut().for_each(|count| *count = 0);
+        for &i in by_second.iter() {
+            counts[rank[i]] += 1;
+        }
+        for r in 1..n {
+            counts[r] += counts[r - 1];
+        }
+        for &i in by_second.iter().rev() {
+            counts[rank[i]] -= 1;
+            order[counts[rank[i]]] = i;
+        }
+
+        new_rank[order[0]] = 0;
+        for j in 1..n {
+            let (previous, current) = (order[j - 1], order[j]);
+            let differs = (rank[current], rank[(current + k) % n])
+                != (rank[previous], rank[(previous + k) % n]);
+            new_rank[current] = new_rank[previous] + differs as usize;
+        }
+        std::mem::swap(&mut rank, &mut new_rank);
+        k *= 2;
+    }
+    order
+}
+
+pub fn bwt_encode(data: &[u8]) -> (Vec<u8>, u32) {
+    let n = data.len();
+    let order = sort_rotations(data);
+    let last = order.iter().map(|&i| data[(i + n - 1) % n]).collect();
+    let primary = order.iter().position(|&i| i == 0).unwrap_or(0);
+    (last, primary as u32)
+}
+
+pub fn bwt_decode(last: &[u8], primary: u32) -> Result<Vec<u8>, LzwError> {
+    let n = last.len();
+    let primary = primary as usize;
+    if primary >= n.max(1) {
+        return Err(LzwError::Format(format!(
+            "Burrows-Wheeler primary index {} is out of range for {} bytes",
+            primary, n
+        )));
+    }
+
+    // Row of the first column that each row's last byte is found at
+    let mut starts = [0; 256];
+    for &byte in last.iter() {
+        starts[byte as usize] += 1;
+    }
+    let mut total = 0;
+    for start in starts.iter_mut() {
+        total += *start;
+        *start = total - *start;
+    }
+    let mut next = vec![0; n];
+    for (row, &byte) in last.iter().enumerate() {
+        next[row] = starts[byte as usize];
+        starts[byte as usize] += 1;
+    }
+
+    // Walk back from the block's own row, one byte at a time from the end
+    let mut output = vec![0; n];
+    let mut row = primary;
+    for position in (0..n).rev() {
+        output[position] = last[row];
+        row = next[row];
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn delta_and_move_to_front() {
+        let mut data = vec![10, 12, 14, 13, 200, 2];
+        delta_encode(&mut data, 1);
+        assert_eq!(data, vec![10, 2, 2, 255, 187, 58]);
+        delta_decode(&mut data, 1);
+        assert_eq!(data, vec![10, 12, 14, 13, 200, 2]);
+
+        let mut data = b"aaabbbaaa".to_vec();
+        move_to_front_encode(&mut data);
+        assert_eq!(data, vec![97, 0, 0, 98, 0, 0, 1, 0, 0]);
+        move_to_front_decode(&mut data);
+        assert_eq!(data, b"aaabbbaaa");
+    }
+
+    #[test]
+    fn bwt_known_answer() {
+        let (last, primary) = bwt_encode(b"banana");
+        assert_eq!(last, b"nnbaaa");
+        assert_eq!(primary, 3);
+        assert_eq!(bwt_decode(&last, primary).unwrap(), b"banana");
+        assert!(bwt_decode(&last, 6).is_err());
+    }
+
+    #[test]
+    fn filters_round_trip() {
+        let mut state: u32 = 9;
+        let mixed: Vec<u8> = (0..5000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                b"abcabd"[(state >> 16) as usize % 6]
+            })
+            .collect();
+        let inputs: [&[u8]; 6] = [b"", b"x", b"abababab", &[0; 1000], b"mississippi", &mixed];
+        for delta in [0, 1, 3] {
+            for bwt in [false, true] {
+                for move_to_front in [false, true] {
+                    let filters = Filters {
+                        delta,
+                        bwt,
+                        move_to_front,
+                    };
+                    for input in inputs {
+                        let (data, primary) = filters.apply(input);
+                        assert_eq!(data.len(), input.len());
+                        assert_eq!(filters.undo(data, primary).unwrap(), input);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,872 @@
+use clap::ValueEnum;
+
+pub mod alphabets;
+pub mod archive;
+#[cfg(feature = "tokio")]
+pub mod async_io;
+pub mod auto_spec;
+pub mod bit_packing;
+pub mod block_mode;
+pub mod checkpoint;
+pub mod checksum;
+pub mod container;
+pub mod decoder_table;
+pub mod ffi;
+pub mod filters;
+pub mod lru_tracker;
+pub mod lzw_code;
+pub mod lzw_decoder;
+pub mod lzw_dictionary;
+pub mod lzw_encoder;
+pub mod lzw_error;
+pub mod lzw_token;
+pub mod mapped_input;
+pub mod mutable_trie_dict;
+pub mod progress;
+pub mod range_coder;
+pub mod recovery;
+pub mod sample_mode;
+pub mod seekable_reader;
+pub mod shared_dictionary;
+pub mod stats;
+pub mod stream;
+pub mod trie_dictionary;
+pub mod word_mode;
+
+use lzw_decoder::LzwDecoder;
+use lzw_dictionary::LzwDictionary;
+use lzw_encoder::LzwEncoder;
+use lzw_error::LzwError;
+use lzw_token::Token;
+use shared_dictionary::SharedDictionary;
+use stats::CodeStats;
+
+// Keeps the dictionary, which has a HashMap per node, within reason
+pub const MAX_CODE_WIDTH: u8 = 24;
+
+/* MEMORY BUDGET
+    Dictionary memory grows with every entry added, so a spec can set a budget in bytes.
+    The budget caps the number of entries, and when the cap is reached the dictionary is full,
+    just as when the code space runs out: it is reset, frozen or left to evict entries as the spec says.
+    Both sides have to agree on when that happens, so the cap is worked out from the budget alone,
+    at DICTIONARY_ENTRY_BYTES per entry, and not from memory actually in use.
+    That is the most one entry can take on a 64 bit target: the trie node, its slot in a parent's HashMap
+    that may be only a quarter full, and its place in the LRU tracker. Typical use is well under half of it.
+    LZMW can add many nodes without codes for one entry, so it can't be given a budget.
+*/
+pub const DICTIONARY_ENTRY_BYTES: usize = 512;
+
+// LZMW and LZAP entries can double in length each step, so longer ones are not added
+pub const MAX_GROWN_LENGTH: usize = 1 << 16;
+
+/* DICTIONARY GROWTH
+    After emitting the code for the match m, with p the match before it
+        Lzw     adds p + the first token of m (added a step late, once that token is known)
+        Lzmw    adds p + m
+        Lzap    adds p + every non-empty prefix of m, so p + m[..1], p + m[..2], ..., p + m
+    Lzmw and Lzap grow long entries much faster on repetitive input.
+    Lzmw entries are not prefix closed, so the encoder may have to back off to a shorter match.
+*/
+#[derive(Debug, Copy, Clone, PartialEq, ValueEnum)]
+pub enum Growth {
+    Lzw,
+    Lzmw,
+    Lzap,
+}
+
+// How the codes are written out, see range_coder.rs
+#[derive(Debug, Copy, Clone, PartialEq, ValueEnum)]
+pub enum Coding {
+    // Each code in the current code width, see bit_packing.rs
+    Packed,
+    // Adaptive range coding, slower but smaller
+    Range,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LzwSpec {
+    pub alphabet: alphabets::Alphabet,
+    pub variable_width: bool,
+    pub width: u8,
+    pub min_width: u8,
+    pub max_width: u8,
+    pub end_code: bool,
+    pub clear_code: bool,
+    pub pack_msb_first: bool,
+    pub early_change: bool,
+    pub evict_lru: bool,
+    pub growth: Growth,
+    pub coding: Coding,
+    pub memory_budget: Option<usize>,
+}
+
+impl LzwSpec {
+    pub fn validate(&self) -> Result<(), LzwError> {
+        let (narrowest, widest) = if self.variable_width {
+            (self.min_width, self.max_width)
+        } else {
+            (self.width, self.width)
+        };
+        if narrowest == 0 || narrowest > widest {
+            return Err(LzwError::Spec(format!(
+                "Code widths {}..={} are not a valid range",
+                narrowest, widest
+            )));
+        }
+        if widest > MAX_CODE_WIDTH {
+            return Err(LzwError::Spec(format!(
+                "Code width {} is wider than the maximum of {}",
+                widest, MAX_CODE_WIDTH
+            )));
+        }
+        // Alphabets that depend on the input are checked once it has been read
+        self.check_alphabet_size(self.alphabet.size().unwrap_or(0))?;
+        // Eviction relies on every entry being a shorter entry plus one token
+        if self.evict_lru && self.growth != Growth::Lzw {
+            return Err(LzwError::Spec(format!(
+                "LRU replacement is not supported with {:?} growth",
+                self.growth
+            )));
+        }
+        if self.memory_budget.is_some() && self.growth == Growth::Lzmw {
+            return Err(LzwError::Spec(
+                "A memory budget can't be used with Lzmw growth".to_string(),
+            ));
+        }
+        // The padding at the end of the stream could hold a whole code
+        if narrowest < 8 && !self.end_code {
+            return Err(LzwError::Spec(format!(
+                "Code width {} is narrower than a byte, so an end code is needed",
+                narrowest
+            )));
+        }
+        Ok(())
+    }
+
+    // Most entries the dictionary can hold, initial ones included, see MEMORY BUDGET
+    pub fn max_entries(&self) -> u32 {
+        let widest = if self.variable_width {
+            self.max_width
+        } else {
+            self.width
+        };
+        let codes = 1u32 << widest;
+        match self.memory_budget {
+            Some(budget) => {
+                codes.min((budget / DICTIONARY_ENTRY_BYTES).min(u32::MAX as usize) as u32)
+            }
+            None => codes,
+        }
+    }
+
+    // The alphabet, plus the control codes, must fit in the widest codes, and in the memory budget
+    pub fn check_alphabet_size(&self, alphabet_size: usize) -> Result<(), LzwError> {
+        let widest = if self.variable_width {
+            self.max_width
+        } else {
+            self.width
+        };
+        let initial_size = alphabet_size + self.clear_code as usize + self.end_code as usize;
+        if initial_size > 1 << widest {
+            return Err(LzwError::Spec(format!(
+                "Initial dictionary of {} entries does not fit in {} bit codes",
+                initial_size, widest
+            )));
+        }
+        if let Some(budget) = self.memory_budget {
+            if initial_size > self.max_entries() as usize {
+                return Err(LzwError::Spec(format!(
+                    "Initial dictionary of {} entries needs a memory budget of at least {} bytes, not {}",
+                    initial_size,
+                    initial_size * DICTIONARY_ENTRY_BYTES,
+                    budget
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+pub enum ArgAlphabet {
+    _Test,
+    Ascii,
+    Bytes,
+    Words,
+    U16Le,
+    U16Be,
+    U32Le,
+    U32Be,
+    // TODO add more
+}
+
+// Shared dictionaries hold byte sequences, so can only be used with byte alphabets
+fn bytes_only(shared: Option<&SharedDictionary>, spec: LzwSpec) -> Result<(), LzwError> {
+    match shared {
+        Some(_) => Err(LzwError::Spec(format!(
+            "Shared dictionaries can't be used with the {:?} alphabet",
+            spec.alphabet
+        ))),
+        None => Ok(()),
+    }
+}
+
+// Compress a byte stream using the dictionary D
+// Word and sample alphabets always use TrieDictionary, see word_mode.rs and sample_mode.rs
+pub fn compress<D: LzwDictionary<u8>>(spec: LzwSpec, input: &[u8]) -> Result<Vec<u8>, LzwError> {
+    compress_with_dictionary::<D>(spec, None, input)
+}
+
+// The same shared dictionary has to be given to decompress_with_dictionary
+pub fn compress_with_dictionary<D: LzwDictionary<u8>>(
+    spec: LzwSpec,
+    shared: Option<&SharedDictionary>,
+    input: &[u8],
+) -> Result<Vec<u8>, LzwError> {
+    compress_with_stats::<D>(spec, shared, input).map(|(output, _)| output)
+}
+
+// Also returns what the encoder did, see stats.rs
+pub fn compress_with_stats<D: LzwDictionary<u8>>(
+    spec: LzwSpec,
+    shared: Option<&SharedDictionary>,
+    input: &[u8],
+) -> Result<(Vec<u8>, CodeStats), LzwError> {
+    spec.validate()?;
+    if spec.alphabet == alphabets::Alphabet::Words {
+        bytes_only(shared, spec)?;
+        return word_mode::compress_with_stats(spec, input);
+    }
+    if spec.alphabet.sample_bytes() > 1 {
+        bytes_only(shared, spec)?;
+        return sample_mode::compress_with_stats(spec, input);
+    }
+    let alphabet = alphabets::produce_byte_alphabet(spec.alphabet);
+    let shared = shared.map(|shared| shared.tokens()).unwrap_or_default();
+    let mut encoder: LzwEncoder<u8, D> = LzwEncoder::with_dictionary(spec, alphabet, shared)?;
+    let output = encoder.compress(input.iter().map(|byte| Token::new(*byte)))?;
+    Ok((output, encoder.stats()))
+}
+
+pub fn decompress(spec: LzwSpec, input: &[u8]) -> Result<Vec<u8>, LzwError> {
+    decompress_with_dictionary(spec, None, input)
+}
+
+pub fn decompress_with_dictionary(
+    spec: LzwSpec,
+    shared: Option<&SharedDictionary>,
+    input: &[u8],
+) -> Result<Vec<u8>, LzwError> {
+    decompress_with_stats(spec, shared, input).map(|(output, _)| output)
+}
+
+pub fn decompress_with_stats(
+    spec: LzwSpec,
+    shared: Option<&SharedDictionary>,
+    input: &[u8],
+) -> Result<(Vec<u8>, CodeStats), LzwError> {
+    spec.validate()?;
+    if spec.alphabet == alphabets::Alphabet::Words {
+        bytes_only(shared, spec)?;
+        return word_mode::decompress_with_stats(spec, input);
+    }
+    if spec.alphabet.sample_bytes() > 1 {
+        bytes_only(shared, spec)?;
+        return sample_mode::decompress_with_stats(spec, input);
+    }
+    let alphabet = alphabets::produce_byte_alphabet(spec.alphabet);
+    let shared = shared.map(|shared| shared.tokens()).unwrap_or_default();
+    let mut decoder: LzwDecoder<u8> = LzwDecoder::with_dictionary(spec, alphabet, &shared)?;
+    let tokens = decoder.decompress(input)?;
+    let output = tokens
+        .iter()
+        .filter_map(|token| token.get_value())
+        .collect();
+    Ok((output, decoder.stats()))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::alphabets::Alphabet;
+    use crate::mutable_trie_dict::MutableTrieDictionary;
+    use crate::trie_dictionary::TrieDictionary;
+    use proptest::prelude::*;
+
+    const BYTES_SPEC: LzwSpec = LzwSpec {
+        alphabet: Alphabet::Bytes,
+        variable_width: true,
+        width: 12,
+        min_width: 9,
+        max_width: 12,
+        end_code: true,
+        clear_code: true,
+        pack_msb_first: true,
+        early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
+    };
+
+    fn sample_input() -> Vec<u8> {
+        let mut state: u32 = 3;
+        let mut input = Vec::new();
+        for i in 0..20000u32 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            // Mostly a small set of values, with some noise, so the dictionary grows and fills
+            if state >> 28 == 0 {
+                input.push((state >> 16) as u8);
+            } else {
+                input.push(b"abcabd"[(i % 6) as usize]);
+            }
+        }
+        input
+    }
+
+    fn round_trip(spec: LzwSpec, input: &[u8]) {
+        let compressed = compress::<TrieDictionary<u8>>(spec, input).unwrap();
+        assert_eq!(
+            compressed,
+            compress::<MutableTrieDictionary<u8>>(spec, input).unwrap()
+        );
+        assert_eq!(decompress(spec, &compressed).unwrap(), input);
+    }
+
+    #[test]
+    fn round_trip_widths() {
+        let input = sample_input();
+        for variable_width in [true, false] {
+            for early_change in [true, false] {
+                for pack_msb_first in [true, false] {
+                    let spec = LzwSpec {
+                        variable_width,
+                        early_change,
+                        pack_msb_first,
+                        ..BYTES_SPEC
+                    };
+                    round_trip(spec, &input);
+                    round_trip(spec, b"");
+                    round_trip(spec, b"x");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_without_control_codes() {
+        let spec = LzwSpec {
+            end_code: false,
+            clear_code: false,
+            ..BYTES_SPEC
+        };
+        round_trip(spec, &sample_input());
+    }
+
+    #[test]
+    fn range_coding() {
+        let input = sample_input();
+        let range = LzwSpec {
+            coding: Coding::Range,
+            ..BYTES_SPEC
+        };
+        for spec in [
+            range,
+            LzwSpec {
+                end_code: false,
+                clear_code: false,
+                ..range
+            },
+            LzwSpec {
+                variable_width: false,
+                early_change: true,
+                ..range
+            },
+        ] {
+            round_trip(spec, &input);
+            round_trip(spec, b"");
+            round_trip(spec, b"x");
+        }
+
+        let packed = compress::<TrieDictionary<u8>>(BYTES_SPEC, &input).unwrap();
+        let coded = compress::<TrieDictionary<u8>>(range, &input).unwrap();
+        assert!(coded.len() < packed.len());
+        assert!(decompress(range, &coded[..coded.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn round_trip_growth() {
+        let input = sample_input();
+        for growth in [Growth::Lzmw, Growth::Lzap] {
+            for clear_code in [true, false] {
+                let spec = LzwSpec {
+                    growth,
+                    clear_code,
+                    ..BYTES_SPEC
+                };
+                for input in [&input[..], b"", b"x"] {
+                    let compressed = compress::<TrieDictionary<u8>>(spec, input).unwrap();
+                    assert_eq!(decompress(spec, &compressed).unwrap(), input);
+                }
+                assert!(compress::<MutableTrieDictionary<u8>>(spec, &input).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn faster_growth_on_repetitive_input() {
+        // Telemetry-like records that mostly repeat, with a slowly changing counter
+        let mut input = Vec::new();
+        for i in 0..3000u32 {
+            input.extend_from_slice(b"sensor=temp unit=C status=ok value=");
+            input.extend_from_slice((20 + i / 500).to_string().as_bytes());
+            input.push(b'\n');
+        }
+        let lzw_len = compress::<TrieDictionary<u8>>(BYTES_SPEC, &input)
+            .unwrap()
+            .len();
+        for growth in [Growth::Lzmw, Growth::Lzap] {
+            let spec = LzwSpec {
+                growth,
+                ..BYTES_SPEC
+            };
+            let compressed = compress::<TrieDictionary<u8>>(spec, &input).unwrap();
+            assert!(compressed.len() < lzw_len, "{:?} was no smaller", growth);
+            assert_eq!(decompress(spec, &compressed).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn lru_adapts_to_new_data() {
+        // The second half shares nothing with the first, so a frozen dictionary is no help with it
+        let mut input = sample_input();
+        let mut state: u32 = 7;
+        for _ in 0..20000 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            input.push(b"xyzw"[(state >> 16) as usize % 4]);
+        }
+        let frozen = LzwSpec {
+            clear_code: false,
+            ..BYTES_SPEC
+        };
+        let lru = LzwSpec {
+            evict_lru: true,
+            ..frozen
+        };
+        let frozen_compressed = compress::<TrieDictionary<u8>>(frozen, &input).unwrap();
+        let lru_compressed = compress::<TrieDictionary<u8>>(lru, &input).unwrap();
+        assert_eq!(decompress(lru, &lru_compressed).unwrap(), input);
+        assert!(lru_compressed.len() < frozen_compressed.len());
+    }
+
+    #[test]
+    fn memory_budget() {
+        let input = sample_input();
+        // Reached well before the 12 bit codes run out, and not a power of two, so the widths are checked too
+        let budget = 700 * DICTIONARY_ENTRY_BYTES;
+        for (clear_code, evict_lru) in [(true, false), (false, false), (false, true)] {
+            for early_change in [true, false] {
+                let spec = LzwSpec {
+                    clear_code,
+                    evict_lru,
+                    early_change,
+                    memory_budget: Some(budget),
+                    ..BYTES_SPEC
+                };
+                assert_eq!(spec.max_entries(), 700);
+                let compressed = compress::<TrieDictionary<u8>>(spec, &input).unwrap();
+                assert_eq!(decompress(spec, &compressed).unwrap(), input);
+                assert_ne!(
+                    compressed,
+                    compress::<TrieDictionary<u8>>(BYTES_SPEC, &input).unwrap()
+                );
+            }
+        }
+        round_trip(
+            LzwSpec {
+                memory_budget: Some(budget),
+                ..BYTES_SPEC
+            },
+            &input,
+        );
+
+        // A single repeated byte makes one long chain of nodes with one child each, the worst case
+        let chain = vec![b'a'; 300_000];
+        for (input, growth) in [
+            (&input, Growth::Lzw),
+            (&chain, Growth::Lzw),
+            (&input, Growth::Lzap),
+        ] {
+            let spec = LzwSpec {
+                clear_code: false,
+                growth,
+                memory_budget: Some(budget),
+                ..BYTES_SPEC
+            };
+            let mut encoder: LzwEncoder<u8, TrieDictionary<u8>> =
+                LzwEncoder::new(spec, alphabets::produce_byte_alphabet(spec.alphabet));
+            encoder
+                .encode(input.iter().map(|byte| Token::new(*byte)))
+                .unwrap();
+            let used = encoder.memory_used().unwrap();
+            assert!(used <= budget, "{} bytes used of {}", used, budget);
+            assert_eq!(encoder.peak_memory(), Some(used));
+        }
+    }
+
+    #[test]
+    fn encoder_and_decoder_stats_agree() {
+        let input = sample_input();
+        let frozen = LzwSpec {
+            clear_code: false,
+            ..BYTES_SPEC
+        };
+        let lzap = LzwSpec {
+            growth: Growth::Lzap,
+            ..BYTES_SPEC
+        };
+        let lzmw = LzwSpec {
+            growth: Growth::Lzmw,
+            ..BYTES_SPEC
+        };
+        for spec in [BYTES_SPEC, frozen, lzmw, lzap] {
+            let (compressed, encoded) =
+                compress_with_stats::<TrieDictionary<u8>>(spec, None, &input).unwrap();
+            let (output, decoded) = decompress_with_stats(spec, None, &compressed).unwrap();
+            assert_eq!(output, input);
+            // Only the encoder knows its dictionary's memory
+            assert!(encoded.peak_memory.is_some() && decoded.peak_memory.is_none());
+            let codes_only = CodeStats {
+                peak_memory: None,
+                ..encoded.clone()
+            };
+            assert_eq!(codes_only, decoded, "{:?}", spec.growth);
+            let bits: u64 = encoded
+                .used_widths()
+                .map(|(width, count)| width as u64 * count)
+                .sum();
+            assert_eq!(bits.div_ceil(8), compressed.len() as u64);
+            assert!(encoded.longest_entry.unwrap() > 1);
+        }
+
+        // Narrow enough to fill up and be cleared
+        let narrow = LzwSpec {
+            max_width: 10,
+            ..BYTES_SPEC
+        };
+        let (_, stats) = compress_with_stats::<TrieDictionary<u8>>(narrow, None, &input).unwrap();
+        assert!(stats.clears > 0);
+        assert_eq!(
+            stats
+                .used_widths()
+                .map(|(width, _)| width)
+                .collect::<Vec<_>>(),
+            vec![9, 10]
+        );
+        let (_, stats) =
+            compress_with_stats::<MutableTrieDictionary<u8>>(BYTES_SPEC, None, &input).unwrap();
+        assert_eq!(stats.longest_entry, None);
+    }
+
+    fn small_messages() -> Vec<Vec<u8>> {
+        let mut state: u32 = 11;
+        (0..300)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                format!(
+                    "{{\"type\":\"metric\",\"host\":\"web-{}\",\"cpu\":{},\"status\":\"ok\"}}",
+                    (state >> 16) % 16,
+                    (state >> 8) % 100
+                )
+                .into_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shared_dictionary_helps_small_messages() {
+        let messages = small_messages();
+        let (training, testing) = messages.split_at(200);
+        let samples: Vec<&[u8]> = training.iter().map(|message| &message[..]).collect();
+        let shared = SharedDictionary::train(&samples, 1000);
+
+        let mut plain_len = 0;
+        let mut shared_len = 0;
+        for message in testing.iter() {
+            plain_len += compress::<TrieDictionary<u8>>(BYTES_SPEC, message)
+                .unwrap()
+                .len();
+            let compressed =
+                compress_with_dictionary::<TrieDictionary<u8>>(BYTES_SPEC, Some(&shared), message)
+                    .unwrap();
+            assert_eq!(
+                compressed,
+                compress_with_dictionary::<MutableTrieDictionary<u8>>(
+                    BYTES_SPEC,
+                    Some(&shared),
+                    message
+                )
+                .unwrap()
+            );
+            assert_eq!(
+                decompress_with_dictionary(BYTES_SPEC, Some(&shared), &compressed).unwrap(),
+                *message
+            );
+            shared_len += compressed.len();
+        }
+        assert!(
+            shared_len * 2 < plain_len,
+            "{} vs {}",
+            shared_len,
+            plain_len
+        );
+    }
+
+    #[test]
+    fn shared_dictionary_round_trips() {
+        let messages = small_messages();
+        let samples: Vec<&[u8]> = messages.iter().map(|message| &message[..]).collect();
+        let shared = SharedDictionary::train(&samples, 300);
+        let input = messages.concat();
+
+        let lru = LzwSpec {
+            evict_lru: true,
+            clear_code: false,
+            ..BYTES_SPEC
+        };
+        let specs = [
+            BYTES_SPEC,
+            lru,
+            LzwSpec {
+                growth: Growth::Lzap,
+                ..BYTES_SPEC
+            },
+            LzwSpec {
+                growth: Growth::Lzmw,
+                ..BYTES_SPEC
+            },
+        ];
+        // The dictionary fills, and is cleared or has entries replaced, many times over
+        for spec in specs {
+            for input in [&input[..], b"", b"x"] {
+                let compressed =
+                    compress_with_dictionary::<TrieDictionary<u8>>(spec, Some(&shared), input)
+                        .unwrap();
+                assert_eq!(
+                    decompress_with_dictionary(spec, Some(&shared), &compressed).unwrap(),
+                    input
+                );
+            }
+        }
+
+        // Too many entries for 9 bit codes
+        let narrow = LzwSpec {
+            variable_width: false,
+            width: 9,
+            ..BYTES_SPEC
+        };
+        assert!(
+            compress_with_dictionary::<TrieDictionary<u8>>(narrow, Some(&shared), b"x").is_err()
+        );
+        assert!(decompress_with_dictionary(narrow, Some(&shared), b"").is_err());
+    }
+
+    fn prose() -> Vec<u8> {
+        let words = [
+            "the", "quick", "brown", "fox", "jumps", "over", "lazy", "dog", "and", "runs", "away",
+            "from", "farmer", "who", "is", "très", "fâché",
+        ];
+        let mut state: u32 = 13;
+        let mut text = String::new();
+        for i in 0..8000 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            text.push_str(words[(state >> 16) as usize % words.len()]);
+            text.push_str(match i % 11 {
+                10 => ".\n",
+                5 => ", ",
+                _ => " ",
+            });
+        }
+        text.into_bytes()
+    }
+
+    #[test]
+    fn words_round_trip() {
+        let words = LzwSpec {
+            alphabet: Alphabet::Words,
+            ..BYTES_SPEC
+        };
+        let input = prose();
+        for spec in [
+            words,
+            LzwSpec {
+                growth: Growth::Lzap,
+                ..words
+            },
+            LzwSpec {
+                evict_lru: true,
+                ..words
+            },
+        ] {
+            for input in [&input[..], b"", b"x", b"  \t\n", &[0xff, 0xfe, b'!', 0x80]] {
+                let compressed = compress::<TrieDictionary<u8>>(spec, input).unwrap();
+                assert_eq!(decompress(spec, &compressed).unwrap(), input);
+            }
+        }
+
+        // Blocks split the input in the middle of words
+        let compressed =
+            block_mode::compress_blocks::<TrieDictionary<u8>>(words, &input, 1001, 2).unwrap();
+        assert_eq!(
+            block_mode::decompress_blocks(&compressed, 2).unwrap(),
+            input
+        );
+
+        let shared = SharedDictionary::train(&[&input], 10);
+        assert!(
+            compress_with_dictionary::<TrieDictionary<u8>>(words, Some(&shared), &input).is_err()
+        );
+    }
+
+    #[test]
+    fn words_compress_prose_better() {
+        let input = prose();
+        let words = LzwSpec {
+            alphabet: Alphabet::Words,
+            ..BYTES_SPEC
+        };
+        let bytes_len = compress::<TrieDictionary<u8>>(BYTES_SPEC, &input)
+            .unwrap()
+            .len();
+        let words_len = compress::<TrieDictionary<u8>>(words, &input).unwrap().len();
+        assert!(words_len < bytes_len, "{} vs {}", words_len, bytes_len);
+    }
+
+    #[test]
+    fn variable_width_is_smaller() {
+        let input = sample_input();
+        let fixed = LzwSpec {
+            variable_width: false,
+            ..BYTES_SPEC
+        };
+        let fixed_len = compress::<TrieDictionary<u8>>(fixed, &input).unwrap().len();
+        let variable_len = compress::<TrieDictionary<u8>>(BYTES_SPEC, &input)
+            .unwrap()
+            .len();
+        assert!(variable_len < fixed_len);
+    }
+
+    #[test]
+    fn invalid_specs() {
+        let narrow_without_end = LzwSpec {
+            min_width: 7,
+            end_code: false,
+            ..BYTES_SPEC
+        };
+        assert!(narrow_without_end.validate().is_err());
+
+        let too_wide = LzwSpec {
+            max_width: MAX_CODE_WIDTH + 1,
+            ..BYTES_SPEC
+        };
+        assert!(too_wide.validate().is_err());
+
+        let inverted = LzwSpec {
+            min_width: 13,
+            ..BYTES_SPEC
+        };
+        assert!(inverted.validate().is_err());
+
+        let alphabet_too_large = LzwSpec {
+            variable_width: false,
+            width: 8,
+            ..BYTES_SPEC
+        };
+        assert!(alphabet_too_large.validate().is_err());
+
+        let lru_with_lzmw = LzwSpec {
+            evict_lru: true,
+            growth: Growth::Lzmw,
+            ..BYTES_SPEC
+        };
+        assert!(lru_with_lzmw.validate().is_err());
+
+        let budget_too_small = LzwSpec {
+            memory_budget: Some(257 * DICTIONARY_ENTRY_BYTES),
+            ..BYTES_SPEC
+        };
+        assert!(budget_too_small.validate().is_err());
+
+        let budget_with_lzmw = LzwSpec {
+            memory_budget: Some(1 << 20),
+            growth: Growth::Lzmw,
+            ..BYTES_SPEC
+        };
+        assert!(budget_with_lzmw.validate().is_err());
+        assert!(BYTES_SPEC.validate().is_ok());
+    }
+
+    // Every valid combination of the spec options, with codes up to 16 bits wide and sometimes a memory budget
+    fn arb_spec() -> impl Strategy<Value = LzwSpec> {
+        (
+            any::<[bool; 7]>(),
+            prop_oneof![Just(Alphabet::Ascii), Just(Alphabet::Bytes)],
+            prop_oneof![Just(Growth::Lzw), Just(Growth::Lzmw), Just(Growth::Lzap)],
+            1u8..=16,
+            1u8..=16,
+            prop::option::of(0usize..1500),
+        )
+            .prop_map(|(flags, alphabet, growth, narrowest, widest, budget_entries)| {
+                let [variable_width, end_code, clear_code, pack_msb_first, early_change, evict_lru, range] = flags;
+                LzwSpec {
+                    alphabet,
+                    variable_width,
+                    width: widest,
+                    min_width: narrowest.min(widest),
+                    max_width: widest,
+                    end_code,
+                    clear_code,
+                    pack_msb_first,
+                    early_change,
+                    evict_lru,
+                    growth,
+                    coding: if range { Coding::Range } else { Coding::Packed },
+                    memory_budget: budget_entries.map(|entries| entries * DICTIONARY_ENTRY_BYTES),
+                }
+            })
+            .prop_filter("spec must be valid", |spec| spec.validate().is_ok())
+    }
+
+    // Bytes the spec's alphabet can encode: any byte, or printable ascii
+    fn arb_input(spec: LzwSpec) -> impl Strategy<Value = Vec<u8>> {
+        let byte = match spec.alphabet {
+            Alphabet::Bytes => (0..=u8::MAX).boxed(),
+            _ => (b' '..=b'~').boxed(),
+        };
+        // Low entropy inputs grow long dictionary entries and fill the dictionary
+        prop_oneof![
+            prop::collection::vec(byte, 0..2048),
+            prop::collection::vec(prop::sample::select(b"ab ".to_vec()), 0..4096),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn prop_round_trip((spec, input) in arb_spec().prop_flat_map(|spec| (Just(spec), arb_input(spec)))) {
+            let compressed = compress::<TrieDictionary<u8>>(spec, &input).unwrap();
+            // Only TrieDictionary supports LRU replacement and the other kinds of growth
+            if !spec.evict_lru && spec.growth == Growth::Lzw {
+                prop_assert_eq!(&compressed, &compress::<MutableTrieDictionary<u8>>(spec, &input).unwrap());
+            }
+            prop_assert_eq!(decompress(spec, &compressed).unwrap(), input);
+        }
+
+        #[test]
+        fn prop_decompress_garbage(spec in arb_spec(), input in prop::collection::vec(any::<u8>(), 0..512)) {
+            // Only has to not panic, errors are expected
+            let _ = decompress(spec, &input);
+            let _ = decompress(LzwSpec { alphabet: Alphabet::Words, ..spec }, &input);
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use crate::checkpoint::{self, StateReader};
+use crate::lzw_error::LzwError;
+use std::collections::BTreeSet;
+
+/* LEAST RECENTLY USED REPLACEMENT (LZT)
+    Instead of clearing or freezing a full dictionary, the entry that has gone longest without being
+    emitted is removed, and its code is reused for the new entry.
+    Only leaves can be removed, as every longer entry is built on its prefix. The initial dictionary is never removed.
+
+    The encoder and decoder each keep a tracker and update it in the same order, so they always pick the same entry:
+        touch the code emitted
+        then, if out of codes, evict a leaf other than the entry being extended
+        then add the new entry, which counts as used at the same time as its prefix
+    The decoder adds entries a step late, but always before touching the next code, so the order matches.
+    Ties are broken by the lowest code.
+*/
+
+#[derive(Copy, Clone, Debug)]
+struct Entry {
+    prefix_code: u32,
+    children: u32,
+    last_used: u64,
+}
+
+pub struct LruTracker {
+    // Codes below this are the initial dictionary
+    first_code: u32,
+    entries: Vec<Entry>,
+    // (last_used, code) of every entry with no children, oldest first
+    leaves: BTreeSet<(u64, u32)>,
+    clock: u64,
+}
+
+impl LruTracker {
+    pub fn new(first_code: u32) -> LruTracker {
+        LruTracker {
+            first_code,
+            entries: Vec::new(),
+            leaves: BTreeSet::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.entries.clear();
+        self.leaves.clear();
+        self.clock = 0;
+    }
+
+    fn index(&self, code: u32) -> Option<usize> {
+        code.checked_sub(self.first_code)
+            .map(|index| index as usize)
+    }
+
+    pub fn touch(&mut self, code: u32) {
+        self.clock += 1;
+        let Some(index) = self.index(code) else {
+            return;
+        };
+        if let Some(entry) = self.entries.get_mut(index) {
+            if entry.children == 0 {
+                self.leaves.remove(&(entry.last_used, code));
+                self.leaves.insert((self.clock, code));
+            }
+            entry.last_used = self.clock;
+        }
+    }
+
+    // Record a new entry, under either the next unused code or one just evicted
+    pub fn add(&mut self, code: u32, prefix_code: u32) {
+        if let Some(prefix) = self
+            .index(prefix_code)
+            .and_then(|i| self.entries.get_mut(i))
+        {
+            if prefix.children == 0 {
+                self.leaves.remove(&(prefix.last_used, prefix_code));
+            }
+            prefix.children += 1;
+        }
+
+        let entry = Entry {
+            prefix_code,
+            children: 0,
+            last_used: self.clock,
+        };
+        let index = self
+            .index(code)
+            .expect("Added a code from the initial dictionary");
+        if index == self.entries.len() {
+            self.entries.push(entry);
+        } else {
+            self.entries[index] = entry;
+        }
+        self.leaves.insert((self.clock, code));
+    }
+
+    // For a checkpoint, see checkpoint.rs
+    // Only when each entry was last used is written, the rest comes from the dictionary's prefix codes
+    pub fn write_state(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&self.first_code.to_le_bytes());
+        output.extend_from_slice(&self.clock.to_le_bytes());
+        output.extend_from_slice(&(self.entries.len() as u64).to_le_bytes());
+        for entry in self.entries.iter() {
+            output.extend_from_slice(&entry.last_used.to_le_bytes());
+        }
+    }
+
+    // prefixes holds the prefix code of every code in the dictionary, which must all be tracked
+    // from first_code on, and the leaves are the entries nothing is built on
+    pub fn read_state(
+        reader: &mut StateReader,
+        prefixes: &[Option<u32>],
+    ) -> Result<LruTracker, LzwError> {
+        let mut tracker = LruTracker::new(reader.read_u32()?);
+        tracker.clock = reader.read_u64()?;
+        let count = reader.read_count(8)?;
+        let first_code = tracker.first_code as usize;
+        if first_code.checked_add(count) != Some(prefixes.len()) {
+            return Err(checkpoint::invalid(format!(
+                "LRU tracks {} entries from {}, the dictionary has {} codes",
+                count,
+                first_code,
+                prefixes.len()
+            )));
+        }
+        // Only tracked entries can be built on tracked ones, or a prefix would look like a leaf
+        if let Some(code) = (0..first_code)
+            .find(|code| prefixes[*code].is_some_and(|prefix| prefix as usize >= first_code))
+        {
+            return Err(checkpoint::invalid(format!(
+                "Initial entry {} is built on an LRU entry",
+                code
+            )));
+        }
+        for (code, prefix_code) in prefixes.iter().enumerate().skip(first_code) {
+            let Some(prefix_code) = *prefix_code else {
+                return Err(checkpoint::invalid(format!(
+                    "LRU entry {} has no prefix",
+                    code
+                )));
+            };
+            tracker.entries.push(Entry {
+                prefix_code,
+                children: 0,
+                last_used: reader.read_u64()?,
+            });
+        }
+        for index in 0..tracker.entries.len() {
+            let prefix_code = tracker.entries[index].prefix_code;
+            if let Some(prefix) = tracker
+                .index(prefix_code)
+                .and_then(|i| tracker.entries.get_mut(i))
+            {
+                prefix.children += 1;
+            }
+        }
+        for (index, entry) in tracker.entries.iter().enumerate() {
+            if entry.children == 0 {
+                tracker
+                    .leaves
+                    .insert((entry.last_used, (first_code + index) as u32));
+            }
+        }
+        Ok(tracker)
+    }
+
+    // Estimated heap bytes, a BTreeSet takes about twice its elements
+    pub fn memory_used(&self) -> usize {
+        self.entries.capacity() * std::mem::size_of::<Entry>()
+            + self.leaves.len() * 2 * std::mem::size_of::<(u64, u32)>()
+    }
+
+    // Remove the least recently used leaf, other than keep, returning its code to be reused
+    pub fn evict(&mut self, keep: u32) -> Option<u32> {
+        let &(last_used, code) = self.leaves.iter().find(|(_, code)| *code != keep)?;
+        self.leaves.remove(&(last_used, code));
+
+        let prefix_code = self.entries[self.index(code)?].prefix_code;
+        if let Some(prefix) = self
+            .index(prefix_code)
+            .and_then(|i| self.entries.get_mut(i))
+        {
+            prefix.children -= 1;
+            if prefix.children == 0 {
+                self.leaves.insert((prefix.last_used, prefix_code));
+            }
+        }
+        Some(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut tracker = LruTracker::new(10);
+        tracker.touch(1);
+        tracker.add(10, 1);
+        tracker.touch(2);
+        tracker.add(11, 2);
+        tracker.touch(10);
+        // 11 is now older than 10
+        assert_eq!(tracker.evict(3), Some(11));
+        assert_eq!(tracker.evict(3), Some(10));
+        assert_eq!(tracker.evict(3), None);
+    }
+
+    #[test]
+    fn only_leaves_are_evicted() {
+        let mut tracker = LruTracker::new(10);
+        tracker.touch(1);
+        tracker.add(10, 1);
+        tracker.touch(10);
+        tracker.add(11, 10);
+        tracker.touch(2);
+        tracker.add(12, 2);
+
+        // 10 is the oldest, but 11 is built on it
+        assert_eq!(tracker.evict(12), Some(11));
+        // Now 10 is a leaf again
+        assert_eq!(tracker.evict(12), Some(10));
+        assert_eq!(tracker.evict(12), None);
+    }
+
+    #[test]
+    fn reused_code() {
+        let mut tracker = LruTracker::new(10);
+        tracker.touch(1);
+        tracker.add(10, 1);
+        tracker.touch(2);
+        tracker.add(11, 2);
+
+        let code = tracker.evict(11).unwrap();
+        assert_eq!(code, 10);
+        tracker.add(code, 11);
+        // 11 has a child now, and 10 is the newest leaf
+        assert_eq!(tracker.evict(3), Some(10));
+        assert_eq!(tracker.evict(3), Some(11));
+    }
+}
@@ -1,3 +1,5 @@
+use crate::checkpoint;
+use crate::lzw_error::LzwError;
 use crate::LzwSpec;
 use std::fmt;
 
@@ -41,35 +43,175 @@ pub struct CodeGenerator {
     width: u8,
     min_width: u8,
     max_width: u8,
-    pack_msb_first: bool,
+    early_change: bool,
+    // Codes from here on are not handed out, see LzwSpec::max_entries
+    code_limit: u32,
 }
 
+/* VARIABLE WIDTH
+    Codes are written with just enough bits for the largest code that could appear at that point,
+    clamped between min_width and max_width. The encoder and decoder work this out from their own counters,
+    so the width is never stored in the stream.
+    With early change the width goes up one code sooner than needed, as in TIFF and PDF.
+*/
+
 impl CodeGenerator {
     pub fn new(lzw_spec: LzwSpec) -> CodeGenerator {
-        // Perform any neccessary validation
+        // Validation is done by LzwSpec::validate
         CodeGenerator {
             current_code: 0,
             variable_width: lzw_spec.variable_width,
             width: lzw_spec.width,
             min_width: lzw_spec.min_width,
             max_width: lzw_spec.max_width,
-            pack_msb_first: lzw_spec.pack_msb_first,
+            early_change: lzw_spec.early_change,
+            code_limit: lzw_spec.max_entries(),
         }
     }
 
+    // Carry on handing out codes from current_code, where a checkpoint left off, see checkpoint.rs
+    pub fn resume(lzw_spec: LzwSpec, current_code: u32) -> Result<CodeGenerator, LzwError> {
+        let mut code_gen = CodeGenerator::new(lzw_spec);
+        if current_code > code_gen.code_limit {
+            return Err(checkpoint::invalid(format!(
+                "Code {} is past the last of {}",
+                current_code, code_gen.code_limit
+            )));
+        }
+        code_gen.current_code = current_code;
+        Ok(code_gen)
+    }
+
+    // Width of the largest code the dictionary may hold
+    pub fn max_code_width(&self) -> u8 {
+        if self.variable_width {
+            self.max_width
+        } else {
+            self.width
+        }
+    }
+
+    // Width to write codes with while largest is the largest code that could be written
+    pub fn width_for(&self, largest: u32) -> u8 {
+        if !self.variable_width {
+            return self.width;
+        }
+        let largest = largest + self.early_change as u32;
+        let needed = (u32::BITS - largest.leading_zeros()) as u8;
+        needed.clamp(self.min_width, self.max_width)
+    }
+
+    // Width to write the next code with, as the largest code handed out so far could be next
+    pub fn current_width(&self) -> u8 {
+        self.width_for(self.current_code.saturating_sub(1))
+    }
+
+    pub fn get_current_code(&self) -> u32 {
+        self.current_code
+    }
+
+    // Out of codes, or at the memory budget's cap on entries
+    pub fn is_exhausted(&self) -> bool {
+        self.current_code >= self.code_limit
+    }
+
     pub fn get_next_code(&mut self) -> Option<Code> {
         // Check that current bit width is respected
-        if self.current_code >> self.width != 0 {
+        if self.is_exhausted() {
             tracing::debug!(
-                "All codes for current code width {} already used",
-                self.width,
+                "All {} codes for code width {} already used",
+                self.code_limit,
+                self.max_code_width(),
             );
             None
         } else {
             // current fits within bit width
-            let res = Some(Code::new(self.current_code, self.width));
+            let res = Some(Code::new(self.current_code, self.max_code_width()));
             self.current_code += 1;
             res
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::alphabets;
+    use crate::{Coding, Growth};
+
+    const VARIABLE_SPEC: LzwSpec = LzwSpec {
+        alphabet: alphabets::Alphabet::Bytes,
+        variable_width: true,
+        width: 12,
+        min_width: 9,
+        max_width: 12,
+        end_code: true,
+        clear_code: true,
+        pack_msb_first: true,
+        early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
+    };
+
+    #[test]
+    fn fixed_width() {
+        let spec = LzwSpec {
+            variable_width: false,
+            ..VARIABLE_SPEC
+        };
+        let code_gen = CodeGenerator::new(spec);
+        assert_eq!(code_gen.width_for(0), 12);
+        assert_eq!(code_gen.width_for(4000), 12);
+    }
+
+    #[test]
+    fn variable_width() {
+        let code_gen = CodeGenerator::new(VARIABLE_SPEC);
+        assert_eq!(code_gen.width_for(0), 9);
+        assert_eq!(code_gen.width_for(511), 9);
+        assert_eq!(code_gen.width_for(512), 10);
+        assert_eq!(code_gen.width_for(4095), 12);
+        assert_eq!(code_gen.width_for(4096), 12);
+    }
+
+    #[test]
+    fn variable_width_early_change() {
+        let spec = LzwSpec {
+            early_change: true,
+            ..VARIABLE_SPEC
+        };
+        let code_gen = CodeGenerator::new(spec);
+        assert_eq!(code_gen.width_for(510), 9);
+        assert_eq!(code_gen.width_for(511), 10);
+        assert_eq!(code_gen.width_for(4095), 12);
+    }
+
+    #[test]
+    fn exhausted_at_max_width() {
+        let mut code_gen = CodeGenerator::new(VARIABLE_SPEC);
+        for _ in 0..4096 {
+            assert!(code_gen.get_next_code().is_some());
+        }
+        assert!(code_gen.is_exhausted());
+        assert!(code_gen.get_next_code().is_none());
+    }
+
+    #[test]
+    fn exhausted_at_budget() {
+        let spec = LzwSpec {
+            memory_budget: Some(1000 * crate::DICTIONARY_ENTRY_BYTES + 100),
+            ..VARIABLE_SPEC
+        };
+        let mut code_gen = CodeGenerator::new(spec);
+        for _ in 0..1000 {
+            assert!(code_gen.get_next_code().is_some());
+        }
+        assert!(code_gen.is_exhausted());
+        assert!(code_gen.get_next_code().is_none());
+        // Codes are only as wide as the entries allowed need
+        assert_eq!(code_gen.current_width(), 10);
+    }
+}
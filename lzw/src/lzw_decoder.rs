@@ -0,0 +1,453 @@
+use crate::bit_packing::BitReader;
+use crate::decoder_table::DecoderTable;
+use crate::lru_tracker::LruTracker;
+use crate::lzw_code::{Code, CodeGenerator};
+use crate::lzw_error::LzwError;
+use crate::lzw_token::{ControlToken, HashableToken, Token};
+use crate::range_coder::CodeDecoder;
+use crate::seekable_reader::SeekableReader;
+use crate::shared_dictionary::SharedDictionary;
+use crate::stats::CodeStats;
+use crate::{Coding, Growth, LzwSpec, MAX_GROWN_LENGTH};
+use std::io::{Read, Seek};
+
+/* DECODING LOOP
+    The decoder rebuilds the encoder's dictionary one step behind it.
+    After reading a code, the entry the encoder added on the previous step is
+        previous sequence + first token of the current sequence
+    The 2 cases to think of:
+        1. The code is already in the table, expand it and add the entry
+        2. The code is the one about to be added (cScSc), so the current sequence is
+           previous sequence + first token of the previous sequence
+    With LRU replacement the code about to be added may be an evicted one that is still in the table,
+    so case 2 is checked first.
+
+    LZMW and LZAP entries only need the whole of the current sequence, so the decoder keeps up with
+    the encoder, and every code read must already be in the table.
+*/
+
+pub struct LzwDecoder<T: HashableToken> {
+    spec: LzwSpec,
+    code_gen: CodeGenerator,
+    table: DecoderTable<T>,
+    previous_code: Option<u32>,
+    buffer: Vec<Token<T>>,
+    ended: bool,
+    lru: Option<LruTracker>,
+    stats: CodeStats,
+}
+
+impl<T: HashableToken> LzwDecoder<T> {
+    pub fn new(spec: LzwSpec, alphabet: Vec<Token<T>>) -> LzwDecoder<T> {
+        let mut code_gen = CodeGenerator::new(spec);
+        let table = DecoderTable::new(spec, &mut code_gen, alphabet);
+        let lru = spec.evict_lru.then(|| LruTracker::new(table.next_code()));
+        LzwDecoder {
+            spec,
+            code_gen,
+            table,
+            previous_code: None,
+            buffer: Vec::new(),
+            ended: false,
+            lru,
+            stats: CodeStats::default(),
+        }
+    }
+
+    // Start from the initial table plus the entries of a shared dictionary, see SharedDictionary
+    pub fn with_dictionary(
+        spec: LzwSpec,
+        alphabet: Vec<Token<T>>,
+        shared: &[Vec<Token<T>>],
+    ) -> Result<LzwDecoder<T>, LzwError> {
+        let mut decoder = LzwDecoder::new(spec, alphabet);
+        decoder.table.seed(shared, &mut decoder.code_gen)?;
+        if let Some(lru) = &mut decoder.lru {
+            *lru = LruTracker::new(decoder.table.next_code());
+        }
+        Ok(decoder)
+    }
+
+    fn reset(&mut self) {
+        self.stats.record_longest(Some(self.table.longest_entry()));
+        self.table.reset();
+        // Move the generator past the codes of the initial table
+        self.code_gen = CodeGenerator::new(self.spec);
+        for _ in 0..self.table.next_code() {
+            self.code_gen.get_next_code();
+        }
+        self.previous_code = None;
+        if let Some(lru) = &mut self.lru {
+            lru.reset();
+        }
+    }
+
+    // The code the encoder gave the entry it added along with previous, if it added one
+    fn pending_code(&mut self, previous: u32) -> Option<u32> {
+        if !self.code_gen.is_exhausted() {
+            return Some(self.table.next_code());
+        }
+        self.lru.as_mut().and_then(|lru| lru.evict(previous))
+    }
+
+    // Same entries as TrieDictionary::grow, the current sequence is in the buffer
+    fn grow(&mut self, previous: u32, code: u32) {
+        let length = self.table.length(previous) as usize + self.buffer.len();
+        if length > MAX_GROWN_LENGTH {
+            return;
+        }
+        match self.spec.growth {
+            Growth::Lzw => {}
+            Growth::Lzmw => {
+                if self.code_gen.get_next_code().is_some() {
+                    self.table.add_joined(previous, code);
+                }
+            }
+            Growth::Lzap => {
+                let mut prefix_code = previous;
+                for token in self.buffer.iter() {
+                    if self.code_gen.get_next_code().is_none() {
+                        break;
+                    }
+                    self.table.add(prefix_code, *token);
+                    prefix_code = self.table.next_code() - 1;
+                }
+            }
+        }
+    }
+
+    // Decode a single code, appending its sequence to output
+    pub fn decode_code(&mut self, code: u32, output: &mut Vec<Token<T>>) -> Result<(), LzwError> {
+        if self.ended {
+            return Err(LzwError::Decode(format!(
+                "Code {} after the End code",
+                code
+            )));
+        }
+        self.stats.record_code(self.current_width());
+        match self.table.control(code) {
+            Some(ControlToken::Clear) => {
+                self.stats.clears += 1;
+                self.reset();
+                return Ok(());
+            }
+            Some(ControlToken::End) => {
+                self.ended = true;
+                return Ok(());
+            }
+            None => {}
+        }
+
+        match self.previous_code {
+            None => {
+                if !self.table.contains(code) {
+                    return Err(LzwError::Decode(format!(
+                        "First code {} is not in the initial dictionary",
+                        code
+                    )));
+                }
+                self.table.expand_into(code, &mut self.buffer);
+            }
+            Some(previous) if self.spec.growth != Growth::Lzw => {
+                if !self.table.contains(code) {
+                    return Err(LzwError::Decode(format!(
+                        "Code {} is not in the dictionary",
+                        code
+                    )));
+                }
+                self.table.expand_into(code, &mut self.buffer);
+                self.grow(previous, code);
+            }
+            Some(previous) => {
+                let pending = self.pending_code(previous);
+                if pending == Some(code) {
+                    self.table.expand_into(previous, &mut self.buffer);
+                    self.buffer.push(self.buffer[0]);
+                } else if self.table.contains(code) {
+                    self.table.expand_into(code, &mut self.buffer);
+                } else {
+                    return Err(LzwError::Decode(format!(
+                        "Code {} is not in the dictionary",
+                        code
+                    )));
+                }
+                if let Some(pending) = pending {
+                    if pending == self.table.next_code() {
+                        self.code_gen.get_next_code();
+                        self.table.add(previous, self.buffer[0]);
+                    } else {
+                        self.table.replace(pending, previous, self.buffer[0]);
+                    }
+                    if let Some(lru) = &mut self.lru {
+                        lru.add(pending, previous);
+                    }
+                }
+            }
+        }
+        if let Some(lru) = &mut self.lru {
+            lru.touch(code);
+        }
+
+        output.extend_from_slice(&self.buffer);
+        self.previous_code = Some(code);
+        Ok(())
+    }
+
+    // What has been decoded so far, see stats.rs
+    pub fn stats(&self) -> CodeStats {
+        let mut stats = self.stats.clone();
+        stats.dictionary_entries = self.table.next_code() as u64;
+        stats.record_longest(Some(self.table.longest_entry()));
+        stats
+    }
+
+    // Width of the next code, see CodeGenerator for how the encoder picks it
+    fn current_width(&self) -> u8 {
+        let next_code = self.table.next_code();
+        match self.previous_code {
+            // Could be the code about to be added, unless the dictionary is full
+            Some(_) if self.spec.growth == Growth::Lzw && !self.code_gen.is_exhausted() => {
+                self.code_gen.width_for(next_code)
+            }
+            _ => self.code_gen.width_for(next_code - 1),
+        }
+    }
+
+    pub fn decompress(&mut self, bytes: &[u8]) -> Result<Vec<Token<T>>, LzwError> {
+        let mut output: Vec<Token<T>> = Vec::new();
+        match self.spec.coding {
+            Coding::Packed => {
+                let mut reader = BitReader::new(bytes, self.spec.pack_msb_first);
+                self.read_codes(&mut reader, &mut output)?;
+            }
+            Coding::Range => {
+                let mut reader = CodeDecoder::new(bytes);
+                while !self.ended {
+                    let Some(code) = reader.decode(self.current_width())? else {
+                        break;
+                    };
+                    self.decode_code(code, &mut output)?;
+                }
+                if self.ended {
+                    reader.finish()?;
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    // Decode packed codes until the End code or until the reader runs out
+    pub fn read_codes(
+        &mut self,
+        reader: &mut BitReader,
+        output: &mut Vec<Token<T>>,
+    ) -> Result<(), LzwError> {
+        while !self.ended {
+            let Some(code) = reader.read(self.current_width()) else {
+                break;
+            };
+            self.decode_code(code, output)?;
+        }
+        Ok(())
+    }
+
+    // Whether the End code has been read
+    pub fn has_ended(&self) -> bool {
+        self.ended
+    }
+
+    // For a stream with more data after it: decompress up to the End code,
+    // also returning the number of bytes the stream took up
+    pub fn decompress_until_end(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<(Vec<Token<T>>, usize), LzwError> {
+        if self.spec.coding != Coding::Packed || !self.spec.end_code {
+            return Err(LzwError::Spec(
+                "Only packed codes with an End code can be followed by more data".to_string(),
+            ));
+        }
+        let mut reader = BitReader::new(bytes, self.spec.pack_msb_first);
+        let mut output: Vec<Token<T>> = Vec::new();
+        while !self.ended {
+            let Some(code) = reader.read(self.current_width()) else {
+                return Err(LzwError::Decode(
+                    "Stream ended before its End code".to_string(),
+                ));
+            };
+            self.decode_code(code, &mut output)?;
+        }
+        Ok((output, reader.bytes_read()))
+    }
+
+    pub fn decode<I>(&mut self, codes: I) -> Result<Vec<Token<T>>, LzwError>
+    where
+        I: Iterator<Item = Code>,
+    {
+        let mut output: Vec<Token<T>> = Vec::new();
+        for code in codes {
+            self.decode_code(code.get_code(), &mut output)?;
+            if self.ended {
+                break;
+            }
+        }
+        Ok(output)
+    }
+}
+
+// A single stream can only be decoded from the start. To seek to an uncompressed position, decode a
+// block mode container, whose index has the uncompressed offset of every block, through the
+// seekable view, see SeekableReader
+impl LzwDecoder<u8> {
+    pub fn seekable<R: Read + Seek>(
+        source: R,
+        shared: Option<SharedDictionary>,
+    ) -> Result<SeekableReader<R>, LzwError> {
+        SeekableReader::with_dictionary(source, shared)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::alphabets;
+    use crate::lzw_encoder::LzwEncoder;
+    use crate::trie_dictionary::TrieDictionary;
+
+    const ASCII_SPEC: LzwSpec = LzwSpec {
+        alphabet: alphabets::Alphabet::Ascii,
+        variable_width: false,
+        width: 12,
+        min_width: 12,
+        max_width: 12,
+        end_code: true,
+        clear_code: true,
+        pack_msb_first: true,
+        early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
+    };
+
+    const SMALL_SPEC: LzwSpec = LzwSpec {
+        width: 8,
+        min_width: 8,
+        max_width: 8,
+        ..ASCII_SPEC
+    };
+
+    const SMALL_FROZEN_SPEC: LzwSpec = LzwSpec {
+        clear_code: false,
+        end_code: false,
+        ..SMALL_SPEC
+    };
+
+    const SMALL_LRU_SPEC: LzwSpec = LzwSpec {
+        evict_lru: true,
+        ..SMALL_SPEC
+    };
+
+    fn tokens(input: &str) -> Vec<Token<char>> {
+        input.chars().map(Token::new).collect()
+    }
+
+    fn long_input() -> String {
+        let words = [
+            "to", "be", "or", "not", "TO", "BE", "that", "is", "the", "question",
+        ];
+        let mut state: u32 = 11;
+        let mut input = String::new();
+        for _ in 0..2000 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            input.push_str(words[(state >> 16) as usize % words.len()]);
+            input.push(' ');
+        }
+        input
+    }
+
+    fn round_trip(spec: LzwSpec, input: &str) {
+        let mut encoder: LzwEncoder<char, TrieDictionary<char>> =
+            LzwEncoder::new(spec, alphabets::generate_ascii());
+        let codes = encoder.encode(tokens(input).into_iter()).unwrap();
+
+        let mut decoder = LzwDecoder::new(spec, alphabets::generate_ascii());
+        let decoded = decoder.decode(codes.into_iter()).unwrap();
+        assert_eq!(decoded, tokens(input));
+    }
+
+    #[test]
+    fn decode_round_trip() {
+        for spec in [ASCII_SPEC, SMALL_SPEC, SMALL_FROZEN_SPEC, SMALL_LRU_SPEC] {
+            round_trip(spec, "");
+            round_trip(spec, "a");
+            round_trip(spec, "tobeornottobeortobeornot");
+            round_trip(spec, &long_input());
+        }
+    }
+
+    #[test]
+    fn decode_code_not_yet_in_table() {
+        // "abababa" is encoded as a, b, ab, aba, where aba is only added once it has been read
+        round_trip(ASCII_SPEC, "abababa");
+        round_trip(ASCII_SPEC, "aaaaaaaaaa");
+    }
+
+    #[test]
+    fn decode_reused_code() {
+        // Long runs grow the dictionary fast, so evicted codes are soon reused,
+        // sometimes by the very next code (the cScSc case on a reused code)
+        let mut input = long_input();
+        for length in 1..200 {
+            input.push_str(&"x".repeat(length));
+            input.push_str(&"ab".repeat(length));
+        }
+        round_trip(SMALL_LRU_SPEC, &input);
+        round_trip(
+            LzwSpec {
+                clear_code: false,
+                end_code: false,
+                ..SMALL_LRU_SPEC
+            },
+            &input,
+        );
+    }
+
+    #[test]
+    fn decode_growth_variants() {
+        for growth in [Growth::Lzmw, Growth::Lzap] {
+            // The small specs fill up, so the dictionary is cleared or frozen part way through
+            for spec in [ASCII_SPEC, SMALL_SPEC, SMALL_FROZEN_SPEC] {
+                let spec = LzwSpec { growth, ..spec };
+                round_trip(spec, "");
+                round_trip(spec, "a");
+                round_trip(spec, "abababababababab");
+                round_trip(spec, "tobeornottobeortobeornot");
+                round_trip(spec, &long_input());
+            }
+        }
+    }
+
+    #[test]
+    fn decode_invalid_code() {
+        let mut decoder: LzwDecoder<char> =
+            LzwDecoder::new(ASCII_SPEC, alphabets::generate_ascii());
+        let mut output = Vec::new();
+        decoder.decode_code(0, &mut output).unwrap();
+        assert!(decoder.decode_code(500, &mut output).is_err());
+    }
+
+    #[test]
+    fn decode_stops_at_end() {
+        let mut encoder: LzwEncoder<char, TrieDictionary<char>> =
+            LzwEncoder::new(ASCII_SPEC, alphabets::generate_ascii());
+        let mut codes = encoder.encode(tokens("abc").into_iter()).unwrap();
+        // Anything after the End code is ignored
+        codes.push(codes[0]);
+
+        let mut decoder = LzwDecoder::new(ASCII_SPEC, alphabets::generate_ascii());
+        let decoded = decoder.decode(codes.into_iter()).unwrap();
+        assert_eq!(decoded, tokens("abc"));
+    }
+}
@@ -0,0 +1,57 @@
+use crate::lzw_code::{Code, CodeGenerator};
+use crate::lzw_error::LzwError;
+use crate::lzw_token::{ControlToken, HashableToken, Token};
+use crate::LzwSpec;
+use std::iter::Peekable;
+
+// Common interface for the dictionaries that can drive an LzwEncoder
+// Allows the encoder to be run with either trie implementation and the outputs compared
+
+pub trait LzwDictionary<T: HashableToken> {
+    fn initialise(lzw_spec: LzwSpec, code_gen: &mut CodeGenerator, alphabet: Vec<Token<T>>) -> Self
+    where
+        Self: Sized;
+
+    // Consume the longest sequence w at the front of the tokens that is in the dictionary, returning its code
+    // If a token follows w, w+token is added with the next code from code_gen, if there is one left
+    // Returns None once the tokens are exhausted
+    fn lzw_step<I: Iterator<Item = Token<T>>>(
+        &mut self,
+        tokens: &mut Peekable<I>,
+        code_gen: &mut CodeGenerator,
+    ) -> Result<Option<Code>, LzwError>;
+
+    fn control_code(&self, control_token: ControlToken) -> Option<Code>;
+
+    // Add the entries of a shared dictionary, each under the next code from code_gen
+    // Every entry is at least two tokens long, and its prefix is either a single token or an earlier entry
+    fn seed(
+        &mut self,
+        entries: &[Vec<Token<T>>],
+        code_gen: &mut CodeGenerator,
+    ) -> Result<(), LzwError>;
+
+    // Back to the initial dictionary after a Clear
+    // Dictionaries that read ahead of their match (see Growth) must keep the tokens they hold back
+    fn reset(&mut self, lzw_spec: LzwSpec, code_gen: &mut CodeGenerator, alphabet: Vec<Token<T>>)
+    where
+        Self: Sized,
+    {
+        *self = Self::initialise(lzw_spec, code_gen, alphabet);
+    }
+
+    // Estimated heap bytes in use, for dictionaries that can tell, see MEMORY BUDGET in lib.rs
+    fn memory_used(&self) -> Option<usize> {
+        None
+    }
+
+    // Tokens in the longest entry, for dictionaries that can tell
+    fn longest_entry(&self) -> Option<usize> {
+        None
+    }
+
+    // Whether tokens have been read from the input that have not been encoded yet
+    fn has_lookahead(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,391 @@
+use crate::bit_packing::BitWriter;
+use crate::checkpoint::StateReader;
+use crate::lzw_code::{Code, CodeGenerator};
+use crate::lzw_dictionary::LzwDictionary;
+use crate::lzw_error::LzwError;
+use crate::lzw_token::{ControlToken, HashableToken, Token};
+use crate::range_coder::CodeEncoder;
+use crate::stats::CodeStats;
+use crate::trie_dictionary::TrieDictionary;
+use crate::{Coding, Growth, LzwSpec};
+use std::iter::Peekable;
+use std::marker::PhantomData;
+
+/* ENCODING LOOP
+    Repeatedly ask the dictionary for the code of the longest known sequence at the front of the input,
+    letting it add that sequence plus the following token under the next code.
+    Once the code generator runs out of codes the dictionary is either
+        left to replace its least recently used entries, if the spec asks for LRU replacement
+        reset, emitting the Clear code, if the spec has a clear code
+        frozen, so that no more sequences are added, otherwise
+    What is added on each step depends on the spec's Growth.
+    A shared dictionary's entries are added after the initial dictionary, again after every reset.
+*/
+
+pub struct LzwEncoder<T: HashableToken, D: LzwDictionary<T>> {
+    spec: LzwSpec,
+    alphabet: Vec<Token<T>>,
+    code_gen: CodeGenerator,
+    dictionary: D,
+    shared: Vec<Vec<Token<T>>>,
+    // Includes the most memory the dictionary has used, as it only grows between resets
+    stats: CodeStats,
+    // Whether a code has been emitted since the start or the last Clear
+    emitted: bool,
+    _token: PhantomData<T>,
+}
+
+impl<T: HashableToken, D: LzwDictionary<T>> LzwEncoder<T, D> {
+    pub fn new(spec: LzwSpec, alphabet: Vec<Token<T>>) -> LzwEncoder<T, D> {
+        let mut code_gen = CodeGenerator::new(spec);
+        let dictionary = D::initialise(spec, &mut code_gen, alphabet.clone());
+        LzwEncoder {
+            spec,
+            alphabet,
+            code_gen,
+            dictionary,
+            shared: Vec::new(),
+            stats: CodeStats::default(),
+            emitted: false,
+            _token: PhantomData,
+        }
+    }
+
+    // Start from the initial dictionary plus the entries of a shared dictionary, see SharedDictionary
+    pub fn with_dictionary(
+        spec: LzwSpec,
+        alphabet: Vec<Token<T>>,
+        shared: Vec<Vec<Token<T>>>,
+    ) -> Result<LzwEncoder<T, D>, LzwError> {
+        let mut encoder: LzwEncoder<T, D> = LzwEncoder::new(spec, alphabet);
+        encoder.dictionary.seed(&shared, &mut encoder.code_gen)?;
+        encoder.shared = shared;
+        Ok(encoder)
+    }
+
+    fn reset(&mut self) -> Result<(), LzwError> {
+        self.stats.record_memory(self.dictionary.memory_used());
+        self.stats.record_longest(self.dictionary.longest_entry());
+        self.code_gen = CodeGenerator::new(self.spec);
+        self.dictionary
+            .reset(self.spec, &mut self.code_gen, self.alphabet.clone());
+        self.dictionary.seed(&self.shared, &mut self.code_gen)
+    }
+
+    // Estimated heap bytes the dictionary uses now, and the most it has used, if it can tell
+    // The peak is updated before every reset and at the end of encode
+    pub fn memory_used(&self) -> Option<usize> {
+        self.dictionary.memory_used()
+    }
+
+    pub fn peak_memory(&self) -> Option<usize> {
+        self.stats.peak_memory.map(|peak| peak as usize)
+    }
+
+    // What has been encoded so far, see stats.rs
+    pub fn stats(&self) -> CodeStats {
+        let mut stats = self.stats.clone();
+        stats.dictionary_entries = self.code_gen.get_current_code() as u64;
+        stats.record_longest(self.dictionary.longest_entry());
+        stats.record_memory(self.dictionary.memory_used());
+        stats
+    }
+
+    // The dictionary as it stands, which is the initial one again after a Clear
+    pub fn dictionary(&self) -> &D {
+        &self.dictionary
+    }
+
+    fn control_code(&self, control_token: ControlToken) -> Result<Code, LzwError> {
+        self.dictionary
+            .control_code(control_token)
+            .ok_or(LzwError::Encode(format!(
+                "No code for {:?} in the dictionary",
+                control_token
+            )))
+    }
+
+    // The Clear code, in the width it would be written with next
+    pub fn clear_code(&self) -> Result<Code, LzwError> {
+        let clear = self.control_code(ControlToken::Clear)?;
+        Ok(Code::new(clear.get_code(), self.code_gen.current_width()))
+    }
+
+    pub fn encode<I>(&mut self, tokens: I) -> Result<Vec<Code>, LzwError>
+    where
+        I: Iterator<Item = Token<T>>,
+    {
+        let mut tokens = tokens.peekable();
+        let mut codes: Vec<Code> = Vec::new();
+        self.emitted = false;
+        while self.encode_step(&mut tokens, &mut codes)? {}
+        self.encode_end(&mut codes)?;
+        Ok(codes)
+    }
+
+    // One step of the encoding loop, adding the codes emitted to codes
+    // Codes are returned with the width they should be written with
+    // Returns false, having added nothing, once the tokens are exhausted
+    pub fn encode_step<I>(
+        &mut self,
+        tokens: &mut Peekable<I>,
+        codes: &mut Vec<Code>,
+    ) -> Result<bool, LzwError>
+    where
+        I: Iterator<Item = Token<T>>,
+    {
+        let width = self.code_gen.current_width();
+        let Some(code) = self.dictionary.lzw_step(tokens, &mut self.code_gen)? else {
+            return Ok(false);
+        };
+        self.push_code(codes, Code::new(code.get_code(), width));
+        self.emitted = true;
+
+        let full = self.code_gen.is_exhausted() && !self.spec.evict_lru;
+        let input_left = tokens.peek().is_some() || self.dictionary.has_lookahead();
+        if self.spec.clear_code && full && input_left {
+            let clear = self.control_code(ControlToken::Clear)?;
+            self.push_code(
+                codes,
+                Code::new(clear.get_code(), self.code_gen.current_width()),
+            );
+            self.stats.clears += 1;
+            self.reset()?;
+            self.emitted = false;
+        }
+        Ok(true)
+    }
+
+    // Once every token has been encoded, add the End code if the spec has one
+    pub fn encode_end(&mut self, codes: &mut Vec<Code>) -> Result<(), LzwError> {
+        self.stats.record_memory(self.dictionary.memory_used());
+
+        if self.spec.end_code {
+            // The last step adds nothing, but the LZW decoder can't know that, and expects one more code
+            let width =
+                if self.emitted && self.spec.growth == Growth::Lzw && !self.code_gen.is_exhausted()
+                {
+                    self.code_gen.width_for(self.code_gen.get_current_code())
+                } else {
+                    self.code_gen.current_width()
+                };
+            let end = self.control_code(ControlToken::End)?;
+            self.push_code(codes, Code::new(end.get_code(), width));
+        }
+        Ok(())
+    }
+
+    fn push_code(&mut self, codes: &mut Vec<Code>, code: Code) {
+        self.stats.record_code(code.get_used_bits());
+        codes.push(code);
+    }
+
+    pub fn compress<I>(&mut self, tokens: I) -> Result<Vec<u8>, LzwError>
+    where
+        I: Iterator<Item = Token<T>>,
+    {
+        let codes = self.encode(tokens)?;
+        match self.spec.coding {
+            Coding::Packed => {
+                let mut writer = BitWriter::new(self.spec.pack_msb_first);
+                for code in codes {
+                    writer.write(code.get_code(), code.get_used_bits());
+                }
+                Ok(writer.finish())
+            }
+            Coding::Range => {
+                let mut encoder = CodeEncoder::new();
+                for code in codes {
+                    encoder.encode(code.get_code(), code.get_used_bits());
+                }
+                Ok(encoder.finish())
+            }
+        }
+    }
+}
+
+// The state of a byte encoder for a checkpoint, see checkpoint.rs
+// The spec, alphabet and shared dictionary are not part of it, and must be given again to resume
+impl LzwEncoder<u8, TrieDictionary<u8>> {
+    pub fn write_state(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&self.code_gen.get_current_code().to_le_bytes());
+        output.push(self.emitted as u8);
+        self.stats.write_to(output);
+        self.dictionary.write_state(output);
+    }
+
+    pub fn read_state(
+        spec: LzwSpec,
+        alphabet: Vec<Token<u8>>,
+        shared: Vec<Vec<Token<u8>>>,
+        reader: &mut StateReader,
+    ) -> Result<LzwEncoder<u8, TrieDictionary<u8>>, LzwError> {
+        let code_gen = CodeGenerator::resume(spec, reader.read_u32()?)?;
+        let emitted = reader.read_bool()?;
+        let stats = CodeStats::read_from(reader)?;
+        let dictionary = TrieDictionary::read_state(spec, code_gen.get_current_code(), reader)?;
+        Ok(LzwEncoder {
+            spec,
+            alphabet,
+            code_gen,
+            dictionary,
+            shared,
+            stats,
+            emitted,
+            _token: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::alphabets;
+    use crate::mutable_trie_dict::MutableTrieDictionary;
+
+    const ASCII_SPEC: LzwSpec = LzwSpec {
+        alphabet: alphabets::Alphabet::Ascii,
+        variable_width: false,
+        width: 12,
+        min_width: 12,
+        max_width: 12,
+        end_code: true,
+        clear_code: true,
+        pack_msb_first: true,
+        early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
+    };
+
+    // Small enough that the dictionary fills up on the longer inputs
+    const SMALL_SPEC: LzwSpec = LzwSpec {
+        width: 8,
+        min_width: 8,
+        max_width: 8,
+        ..ASCII_SPEC
+    };
+
+    const SMALL_FROZEN_SPEC: LzwSpec = LzwSpec {
+        clear_code: false,
+        ..SMALL_SPEC
+    };
+
+    fn tokens(input: &str) -> Vec<Token<char>> {
+        input.chars().map(Token::new).collect()
+    }
+
+    fn long_input() -> String {
+        // Deterministic but not too repetitive, so plenty of new sequences are added
+        let words = [
+            "to", "be", "or", "not", "TO", "BE", "that", "is", "the", "question",
+        ];
+        let mut state: u32 = 7;
+        let mut input = String::new();
+        for _ in 0..2000 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            input.push_str(words[(state >> 16) as usize % words.len()]);
+            input.push(' ');
+        }
+        input
+    }
+
+    fn encode_with<D: LzwDictionary<char>>(spec: LzwSpec, input: &str) -> Vec<Code> {
+        let mut encoder: LzwEncoder<char, D> = LzwEncoder::new(spec, alphabets::generate_ascii());
+        encoder.encode(tokens(input).into_iter()).unwrap()
+    }
+
+    #[test]
+    fn encode_repeated_pair() {
+        let codes = encode_with::<TrieDictionary<char>>(ASCII_SPEC, "abababa");
+
+        let mut code_gen = CodeGenerator::new(ASCII_SPEC);
+        let dict = TrieDictionary::new(ASCII_SPEC, &mut code_gen, alphabets::generate_ascii());
+        let a = dict._search(&tokens("a")).unwrap();
+        let b = dict._search(&tokens("b")).unwrap();
+        let ab = code_gen.get_next_code().unwrap();
+        let _ba = code_gen.get_next_code().unwrap();
+        let aba = code_gen.get_next_code().unwrap();
+        let end = dict.control_code(ControlToken::End).unwrap();
+
+        // a, b, ab, aba, then end
+        assert_eq!(codes, vec![a, b, ab, aba, end]);
+    }
+
+    #[test]
+    fn encode_empty() {
+        let codes = encode_with::<TrieDictionary<char>>(ASCII_SPEC, "");
+        assert_eq!(codes.len(), 1);
+    }
+
+    #[test]
+    fn encode_unknown_token() {
+        let mut encoder: LzwEncoder<char, TrieDictionary<char>> =
+            LzwEncoder::new(ASCII_SPEC, alphabets::generate_ascii());
+        assert!(encoder.encode(tokens("ab\ncd").into_iter()).is_err());
+    }
+
+    #[test]
+    fn encode_clears_when_full() {
+        let codes = encode_with::<TrieDictionary<char>>(SMALL_SPEC, &long_input());
+        let clear =
+            LzwEncoder::<char, TrieDictionary<char>>::new(SMALL_SPEC, alphabets::generate_ascii())
+                .control_code(ControlToken::Clear)
+                .unwrap();
+        assert!(codes.contains(&clear));
+    }
+
+    #[test]
+    fn encode_lru_never_clears() {
+        let spec = LzwSpec {
+            evict_lru: true,
+            ..SMALL_SPEC
+        };
+        let codes = encode_with::<TrieDictionary<char>>(spec, &long_input());
+        let encoder =
+            LzwEncoder::<char, TrieDictionary<char>>::new(spec, alphabets::generate_ascii());
+        let clear = encoder.control_code(ControlToken::Clear).unwrap();
+        assert!(!codes.contains(&clear));
+        // Every code still fits in the 8 bits
+        assert!(codes.iter().all(|code| code.get_code() < 256));
+
+        let mut encoder: LzwEncoder<char, MutableTrieDictionary<char>> =
+            LzwEncoder::new(spec, alphabets::generate_ascii());
+        assert!(encoder.encode(tokens("abc").into_iter()).is_err());
+    }
+
+    #[test]
+    fn backend_parity() {
+        let inputs = [
+            String::from(""),
+            String::from("a"),
+            String::from("tobeornottobeortobeornot"),
+            long_input(),
+        ];
+        for spec in [ASCII_SPEC, SMALL_SPEC, SMALL_FROZEN_SPEC] {
+            for input in inputs.iter() {
+                let trie_codes = encode_with::<TrieDictionary<char>>(spec, input);
+                let mutable_trie_codes = encode_with::<MutableTrieDictionary<char>>(spec, input);
+                assert_eq!(trie_codes, mutable_trie_codes);
+            }
+        }
+    }
+
+    #[test]
+    fn export_after_clear() {
+        let mut encoder: LzwEncoder<char, TrieDictionary<char>> =
+            LzwEncoder::new(SMALL_SPEC, alphabets::generate_ascii());
+        let initial = encoder.dictionary().to_json();
+        let codes = encoder.encode(tokens(&long_input()).into_iter()).unwrap();
+        let clear = encoder.control_code(ControlToken::Clear).unwrap();
+        assert!(codes.contains(&clear));
+
+        // One node with a code for every code handed out since the last Clear, plus the root
+        let json = encoder.dictionary().to_json();
+        let nodes = json.matches("\"id\":").count();
+        assert_eq!(nodes, encoder.code_gen.get_current_code() as usize + 1);
+        assert!(nodes > initial.matches("\"id\":").count());
+    }
+}
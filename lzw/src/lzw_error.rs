@@ -0,0 +1,31 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum LzwError {
+    Spec(String),
+    Dictionary(String),
+    Encode(String),
+    Decode(String),
+    Format(String),
+    Worker(String),
+    Io(String),
+    // Stopped by a CancelToken, see progress.rs
+    Cancelled(String),
+}
+
+impl fmt::Display for LzwError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LzwError::Spec(msg) => write!(f, "Spec error: {}", msg),
+            LzwError::Dictionary(msg) => write!(f, "Dictionary error: {}", msg),
+            LzwError::Encode(msg) => write!(f, "Encode error: {}", msg),
+            LzwError::Decode(msg) => write!(f, "Decode error: {}", msg),
+            LzwError::Format(msg) => write!(f, "Format error: {}", msg),
+            LzwError::Worker(msg) => write!(f, "Worker error: {}", msg),
+            LzwError::Io(msg) => write!(f, "IO error: {}", msg),
+            LzwError::Cancelled(msg) => write!(f, "Cancelled: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for LzwError {}
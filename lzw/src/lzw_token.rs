@@ -37,7 +37,7 @@ impl<T: HashableToken> Token<T> {
         }
     }
 
-    fn get_value(&self) -> Option<T> {
+    pub fn get_value(&self) -> Option<T> {
         self.value
     }
 
@@ -1,34 +1,60 @@
-use base64::engine::general_purpose;
-use clap::{Args, Parser, ValueEnum};
-use std::env;
-use std::fs::File;
-use std::io::{Read, Write};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use lzw::archive::{ArchiveReader, ArchiveWriter, EntryKind};
+use lzw::auto_spec;
+use lzw::block_mode::{self, DEFAULT_BLOCK_SIZE};
+use lzw::checkpoint::CheckpointedCompressor;
+use lzw::container::ContainerHeader;
+use lzw::filters::Filters;
+use lzw::lzw_encoder::LzwEncoder;
+use lzw::lzw_error::LzwError;
+use lzw::lzw_token::Token;
+use lzw::mapped_input::InputFile;
+use lzw::mutable_trie_dict::MutableTrieDictionary;
+use lzw::progress::{Monitor, Progress, ProgressObserver, UNMONITORED};
+use lzw::seekable_reader::SeekableReader;
+use lzw::shared_dictionary::SharedDictionary;
+use lzw::stats::{CodeStats, Operation, Report};
+use lzw::trie_dictionary::TrieDictionary;
+use lzw::{alphabets, ArgAlphabet, Coding, Growth, LzwSpec};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Instant;
 use tracing::Level;
 use tracing_subscriber::FmtSubscriber;
-mod alphabets;
-mod lzw_code;
-mod lzw_token;
-mod trie_dictionary;
-use trie_dictionary::TrieDictionary;
-
-#[derive(Debug, Copy, Clone)]
-pub struct LzwSpec {
-    alphabet: alphabets::Alphabet,
-    variable_width: bool,
-    width: u8,
-    min_width: u8,
-    max_width: u8,
-    end_code: bool,
-    clear_code: bool,
-    pack_msb_first: bool,
-    early_change: bool,
-}
 
-// TODO: do fancier exclusive fields? min and max code width only needed for variable width.
 #[derive(Parser)]
 #[command(author, version, about, long_about=None)]
 // #[command(propagate_version = true)]
 struct LzwArgs {
+    #[command(subcommand)]
+    command: LzwCommand,
+}
+
+#[derive(Subcommand)]
+enum LzwCommand {
+    /// Compress a file into an LZW container
+    Compress(CompressArgs),
+    /// Decompress an LZW container, the spec is read from its header
+    Decompress(DecompressArgs),
+    /// Append a file to an LZW container, compressing its last block again
+    Append(AppendArgs),
+    /// Train a shared dictionary for compressing many small messages
+    Train(TrainArgs),
+    /// Write out the encoder's dictionary after compressing a file, as a DOT graph or JSON
+    Dump(DumpArgs),
+    /// Pack files and directory trees into one archive, each file compressed on its own
+    Archive(ArchiveArgs),
+    /// List an archive or extract files from it
+    Extract(ExtractArgs),
+}
+
+// TODO: do fancier exclusive fields? min and max code width only needed for variable width.
+#[derive(Args)]
+struct SpecArgs {
     #[arg(short, long)]
     end_code: bool,
 
@@ -44,38 +70,388 @@ struct LzwArgs {
     #[arg(long)]
     early_change: bool,
 
-    #[arg(default_value = "encoded.txt")]
-    filename: String,
+    /// When out of codes, replace the least recently used entry rather than clearing or freezing
+    #[arg(long)]
+    evict_lru: bool,
+
+    /// What is added to the dictionary on each step
+    #[arg(long, value_enum, default_value_t=Growth::Lzw)]
+    growth: Growth,
+
+    /// How codes are written, range coding is slower but smaller
+    #[arg(long, value_enum, default_value_t=Coding::Packed)]
+    coding: Coding,
+
+    /// Cap the dictionary's memory, in bytes or with a K, M or G suffix. When it is reached the
+    /// dictionary is full, and is reset, frozen or evicts entries as when out of codes
+    #[arg(long, value_parser = parse_size)]
+    memory_budget: Option<usize>,
 
-    #[arg(value_enum, default_value_t=ArgAlphabet::Ascii)]
+    #[arg(long, value_enum, default_value_t=ArgAlphabet::Bytes)]
     alphabet: ArgAlphabet,
 
-    #[arg(default_value_t = 12)]
+    #[arg(long, default_value_t = 12)]
     width: u8,
 
-    #[arg(default_value_t = 8)]
+    #[arg(long, default_value_t = 9)]
     min_width: u8,
 
-    #[arg(default_value_t = 16)] // requires variable-width true
+    #[arg(long, default_value_t = 16)] // requires variable-width true
     max_width: u8,
 }
 
+#[derive(Args)]
+struct CompressArgs {
+    #[command(flatten)]
+    spec: SpecArgs,
+
+    #[arg(long, value_enum, default_value_t=ArgDictionary::Trie)]
+    dictionary: ArgDictionary,
+
+    /// Uncompressed size of each independently compressed block
+    #[arg(long, default_value_t = DEFAULT_BLOCK_SIZE)]
+    block_size: usize,
+
+    /// Defaults to the available parallelism
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// Start every block from a dictionary made by train
+    #[arg(long)]
+    shared_dictionary: Option<String>,
+
+    /// Subtract the byte DELTA bytes back from every byte, use the sample or pixel size
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..))]
+    delta: Option<u8>,
+
+    /// Burrows-Wheeler transform each block
+    #[arg(long)]
+    bwt: bool,
+
+    /// Replace bytes by their position in a list of recently seen bytes
+    #[arg(long)]
+    move_to_front: bool,
+
+    /// Restart every SYNC_INTERVAL bytes, so decompress --recover can skip over damage
+    #[arg(long)]
+    sync_interval: Option<usize>,
+
+    /// Try candidate specs on a sample of the input and use the one that compresses it best, in place of
+    /// the alphabet, width, early change, clear code, evict and growth flags
+    #[arg(long, conflicts_with_all = ["dictionary", "sync_interval"])]
+    auto: bool,
+
+    /// Save progress to this file as it goes, and resume from it if it is there. Blocks are compressed
+    /// one at a time on one thread, into OUTPUT.part until they are all done
+    #[arg(long, conflicts_with_all = ["auto", "sync_interval", "stats", "dictionary"])]
+    checkpoint: Option<String>,
+
+    /// Input bytes between checkpoints, with an optional K, M or G suffix
+    #[arg(long, value_parser = parse_size, default_value = "64M", requires = "checkpoint")]
+    checkpoint_interval: usize,
+
+    /// Report sizes, codes and timing afterwards, as text or JSON (--stats=json)
+    #[arg(long, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "text", conflicts_with = "sync_interval")]
+    stats: Option<StatsFormat>,
+
+    /// Map the input into memory rather than reading it. Only for files that nothing will change
+    /// while they are compressed, and only files of 1 MiB or more are mapped
+    #[arg(long)]
+    mmap: bool,
+
+    /// Don't show a progress bar, which is only shown when stderr is a terminal
+    #[arg(long)]
+    no_progress: bool,
+
+    input: String,
+
+    output: String,
+}
+
+#[derive(Args)]
+struct DecompressArgs {
+    /// Defaults to the available parallelism
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// Uncompressed position to start from, only the blocks needed are decompressed
+    #[arg(long, conflicts_with = "tail")]
+    offset: Option<u64>,
+
+    /// Decompress just the last TAIL bytes
+    #[arg(long)]
+    tail: Option<u64>,
+
+    /// Stop after this many bytes
+    #[arg(long)]
+    length: Option<u64>,
+
+    /// The dictionary the container was compressed with
+    #[arg(long)]
+    shared_dictionary: Option<String>,
+
+    /// Recover what can be read of a damaged container, with zeros in place of what is lost
+    #[arg(long, conflicts_with_all = ["offset", "tail", "length"])]
+    recover: bool,
+
+    /// Report sizes, codes and timing afterwards, as text or JSON (--stats=json)
+    #[arg(long, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "text", conflicts_with_all = ["offset", "tail", "length", "recover"])]
+    stats: Option<StatsFormat>,
+
+    /// Don't show a progress bar, which is only shown when stderr is a terminal, and not for
+    /// --recover or a range
+    #[arg(long)]
+    no_progress: bool,
+
+    input: String,
+
+    output: String,
+}
+
+#[derive(Args)]
+struct AppendArgs {
+    /// Defaults to the available parallelism
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// The dictionary the container was compressed with
+    #[arg(long)]
+    shared_dictionary: Option<String>,
+
+    /// Container to append to, rewritten in place
+    container: String,
+
+    input: String,
+}
+
+#[derive(Args)]
+struct TrainArgs {
+    /// Most entries to keep, each needs a code before the first new entry
+    #[arg(long, default_value_t = 2048)]
+    entries: usize,
+
+    /// Treat every line of the corpus files as a separate message
+    #[arg(long)]
+    lines: bool,
+
+    output: String,
+
+    #[arg(required = true)]
+    corpus: Vec<String>,
+}
+
+#[derive(Args)]
+struct DumpArgs {
+    #[command(flatten)]
+    spec: SpecArgs,
+
+    #[arg(long, value_enum, default_value_t=DumpFormat::Dot)]
+    format: DumpFormat,
+
+    /// Only compress the first LENGTH bytes
+    #[arg(long)]
+    length: Option<usize>,
+
+    input: String,
+
+    output: String,
+}
+
+#[derive(Args)]
+struct ArchiveArgs {
+    #[command(flatten)]
+    spec: SpecArgs,
+
+    /// Uncompressed size of each independently compressed block of a file
+    #[arg(long, default_value_t = DEFAULT_BLOCK_SIZE)]
+    block_size: usize,
+
+    /// Defaults to the available parallelism
+    #[arg(long)]
+    threads: Option<usize>,
+
+    output: String,
+
+    /// Files and directories to add, directories with everything under them
+    #[arg(required = true)]
+    inputs: Vec<String>,
+}
+
+#[derive(Args)]
+struct ExtractArgs {
+    /// List the entries rather than extracting them
+    #[arg(short, long)]
+    list: bool,
+
+    /// Directory to extract into
+    #[arg(short, long, default_value = ".")]
+    output_dir: String,
+
+    /// Defaults to the available parallelism
+    #[arg(long)]
+    threads: Option<usize>,
+
+    input: String,
+
+    /// Only these entries, a directory takes everything under it. Defaults to all of them
+    paths: Vec<String>,
+}
+
 #[derive(Debug, Copy, Clone, ValueEnum)]
-pub enum ArgAlphabet {
-    _Test,
-    Ascii,
-    // TODO add more
+pub enum StatsFormat {
+    Text,
+    Json,
 }
 
-fn main() {
-    // env::set_var("RUST_BACKTRACE", "1");
+#[derive(Debug, Copy, Clone, ValueEnum)]
+pub enum DumpFormat {
+    Dot,
+    Json,
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+pub enum ArgDictionary {
+    Trie,
+    MutableTrie,
+}
+
+fn main() -> ExitCode {
     let subscriber: FmtSubscriber = FmtSubscriber::builder()
-        .with_max_level(Level::TRACE)
+        .with_max_level(Level::INFO)
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 
     let args = LzwArgs::parse();
-    let spec = LzwSpec {
+    let result = match args.command {
+        LzwCommand::Compress(compress_args) => compress(compress_args),
+        LzwCommand::Decompress(decompress_args) => decompress(decompress_args),
+        LzwCommand::Append(append_args) => append(append_args),
+        LzwCommand::Train(train_args) => train(train_args),
+        LzwCommand::Dump(dump_args) => dump(dump_args),
+        LzwCommand::Archive(archive_args) => archive(archive_args),
+        LzwCommand::Extract(extract_args) => extract(extract_args),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            tracing::error!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// https://planetcalc.com/9069/
+
+fn thread_count(threads: Option<usize>) -> usize {
+    threads.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+}
+
+// A byte count, with an optional binary K, M or G suffix
+fn parse_size(size: &str) -> Result<usize, String> {
+    let (digits, shift) = match size.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&size[..size.len() - 1], 10),
+        Some('M') => (&size[..size.len() - 1], 20),
+        Some('G') => (&size[..size.len() - 1], 30),
+        _ => (size, 0),
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|count| count.checked_mul(1 << shift))
+        .ok_or_else(|| format!("{} is not a size in bytes", size))
+}
+
+fn read_file(filename: &str) -> Result<Vec<u8>, LzwError> {
+    let mut input = Vec::new();
+    File::open(filename)
+        .and_then(|mut f| f.read_to_end(&mut input))
+        .map_err(|e| LzwError::Io(format!("Reading {}: {}", filename, e)))?;
+    Ok(input)
+}
+
+fn write_file(filename: &str, output: &[u8]) -> Result<(), LzwError> {
+    File::create(filename)
+        .and_then(|mut f| f.write_all(output))
+        .map_err(|e| LzwError::Io(format!("Writing {}: {}", filename, e)))
+}
+
+fn read_shared_dictionary(filename: &Option<String>) -> Result<Option<SharedDictionary>, LzwError> {
+    filename
+        .as_deref()
+        .map(|filename| SharedDictionary::read_from(&read_file(filename)?))
+        .transpose()
+}
+
+fn print_stats(format: Option<StatsFormat>, report: Report) -> Result<(), LzwError> {
+    let text = match format {
+        None => return Ok(()),
+        Some(StatsFormat::Text) => report.to_text(),
+        Some(StatsFormat::Json) => report.to_json(),
+    };
+    std::io::stdout()
+        .write_all(text.as_bytes())
+        .map_err(|e| LzwError::Io(format!("Writing stats: {}", e)))
+}
+
+const BAR_WIDTH: usize = 30;
+
+// Redrawn on stderr as each block is done
+struct ProgressBar {
+    drawn: AtomicBool,
+}
+
+impl ProgressBar {
+    // Only on a terminal, so that redirected stderr isn't filled with redraws
+    fn new(no_progress: bool) -> Option<ProgressBar> {
+        (!no_progress && std::io::stderr().is_terminal()).then(|| ProgressBar {
+            drawn: AtomicBool::new(false),
+        })
+    }
+
+    fn monitor(&self) -> Monitor<'_> {
+        Monitor {
+            observer: Some(self),
+            cancel: None,
+        }
+    }
+}
+
+impl ProgressObserver for ProgressBar {
+    fn update(&self, progress: Progress) {
+        let filled = ((progress.fraction() * BAR_WIDTH as f64) as usize).min(BAR_WIDTH);
+        eprint!(
+            "\r[{}{}] {:>3}% {} in, {} out, ratio {:.3}",
+            "#".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            (progress.fraction() * 100.0) as u32,
+            size_text(progress.bytes_in),
+            size_text(progress.bytes_out),
+            progress.ratio()
+        );
+        self.drawn.store(true, Ordering::Relaxed);
+    }
+}
+
+// Ends the bar's line, so drop it before logging anything
+impl Drop for ProgressBar {
+    fn drop(&mut self) {
+        if self.drawn.load(Ordering::Relaxed) {
+            eprintln!();
+        }
+    }
+}
+
+fn size_text(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{} B", bytes),
+        1024..0x10_0000 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        0x10_0000..0x4000_0000 => format!("{:.1} MiB", bytes as f64 / 0x10_0000 as f64),
+        _ => format!("{:.1} GiB", bytes as f64 / 0x4000_0000 as f64),
+    }
+}
+
+fn spec_from(args: &SpecArgs) -> LzwSpec {
+    LzwSpec {
         alphabet: alphabets::Alphabet::new(args.alphabet),
         variable_width: args.variable_width,
         width: args.width,
@@ -85,41 +461,554 @@ fn main() {
         clear_code: args.clear_code,
         pack_msb_first: args.pack_msb_first,
         early_change: args.early_change,
+        evict_lru: args.evict_lru,
+        growth: args.growth,
+        coding: args.coding,
+        memory_budget: args.memory_budget,
+    }
+}
+
+fn compress(args: CompressArgs) -> Result<(), LzwError> {
+    let spec = spec_from(&args.spec);
+    let input = if args.mmap {
+        // Asked for with --mmap, which says the file won't change while it is mapped
+        unsafe { InputFile::open(Path::new(&args.input))? }
+    } else {
+        InputFile::read(Path::new(&args.input))?
     };
-    compress(spec);
-    b64_encode_to_file(&args.filename).unwrap();
-    b64_decode_from_file(&args.filename).unwrap();
+    if input.is_mapped() {
+        tracing::debug!("Mapped {}", args.input);
+    }
+    let threads = thread_count(args.threads);
+    let shared = read_shared_dictionary(&args.shared_dictionary)?;
+    if let Some(budget) = spec.memory_budget {
+        tracing::info!(
+            "Memory budget of {} bytes allows {} dictionary entries",
+            budget,
+            spec.max_entries()
+        );
+    }
+    if matches!(args.dictionary, ArgDictionary::MutableTrie) && !spec.alphabet.is_bytes() {
+        return Err(LzwError::Spec(format!(
+            "The {:?} alphabet is only supported by the trie dictionary",
+            spec.alphabet
+        )));
+    }
+
+    let filters = Filters {
+        delta: args.delta.unwrap_or(0),
+        bwt: args.bwt,
+        move_to_front: args.move_to_front,
+    };
+
+    let bar = ProgressBar::new(args.no_progress);
+    let monitor = bar.as_ref().map_or(UNMONITORED, ProgressBar::monitor);
+
+    if let Some(checkpoint) = &args.checkpoint {
+        return compress_checkpointed(
+            &args,
+            checkpoint,
+            spec,
+            filters,
+            shared.as_ref(),
+            &input,
+            bar,
+        );
+    }
+
+    if let Some(sync_interval) = args.sync_interval {
+        if !filters.is_empty()
+            || shared.is_some()
+            || matches!(args.dictionary, ArgDictionary::MutableTrie)
+        {
+            return Err(LzwError::Spec(
+                "Sync points can't be combined with filters, a shared dictionary or the mutable trie"
+                    .to_string(),
+            ));
+        }
+        let output = block_mode::compress_blocks_resilient_with_progress(
+            spec,
+            &input,
+            args.block_size,
+            sync_interval,
+            threads,
+            monitor,
+        )?;
+        drop(bar);
+        tracing::info!("Compressed {} bytes to {}", input.len(), output.len());
+        return write_file(&args.output, &output);
+    }
+
+    let start = Instant::now();
+    let (output, stats) = if args.auto {
+        compress_auto(
+            spec,
+            filters,
+            shared.as_ref(),
+            &input,
+            args.block_size,
+            threads,
+            monitor,
+        )
+    } else {
+        match args.dictionary {
+            ArgDictionary::Trie => block_mode::compress_blocks_with_progress::<TrieDictionary<u8>>(
+                spec,
+                filters,
+                shared.as_ref(),
+                &input,
+                args.block_size,
+                threads,
+                monitor,
+            ),
+            ArgDictionary::MutableTrie => {
+                block_mode::compress_blocks_with_progress::<MutableTrieDictionary<u8>>(
+                    spec,
+                    filters,
+                    shared.as_ref(),
+                    &input,
+                    args.block_size,
+                    threads,
+                    monitor,
+                )
+            }
+        }
+    }?;
+    let elapsed = start.elapsed();
+    drop(bar);
+    tracing::info!("Compressed {} bytes to {}", input.len(), output.len());
+    if let Some(peak) = stats.peak_memory {
+        match spec.memory_budget {
+            Some(budget) => tracing::info!(
+                "Dictionary used at most {} bytes of the {} byte budget",
+                peak,
+                budget
+            ),
+            None => tracing::info!("Dictionary used at most {} bytes", peak),
+        }
+    }
+    write_file(&args.output, &output)?;
+    print_stats(
+        args.stats,
+        Report {
+            operation: Operation::Compress,
+            input_bytes: input.len() as u64,
+            output_bytes: output.len() as u64,
+            blocks: input.len().div_ceil(args.block_size) as u64,
+            elapsed,
+            codes: stats,
+            memory_budget: spec.memory_budget.map(|budget| budget as u64),
+        },
+    )
 }
 
-// https://planetcalc.com/9069/
+// Compress one block at a time, saving a checkpoint every so often, so that an interrupted run
+// can carry on where it left off, see checkpoint.rs
+fn compress_checkpointed(
+    args: &CompressArgs,
+    checkpoint_file: &str,
+    spec: LzwSpec,
+    filters: Filters,
+    shared: Option<&SharedDictionary>,
+    input: &[u8],
+    bar: Option<ProgressBar>,
+) -> Result<(), LzwError> {
+    let mut compressor = match fs::read(checkpoint_file) {
+        Ok(checkpoint) => {
+            let compressor = CheckpointedCompressor::resume(
+                &checkpoint,
+                spec,
+                filters,
+                shared,
+                input,
+                args.block_size,
+            )?;
+            tracing::info!(
+                "Resuming from byte {} of {}",
+                compressor.position(),
+                input.len()
+            );
+            compressor
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            CheckpointedCompressor::new(spec, filters, shared, input, args.block_size)?
+        }
+        Err(e) => return Err(LzwError::Io(format!("Reading {}: {}", checkpoint_file, e))),
+    };
 
-fn compress(spec: LzwSpec) {
-    let mut code_gen = lzw_code::CodeGenerator::new(spec);
-    let alphabet = alphabets::generate_ascii(); //TODO generate from the spec
-    let dict = TrieDictionary::new(spec, &mut code_gen, alphabet);
+    let part_file = format!("{}.part", args.output);
+    let part_error = |e: std::io::Error| LzwError::Io(format!("Writing {}: {}", part_file, e));
+    let mut part = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&part_file)
+        .map_err(part_error)?;
+    // Anything written after the checkpoint is made again
+    let written = part.metadata().map_err(part_error)?.len();
+    if written < compressor.compressed_len() {
+        return Err(LzwError::Io(format!(
+            "{} has {} bytes, but the checkpoint needs {}",
+            part_file,
+            written,
+            compressor.compressed_len()
+        )));
+    }
+    part.set_len(compressor.compressed_len())
+        .and_then(|_| part.seek(SeekFrom::End(0)))
+        .map_err(part_error)?;
 
-    let test_source: Vec<char> = "tobeornottobetobeornottobe".chars().collect();
-    // Repeatedly read, evaluate from input, Emit to output
+    let temporary = format!("{}.tmp", checkpoint_file);
+    let show_progress = |compressor: &CheckpointedCompressor| {
+        if let Some(bar) = &bar {
+            bar.update(Progress {
+                operation: Operation::Compress,
+                bytes_in: compressor.position(),
+                bytes_out: compressor.compressed_len(),
+                total_in: input.len() as u64,
+            });
+        }
+    };
+    show_progress(&compressor);
+    while !compressor.is_finished() {
+        let mut data = Vec::new();
+        compressor.compress_some(args.checkpoint_interval, &mut data)?;
+        part.write_all(&data)
+            .and_then(|_| part.sync_data())
+            .map_err(part_error)?;
+        // Written aside and renamed over the last one, so there is always a whole checkpoint
+        File::create(&temporary)
+            .and_then(|mut f| {
+                f.write_all(&compressor.checkpoint())?;
+                f.sync_all()
+            })
+            .and_then(|_| fs::rename(&temporary, checkpoint_file))
+            .map_err(|e| LzwError::Io(format!("Writing {}: {}", checkpoint_file, e)))?;
+        tracing::debug!(
+            "Checkpoint at byte {} of {}",
+            compressor.position(),
+            input.len()
+        );
+        show_progress(&compressor);
+    }
+    drop(bar);
+
+    let mut header = Vec::new();
+    compressor.header().write_to(&mut header);
+    File::create(&args.output)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            writer.write_all(&header)?;
+            part.seek(SeekFrom::Start(0))?;
+            std::io::copy(&mut part, &mut writer)?;
+            writer.flush()
+        })
+        .map_err(|e| LzwError::Io(format!("Writing {}: {}", args.output, e)))?;
+    fs::remove_file(&part_file)
+        .and_then(|_| fs::remove_file(checkpoint_file))
+        .map_err(|e| LzwError::Io(format!("Removing {}: {}", checkpoint_file, e)))?;
+    tracing::info!(
+        "Compressed {} bytes to {}",
+        input.len(),
+        header.len() as u64 + compressor.compressed_len()
+    );
+    Ok(())
 }
 
-fn decompress() {}
+// Compress with the spec that did best on a sample, falling back to the next best if the whole
+// input doesn't fit it, see auto_spec.rs
+fn compress_auto(
+    base: LzwSpec,
+    filters: Filters,
+    shared: Option<&SharedDictionary>,
+    input: &[u8],
+    block_size: usize,
+    threads: usize,
+    monitor: Monitor,
+) -> Result<(Vec<u8>, CodeStats), LzwError> {
+    let ranked = auto_spec::rank_specs::<TrieDictionary<u8>>(
+        base, filters, shared, input, block_size, threads,
+    )?;
+    let mut last_error = None;
+    for candidate in ranked {
+        let spec = candidate.spec;
+        match block_mode::compress_blocks_with_progress::<TrieDictionary<u8>>(
+            spec, filters, shared, input, block_size, threads, monitor,
+        ) {
+            Ok(result) => {
+                tracing::info!(
+                    "Picked {:?} alphabet, {}..={} bit codes, {:?} growth, clear code {}, evict {}, early change {}, with a ratio of {:.3} on a {} byte sample",
+                    spec.alphabet,
+                    spec.min_width,
+                    spec.max_width,
+                    spec.growth,
+                    spec.clear_code,
+                    spec.evict_lru,
+                    spec.early_change,
+                    candidate.ratio(),
+                    candidate.sample_bytes
+                );
+                return Ok(result);
+            }
+            Err(LzwError::Spec(e)) => {
+                tracing::debug!("{:?} doesn't fit the whole input: {}", spec, e);
+                last_error = Some(LzwError::Spec(e));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_error.unwrap_or(LzwError::Spec(
+        "None of the candidate specs could compress the input".to_string(),
+    )))
+}
 
-fn b64_decode_from_file(filename: &str) -> std::io::Result<()> {
-    // File is in b64 encoding
-    let mut f = File::open(filename)?;
-    let mut decoder = base64::read::DecoderReader::new(&mut f, &general_purpose::STANDARD);
+fn decompress(args: DecompressArgs) -> Result<(), LzwError> {
+    if args.offset.is_some() || args.tail.is_some() || args.length.is_some() {
+        return decompress_range(args);
+    }
+    if args.recover {
+        return recover(args);
+    }
+    let input = read_file(&args.input)?;
+    let shared = read_shared_dictionary(&args.shared_dictionary)?;
+    let bar = ProgressBar::new(args.no_progress);
+    let start = Instant::now();
+    let (output, stats): (Vec<u8>, CodeStats) = block_mode::decompress_blocks_with_progress(
+        &input,
+        shared.as_ref(),
+        thread_count(args.threads),
+        bar.as_ref().map_or(UNMONITORED, ProgressBar::monitor),
+    )?;
+    let elapsed = start.elapsed();
+    drop(bar);
+    tracing::info!("Decompressed {} bytes to {}", input.len(), output.len());
+    write_file(&args.output, &output)?;
+    let (header, _) = ContainerHeader::read_from(&input)?;
+    print_stats(
+        args.stats,
+        Report {
+            operation: Operation::Decompress,
+            input_bytes: input.len() as u64,
+            output_bytes: output.len() as u64,
+            blocks: header.blocks.len() as u64,
+            elapsed,
+            codes: stats,
+            memory_budget: header.spec.memory_budget.map(|budget| budget as u64),
+        },
+    )
+}
 
-    let mut result: Vec<u8> = Vec::new();
-    decoder.read_to_end(&mut result).unwrap();
-    let s_result = String::from_utf8(result).expect("Found invalid UTF-8");
-    println!("{}", s_result);
+fn recover(args: DecompressArgs) -> Result<(), LzwError> {
+    let input = read_file(&args.input)?;
+    let shared = read_shared_dictionary(&args.shared_dictionary)?;
+    let (output, lost) =
+        block_mode::recover_blocks(&input, shared.as_ref(), thread_count(args.threads))?;
+    for range in lost.iter() {
+        tracing::warn!(
+            "Lost bytes {}..{} of the output, from bytes {}..{} of {}",
+            range.uncompressed_start,
+            range.uncompressed_end.unwrap_or(range.uncompressed_start),
+            range.compressed.start,
+            range.compressed.end,
+            args.input
+        );
+    }
+    write_file(&args.output, &output)?;
+    if lost.is_empty() {
+        tracing::info!("Decompressed {} bytes to {}", input.len(), output.len());
+        Ok(())
+    } else {
+        let missing: u64 = lost
+            .iter()
+            .map(|range| {
+                range.uncompressed_end.unwrap_or(range.uncompressed_start)
+                    - range.uncompressed_start
+            })
+            .sum();
+        Err(LzwError::Decode(format!(
+            "{} damaged ranges, {} of {} bytes were lost and are zeros in {}",
+            lost.len(),
+            missing,
+            output.len(),
+            args.output
+        )))
+    }
+}
+
+fn decompress_range(args: DecompressArgs) -> Result<(), LzwError> {
+    let file = File::open(&args.input)
+        .map_err(|e| LzwError::Io(format!("Reading {}: {}", args.input, e)))?;
+    let shared = read_shared_dictionary(&args.shared_dictionary)?;
+    let mut reader = SeekableReader::with_dictionary(BufReader::new(file), shared)?;
+    let start = match args.tail {
+        Some(tail) => reader.len().saturating_sub(tail),
+        None => args.offset.unwrap_or(0),
+    };
+    let length = args.length.unwrap_or(u64::MAX);
+
+    let mut output = Vec::new();
+    reader
+        .seek(SeekFrom::Start(start))
+        .and_then(|_| reader.take(length).read_to_end(&mut output))
+        .map_err(|e| LzwError::Io(format!("Reading {}: {}", args.input, e)))?;
+    tracing::info!(
+        "Decompressed {} bytes from offset {} of {}",
+        output.len(),
+        start,
+        args.input
+    );
+    write_file(&args.output, &output)
+}
+
+fn append(args: AppendArgs) -> Result<(), LzwError> {
+    let container = read_file(&args.container)?;
+    let input = read_file(&args.input)?;
+    let shared = read_shared_dictionary(&args.shared_dictionary)?;
+    let output = block_mode::append_blocks::<TrieDictionary<u8>>(
+        &container,
+        shared.as_ref(),
+        &input,
+        thread_count(args.threads),
+    )?;
+    // Written aside and renamed over it, so a failed append leaves the container as it was
+    let temporary = format!("{}.tmp", args.container);
+    write_file(&temporary, &output)?;
+    fs::rename(&temporary, &args.container)
+        .map_err(|e| LzwError::Io(format!("Writing {}: {}", args.container, e)))?;
+    tracing::info!(
+        "Appended {} bytes to {}, now {} bytes",
+        input.len(),
+        args.container,
+        output.len()
+    );
+    Ok(())
+}
+
+fn train(args: TrainArgs) -> Result<(), LzwError> {
+    let files = args
+        .corpus
+        .iter()
+        .map(|filename| read_file(filename))
+        .collect::<Result<Vec<Vec<u8>>, LzwError>>()?;
+    let samples: Vec<&[u8]> = if args.lines {
+        files
+            .iter()
+            .flat_map(|file| file.split_inclusive(|byte| *byte == b'\n'))
+            .collect()
+    } else {
+        files.iter().map(|file| &file[..]).collect()
+    };
+
+    let shared = SharedDictionary::train(&samples, args.entries);
+    let mut output = Vec::new();
+    shared.write_to(&mut output);
+    tracing::info!(
+        "Trained {} entries from {} messages, dictionary {:08x}",
+        shared.len(),
+        samples.len(),
+        shared.id()
+    );
+    write_file(&args.output, &output)
+}
+
+fn dump(args: DumpArgs) -> Result<(), LzwError> {
+    let spec = spec_from(&args.spec);
+    spec.validate()?;
+    if !spec.alphabet.is_bytes() {
+        return Err(LzwError::Spec(
+            "Only byte alphabets can be dumped".to_string(),
+        ));
+    }
+    let input = read_file(&args.input)?;
+    let input = &input[..args.length.unwrap_or(input.len()).min(input.len())];
+
+    let alphabet = alphabets::produce_byte_alphabet(spec.alphabet);
+    let mut encoder: LzwEncoder<u8, TrieDictionary<u8>> = LzwEncoder::new(spec, alphabet);
+    encoder.encode(input.iter().map(|byte| Token::new(*byte)))?;
+    tracing::info!(
+        "Dictionary uses about {} bytes, at most {} bytes before a reset",
+        encoder.dictionary().memory_used(),
+        encoder.peak_memory().unwrap_or(0)
+    );
+    let output = match args.format {
+        DumpFormat::Dot => encoder.dictionary().to_dot(),
+        DumpFormat::Json => encoder.dictionary().to_json(),
+    };
+    write_file(&args.output, output.as_bytes())
+}
+
+fn archive(args: ArchiveArgs) -> Result<(), LzwError> {
+    let file = File::create(&args.output)
+        .map_err(|e| LzwError::Io(format!("Writing {}: {}", args.output, e)))?;
+    let mut writer = ArchiveWriter::new(
+        BufWriter::new(file),
+        spec_from(&args.spec),
+        args.block_size,
+        thread_count(args.threads),
+    )?;
+    for input in args.inputs.iter() {
+        writer.add_tree(Path::new(input))?;
+    }
+    let entries = writer.entries().len();
+    let size: u64 = writer.entries().iter().map(|entry| entry.size).sum();
+    writer.finish()?;
+    tracing::info!(
+        "Archived {} entries, {} bytes, to {}",
+        entries,
+        size,
+        args.output
+    );
     Ok(())
 }
 
-fn b64_encode_to_file(filename: &str) -> std::io::Result<()> {
-    let s = b"thetest";
-    let mut f: File = File::create(filename)?;
-    let mut encoder = base64::write::EncoderWriter::new(&mut f, &general_purpose::STANDARD);
-    encoder.write_all(s)?;
+fn extract(args: ExtractArgs) -> Result<(), LzwError> {
+    let file = File::open(&args.input)
+        .map_err(|e| LzwError::Io(format!("Reading {}: {}", args.input, e)))?;
+    let mut reader = ArchiveReader::new(BufReader::new(file), thread_count(args.threads))?;
+
+    // Each path given picks out that entry and anything under it
+    let mut selected = Vec::new();
+    for path in args.paths.iter() {
+        let Some(wanted) = reader.entry(path).cloned() else {
+            return Err(LzwError::Io(format!("{} is not in {}", path, args.input)));
+        };
+        let prefix = format!("{}/", wanted.path);
+        selected.extend(
+            reader
+                .entries()
+                .iter()
+                .filter(|entry| entry.path == wanted.path || entry.path.starts_with(&prefix))
+                .cloned(),
+        );
+    }
+    if args.paths.is_empty() {
+        selected = reader.entries().to_vec();
+    }
+
+    if args.list {
+        let mut stdout = std::io::stdout().lock();
+        for entry in selected.iter() {
+            let kind = match entry.kind {
+                EntryKind::File => '-',
+                EntryKind::Directory => 'd',
+            };
+            writeln!(
+                stdout,
+                "{}{:04o} {:>12} {:>12} {:08x} {}",
+                kind, entry.mode, entry.size, entry.mtime, entry.checksum, entry.path
+            )
+            .map_err(|e| LzwError::Io(format!("Writing listing: {}", e)))?;
+        }
+        return Ok(());
+    }
+
+    reader.extract_entries(&selected, Path::new(&args.output_dir))?;
+    tracing::info!(
+        "Extracted {} entries from {} to {}",
+        selected.len(),
+        args.input,
+        args.output_dir
+    );
     Ok(())
 }
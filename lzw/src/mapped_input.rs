@@ -0,0 +1,159 @@
+use crate::lzw_error::LzwError;
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::Read;
+use std::ops::Deref;
+use std::path::Path;
+
+/* MAPPED INPUT
+    Large files are compressed straight from a memory map of them rather than being read into
+    memory first. That saves copying them, and the OS pages the input in as the blocks reach it.
+    The compressors take a slice either way, so the output is the same.
+    Files under MMAP_THRESHOLD bytes are read, as mapping them gains nothing, and so are pipes and
+    anything else that isn't a regular file.
+    A mapped file that is changed or truncated while it is compressed gives a corrupt output, or
+    kills the process with SIGBUS, and nothing here can stop another process doing that. So open and
+    map are unsafe, and the command line only maps with --mmap. Files that may change, such as logs
+    still being written to, should be read instead.
+*/
+
+pub const MMAP_THRESHOLD: u64 = 1024 * 1024;
+
+pub enum InputFile {
+    Mapped(Mmap),
+    Read(Vec<u8>),
+}
+
+fn io_error(path: &Path) -> impl Fn(std::io::Error) -> LzwError + '_ {
+    move |e| LzwError::Io(format!("Reading {}: {}", path.display(), e))
+}
+
+impl InputFile {
+    /// Map the file if it is a large enough regular file, otherwise read it.
+    ///
+    /// # Safety
+    /// Nothing may change or truncate the file while the InputFile is alive.
+    pub unsafe fn open(path: &Path) -> Result<InputFile, LzwError> {
+        let file = File::open(path).map_err(io_error(path))?;
+        let metadata = file.metadata().map_err(io_error(path))?;
+        if metadata.is_file() && metadata.len() >= MMAP_THRESHOLD {
+            InputFile::map_file(path, &file)
+        } else {
+            InputFile::read_file(path, file)
+        }
+    }
+
+    /// Map the file whatever its size.
+    ///
+    /// # Safety
+    /// Nothing may change or truncate the file while the InputFile is alive.
+    pub unsafe fn map(path: &Path) -> Result<InputFile, LzwError> {
+        let file = File::open(path).map_err(io_error(path))?;
+        InputFile::map_file(path, &file)
+    }
+
+    pub fn read(path: &Path) -> Result<InputFile, LzwError> {
+        let file = File::open(path).map_err(io_error(path))?;
+        InputFile::read_file(path, file)
+    }
+
+    // The caller promises nothing changes the file while it is mapped, see open and map
+    unsafe fn map_file(path: &Path, file: &File) -> Result<InputFile, LzwError> {
+        let map = Mmap::map(file).map_err(io_error(path))?;
+        // Only a hint, so failing to give it doesn't matter
+        #[cfg(unix)]
+        let _ = map.advise(memmap2::Advice::Sequential);
+        Ok(InputFile::Mapped(map))
+    }
+
+    fn read_file(path: &Path, mut file: File) -> Result<InputFile, LzwError> {
+        let mut input = Vec::new();
+        file.read_to_end(&mut input).map_err(io_error(path))?;
+        Ok(InputFile::Read(input))
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        matches!(self, InputFile::Mapped(_))
+    }
+}
+
+impl Deref for InputFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            InputFile::Mapped(map) => map,
+            InputFile::Read(input) => input,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::alphabets::Alphabet;
+    use crate::block_mode;
+    use crate::trie_dictionary::TrieDictionary;
+    use crate::{Coding, Growth, LzwSpec};
+    use std::fs;
+
+    const SPEC: LzwSpec = LzwSpec {
+        alphabet: Alphabet::Bytes,
+        variable_width: true,
+        width: 12,
+        min_width: 9,
+        max_width: 16,
+        end_code: true,
+        clear_code: true,
+        pack_msb_first: false,
+        early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
+    };
+
+    #[test]
+    fn mapped_or_read() {
+        let scratch =
+            std::env::temp_dir().join(format!("lzw-mapped-input-test-{}", std::process::id()));
+        fs::create_dir_all(&scratch).unwrap();
+
+        let mut state: u32 = 13;
+        let contents: Vec<u8> = (0..MMAP_THRESHOLD as usize * 3 + 5)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                b"mapped pages "[(state >> 16) as usize % 13]
+            })
+            .collect();
+        let large = scratch.join("large");
+        fs::write(&large, &contents).unwrap();
+        let small = scratch.join("small");
+        fs::write(&small, &contents[..1000]).unwrap();
+
+        // Nothing else touches the test's own files
+        let mapped = unsafe { InputFile::open(&large) }.unwrap();
+        assert!(mapped.is_mapped());
+        let read = InputFile::read(&large).unwrap();
+        assert!(!read.is_mapped());
+        assert_eq!(&mapped[..], &contents[..]);
+        assert_eq!(&read[..], &contents[..]);
+        let from_map =
+            block_mode::compress_blocks::<TrieDictionary<u8>>(SPEC, &mapped, 1 << 20, 2).unwrap();
+        let from_read =
+            block_mode::compress_blocks::<TrieDictionary<u8>>(SPEC, &read, 1 << 20, 2).unwrap();
+        assert_eq!(from_map, from_read);
+
+        let small_input = unsafe { InputFile::open(&small) }.unwrap();
+        assert!(!small_input.is_mapped());
+        assert_eq!(&small_input[..], &contents[..1000]);
+        assert!(unsafe { InputFile::map(&small) }.unwrap().is_mapped());
+
+        assert!(matches!(
+            unsafe { InputFile::open(&scratch.join("missing")) },
+            Err(LzwError::Io(_))
+        ));
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+// An LzwDictionary backed by the trie from the mutable-trie crate
+// Both crates then share one tested trie, and the output can be checked against TrieDictionary
+
+use crate::lzw_code::{Code, CodeGenerator};
+use crate::lzw_dictionary::LzwDictionary;
+use crate::lzw_error::LzwError;
+use crate::lzw_token::{ControlToken, HashableToken, Token};
+use crate::{Growth, LzwSpec};
+use mutable_trie::{IterLzwDict, IterTrie, Trie, TrieNode};
+use std::iter::{self, Peekable};
+
+pub struct MutableTrieDictionary<T: HashableToken> {
+    root: TrieNode<Token<T>, Code>,
+    evict_lru: bool,
+    growth: Growth,
+}
+
+impl<T: HashableToken> MutableTrieDictionary<T> {
+    pub fn new(
+        lzw_spec: LzwSpec,
+        code_gen: &mut CodeGenerator,
+        alphabet: Vec<Token<T>>,
+    ) -> MutableTrieDictionary<T> {
+        let mut new_trie = MutableTrieDictionary {
+            root: TrieNode::new(None, None),
+            evict_lru: lzw_spec.evict_lru,
+            growth: lzw_spec.growth,
+        };
+
+        // Same order as TrieDictionary: alphabet, then clear, then end
+        let mut initial = alphabet;
+        if lzw_spec.clear_code {
+            initial.push(Token::new_control(ControlToken::Clear));
+        }
+        if lzw_spec.end_code {
+            initial.push(Token::new_control(ControlToken::End));
+        }
+
+        let kv_pairs = initial
+            .into_iter()
+            .map(|token| match code_gen.get_next_code() {
+                Some(code) => (token, code),
+                None => panic!("Base alphabet too large for starting bit width"),
+            });
+        new_trie
+            .root
+            .populate_initial(kv_pairs)
+            .expect("Failed to populate initial dictionary");
+        new_trie
+    }
+}
+
+impl<T: HashableToken> LzwDictionary<T> for MutableTrieDictionary<T> {
+    fn initialise(
+        lzw_spec: LzwSpec,
+        code_gen: &mut CodeGenerator,
+        alphabet: Vec<Token<T>>,
+    ) -> MutableTrieDictionary<T> {
+        MutableTrieDictionary::new(lzw_spec, code_gen, alphabet)
+    }
+
+    fn lzw_step<I>(
+        &mut self,
+        tokens: &mut Peekable<I>,
+        code_gen: &mut CodeGenerator,
+    ) -> Result<Option<Code>, LzwError>
+    where
+        I: Iterator<Item = Token<T>>,
+    {
+        // mutable_trie has no way to remove a node, or to add a node without a value
+        if self.evict_lru || self.growth != Growth::Lzw {
+            return Err(LzwError::Dictionary(
+                "LRU replacement and LZMW/LZAP growth are only supported by TrieDictionary"
+                    .to_string(),
+            ));
+        }
+        let Some(&first) = tokens.peek() else {
+            return Ok(None);
+        };
+
+        // lzw_insert_iter needs the new value up front, so take it from a copy of the generator
+        // and only commit to it if the new sequence was really inserted
+        let mut peek_gen = *code_gen;
+        let result = match peek_gen.get_next_code() {
+            Some(next_code) => TrieNode::lzw_insert_iter(&mut self.root, tokens, next_code),
+            None => TrieNode::lzw_search_iter(&self.root, tokens),
+        };
+
+        match result {
+            Ok(Some(code)) => {
+                // The iterator is left on the token that was inserted, unless it ran out first
+                if tokens.peek().is_some() {
+                    *code_gen = peek_gen;
+                }
+                Ok(Some(code))
+            }
+            Ok(None) => Err(LzwError::Dictionary(format!(
+                "Token {:?} is not in the alphabet",
+                first
+            ))),
+            Err(e) => Err(LzwError::Dictionary(format!("{:?}", e))),
+        }
+    }
+
+    fn control_code(&self, control_token: ControlToken) -> Option<Code> {
+        TrieNode::search_iter(&self.root, iter::once(Token::new_control(control_token)))
+            .ok()
+            .flatten()
+    }
+
+    fn seed(
+        &mut self,
+        entries: &[Vec<Token<T>>],
+        code_gen: &mut CodeGenerator,
+    ) -> Result<(), LzwError> {
+        for entry in entries.iter() {
+            let search = |tokens: &[Token<T>]| {
+                TrieNode::search_iter(&self.root, tokens.iter().copied())
+                    .ok()
+                    .flatten()
+            };
+            if entry.len() < 2 || search(&entry[..entry.len() - 1]).is_none() {
+                return Err(LzwError::Dictionary(format!(
+                    "Shared dictionary entry {:?} is not built on an earlier entry",
+                    entry
+                )));
+            }
+            if search(entry).is_some() {
+                return Err(LzwError::Dictionary(format!(
+                    "Shared dictionary entry {:?} is already in the dictionary",
+                    entry
+                )));
+            }
+            let Some(code) = code_gen.get_next_code() else {
+                return Err(LzwError::Dictionary(format!(
+                    "Shared dictionary of {} entries does not fit in the code space",
+                    entries.len()
+                )));
+            };
+            TrieNode::insert_iter(&mut self.root, entry.iter().copied(), code)
+                .map_err(|e| LzwError::Dictionary(format!("{:?}", e)))?;
+        }
+        Ok(())
+    }
+}
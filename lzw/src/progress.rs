@@ -0,0 +1,306 @@
+use crate::lzw_error::LzwError;
+use crate::stats::Operation;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/* PROGRESS
+    The block mode functions that take a Monitor report to its observer as each block is done, and
+    stop when its cancel token is cancelled. Blocks are never stopped part way, so a cancelled run
+    carries on until every thread finishes the block it is on, then fails with LzwError::Cancelled.
+    Observers are called from the worker threads, but only one at a time, and the counts they are
+    given only ever go up. An observer that panics fails the run it panicked in, as a block would,
+    but nothing is left locked, so the counts stay usable. The first call, with nothing done, is made before any block is started.
+    Bytes in and out count whole blocks, so the container's header and index are left out.
+    A CancelToken can be cloned and cancelled from anywhere, such as a GUI's cancel button.
+    The stream types report after each piece instead, see stream.rs, and the async adapters and the
+    C streams hold an OwnedMonitor for them, as they outlive the call that sets them up.
+*/
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Progress {
+    pub operation: Operation,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub total_in: u64,
+}
+
+impl Progress {
+    // Uncompressed over compressed size so far, whichever way the run is going, as in stats.rs
+    pub fn ratio(&self) -> f64 {
+        let (uncompressed, compressed) = match self.operation {
+            Operation::Compress => (self.bytes_in, self.bytes_out),
+            Operation::Decompress => (self.bytes_out, self.bytes_in),
+        };
+        uncompressed as f64 / compressed.max(1) as f64
+    }
+
+    // How much of the input is done, from 0 to 1
+    pub fn fraction(&self) -> f64 {
+        if self.total_in == 0 {
+            return 1.0;
+        }
+        self.bytes_in as f64 / self.total_in as f64
+    }
+}
+
+pub trait ProgressObserver: Sync {
+    fn update(&self, progress: Progress);
+}
+
+impl<F: Fn(Progress) + Sync> ProgressObserver for F {
+    fn update(&self, progress: Progress) {
+        self(progress)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+pub struct Monitor<'a> {
+    pub observer: Option<&'a dyn ProgressObserver>,
+    pub cancel: Option<&'a CancelToken>,
+}
+
+pub const UNMONITORED: Monitor<'static> = Monitor {
+    observer: None,
+    cancel: None,
+};
+
+#[derive(Clone, Default)]
+pub struct OwnedMonitor {
+    pub observer: Option<Arc<dyn ProgressObserver + Send>>,
+    pub cancel: Option<CancelToken>,
+}
+
+impl OwnedMonitor {
+    pub fn monitor(&self) -> Monitor<'_> {
+        Monitor {
+            observer: self.observer.as_deref().map(|observer| observer as _),
+            cancel: self.cancel.as_ref(),
+        }
+    }
+}
+
+// Counts the blocks of one run as they are done, shared by its worker threads
+pub(crate) struct Tracker<'a> {
+    monitor: Monitor<'a>,
+    done: Mutex<Progress>,
+    // Held while the observer runs, so that calls are one at a time and see the counts in order
+    calls: Mutex<()>,
+}
+
+// A panic while a lock was held poisons it, but only ever between whole updates
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl<'a> Tracker<'a> {
+    pub(crate) fn new(monitor: Monitor<'a>, operation: Operation, total_in: usize) -> Tracker<'a> {
+        let start = Progress {
+            operation,
+            bytes_in: 0,
+            bytes_out: 0,
+            total_in: total_in as u64,
+        };
+        if let Some(observer) = monitor.observer {
+            observer.update(start);
+        }
+        Tracker {
+            monitor,
+            done: Mutex::new(start),
+            calls: Mutex::new(()),
+        }
+    }
+
+    // Called before starting each block
+    pub(crate) fn check(&self) -> Result<(), LzwError> {
+        match self.monitor.cancel {
+            Some(cancel) if cancel.is_cancelled() => {
+                let done = *lock(&self.done);
+                Err(LzwError::Cancelled(format!(
+                    "Stopped after {} of {} bytes",
+                    done.bytes_in, done.total_in
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn add(&self, bytes_in: usize, bytes_out: usize) {
+        // Taken before counting, so a later count can't be reported first
+        let _call = lock(&self.calls);
+        let progress = {
+            let mut done = lock(&self.done);
+            done.bytes_in += bytes_in as u64;
+            done.bytes_out += bytes_out as u64;
+            *done
+        };
+        if let Some(observer) = self.monitor.observer {
+            observer.update(progress);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::alphabets::Alphabet;
+    use crate::block_mode;
+    use crate::filters::NO_FILTERS;
+    use crate::trie_dictionary::TrieDictionary;
+    use crate::{Coding, Growth, LzwSpec};
+
+    const SPEC: LzwSpec = LzwSpec {
+        alphabet: Alphabet::Bytes,
+        variable_width: true,
+        width: 12,
+        min_width: 9,
+        max_width: 12,
+        end_code: true,
+        clear_code: true,
+        pack_msb_first: false,
+        early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
+    };
+
+    fn sample_input() -> Vec<u8> {
+        let mut state: u32 = 17;
+        (0..100_000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                b"progress bar "[(state >> 16) as usize % 13]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_every_block() {
+        let input = sample_input();
+        let seen = Mutex::new(Vec::new());
+        let observer = |progress: Progress| seen.lock().unwrap().push(progress);
+        let monitor = Monitor {
+            observer: Some(&observer),
+            cancel: None,
+        };
+        let (compressed, _) = block_mode::compress_blocks_with_progress::<TrieDictionary<u8>>(
+            SPEC, NO_FILTERS, None, &input, 10_000, 3, monitor,
+        )
+        .unwrap();
+        let (output, _) =
+            block_mode::decompress_blocks_with_progress(&compressed, None, 4, monitor).unwrap();
+        assert_eq!(output, input);
+
+        let seen = seen.into_inner().unwrap();
+        // A start and 10 blocks each way
+        assert_eq!(seen.len(), 22);
+        let (compressing, decompressing) = seen.split_at(11);
+        for run in [compressing, decompressing] {
+            assert_eq!((run[0].bytes_in, run[0].bytes_out), (0, 0));
+            assert!(run
+                .windows(2)
+                .all(|pair| pair[0].bytes_in < pair[1].bytes_in
+                    && pair[0].bytes_out < pair[1].bytes_out));
+            let last = run[10];
+            assert_eq!(last.bytes_in, last.total_in);
+            assert_eq!(last.fraction(), 1.0);
+        }
+        assert_eq!(compressing[10].bytes_in, input.len() as u64);
+        assert_eq!(decompressing[10].bytes_out, input.len() as u64);
+        assert_eq!(compressing[10].ratio(), decompressing[10].ratio());
+        assert!(compressing[10].ratio() > 1.0);
+    }
+
+    #[test]
+    fn observer_panics() {
+        let seen = Mutex::new(Vec::new());
+        let observer = |progress: Progress| {
+            seen.lock().unwrap().push(progress.bytes_in);
+            if progress.bytes_in == 10 {
+                panic!("Observer failed");
+            }
+        };
+        let cancel = CancelToken::new();
+        let tracker = Tracker::new(
+            Monitor {
+                observer: Some(&observer),
+                cancel: Some(&cancel),
+            },
+            Operation::Compress,
+            30,
+        );
+        let panicked =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| tracker.add(10, 5)));
+        assert!(panicked.is_err());
+
+        // Still counting and reporting, and cancelling reads the counts
+        tracker.add(20, 10);
+        cancel.cancel();
+        let Err(LzwError::Cancelled(message)) = tracker.check() else {
+            panic!("Expected a cancellation");
+        };
+        assert_eq!(message, "Stopped after 30 of 30 bytes");
+        assert_eq!(*seen.lock().unwrap(), vec![0, 10, 30]);
+    }
+
+    #[test]
+    fn cancelled_between_blocks() {
+        let input = sample_input();
+        let cancel = CancelToken::new();
+        let result = block_mode::compress_blocks_with_progress::<TrieDictionary<u8>>(
+            SPEC,
+            NO_FILTERS,
+            None,
+            &input,
+            10_000,
+            2,
+            Monitor {
+                observer: None,
+                cancel: Some(&cancel),
+            },
+        );
+        assert!(result.is_ok());
+
+        // Cancelled from the observer, by a clone of the token, once the first block is done
+        let canceller = cancel.clone();
+        let blocks_done = Mutex::new(0);
+        let observer = |progress: Progress| {
+            if progress.bytes_in > 0 {
+                *blocks_done.lock().unwrap() += 1;
+                canceller.cancel();
+            }
+        };
+        let result = block_mode::compress_blocks_resilient_with_progress(
+            SPEC,
+            &input,
+            10_000,
+            1000,
+            1,
+            Monitor {
+                observer: Some(&observer),
+                cancel: Some(&cancel),
+            },
+        );
+        assert!(matches!(result, Err(LzwError::Cancelled(_))));
+        assert!(cancel.is_cancelled());
+        assert_eq!(*blocks_done.lock().unwrap(), 1);
+    }
+}
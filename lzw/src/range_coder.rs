@@ -0,0 +1,395 @@
+use crate::lzw_error::LzwError;
+use crate::MAX_CODE_WIDTH;
+
+/* RANGE CODING
+    Packed codes all take the full code width, but the codes written are far from uniform:
+    the alphabet and the recently added entries come up much more often than the rest.
+    Coding::Range instead codes every code with an adaptive binary range coder, as in LZMA.
+    Each binary decision has a probability, 11 bits out of 2048, that moves a 32nd of the way
+    towards whichever way the decision went, so the coder learns the skew as it goes.
+
+    A code is coded as
+        a continue bit  1 before every code, 0 after the last one, so no end code or count is needed
+        its slot        the number of bits in the code, 0 to 24, with separate probabilities per code width
+        the bits below its leading 1, the top 8 with probabilities per slot and position, the rest at even odds
+    The code width is known to both sides at every step, so it costs nothing to use as context.
+    This is slower than packing bits, and saves more the more skewed the codes are.
+*/
+
+const PROBABILITY_BITS: u32 = 11;
+const PROBABILITY_ONE: u16 = 1 << PROBABILITY_BITS;
+const MOVE_BITS: u32 = 5;
+const TOP: u32 = 1 << 24;
+
+const SLOT_BITS: u32 = 5;
+const MODELED_BITS: u32 = 8;
+
+// Bytes the decoder may read past the end of the input, beyond that it must be truncated or garbage
+const OVERRUN_LIMIT: usize = 4;
+
+pub struct RangeEncoder {
+    low: u64,
+    range: u32,
+    cache: u8,
+    cache_size: u64,
+    output: Vec<u8>,
+}
+
+impl Default for RangeEncoder {
+    fn default() -> Self {
+        RangeEncoder::new()
+    }
+}
+
+impl RangeEncoder {
+    pub fn new() -> RangeEncoder {
+        RangeEncoder {
+            low: 0,
+            range: u32::MAX,
+            cache: 0,
+            cache_size: 1,
+            output: Vec::new(),
+        }
+    }
+
+    // Bytes are held back while a carry could still ripple into them
+    fn shift_low(&mut self) {
+        if (self.low as u32) < 0xff00_0000 || self.low >> 32 != 0 {
+            let carry = (self.low >> 32) as u8;
+            let mut byte = self.cache;
+            loop {
+                self.output.push(byte.wrapping_add(carry));
+                byte = 0xff;
+                self.cache_size -= 1;
+                if self.cache_size == 0 {
+                    break;
+                }
+            }
+            self.cache = (self.low >> 24) as u8;
+        }
+        self.cache_size += 1;
+        self.low = (self.low & 0x00ff_ffff) << 8;
+    }
+
+    fn normalize(&mut self) {
+        while self.range < TOP {
+            self.range <<= 8;
+            self.shift_low();
+        }
+    }
+
+    pub fn encode_bit(&mut self, probability: &mut u16, bit: bool) {
+        let bound = (self.range >> PROBABILITY_BITS) * *probability as u32;
+        if bit {
+            self.low += bound as u64;
+            self.range -= bound;
+            *probability -= *probability >> MOVE_BITS;
+        } else {
+            self.range = bound;
+            *probability += (PROBABILITY_ONE - *probability) >> MOVE_BITS;
+        }
+        self.normalize();
+    }
+
+    // Bits at even odds, most significant first
+    pub fn encode_direct(&mut self, value: u32, bits: u32) {
+        for i in (0..bits).rev() {
+            self.range >>= 1;
+            if (value >> i) & 1 != 0 {
+                self.low += self.range as u64;
+            }
+            self.normalize();
+        }
+    }
+
+    // The bits of value, most significant first, each with the probability for the bits before it
+    pub fn encode_tree(&mut self, probabilities: &mut [u16], value: u32, bits: u32) {
+        let mut node = 1;
+        for i in (0..bits).rev() {
+            let bit = (value >> i) & 1 != 0;
+            self.encode_bit(&mut probabilities[node], bit);
+            node = (node << 1) | bit as usize;
+        }
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        for _ in 0..5 {
+            self.shift_low();
+        }
+        self.output
+    }
+}
+
+pub struct RangeDecoder<'a> {
+    input: &'a [u8],
+    position: usize,
+    range: u32,
+    code: u32,
+}
+
+impl<'a> RangeDecoder<'a> {
+    pub fn new(input: &'a [u8]) -> RangeDecoder<'a> {
+        let mut decoder = RangeDecoder {
+            input,
+            position: 0,
+            range: u32::MAX,
+            code: 0,
+        };
+        for _ in 0..5 {
+            decoder.code = (decoder.code << 8) | decoder.next_byte() as u32;
+        }
+        decoder
+    }
+
+    // Zeros past the end, so a truncated stream is caught by overrun rather than a panic
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.input.get(self.position).copied().unwrap_or(0);
+        self.position += 1;
+        byte
+    }
+
+    // Whether bytes past the end have been read, which is fine until the last decision
+    pub fn past_end(&self) -> bool {
+        self.position > self.input.len()
+    }
+
+    pub fn overrun(&self) -> bool {
+        self.position > self.input.len() + OVERRUN_LIMIT
+    }
+
+    fn normalize(&mut self) {
+        while self.range < TOP {
+            self.range <<= 8;
+            self.code = (self.code << 8) | self.next_byte() as u32;
+        }
+    }
+
+    pub fn decode_bit(&mut self, probability: &mut u16) -> bool {
+        let bound = (self.range >> PROBABILITY_BITS) * *probability as u32;
+        let bit = if self.code < bound {
+            self.range = bound;
+            *probability += (PROBABILITY_ONE - *probability) >> MOVE_BITS;
+            false
+        } else {
+            self.code -= bound;
+            self.range -= bound;
+            *probability -= *probability >> MOVE_BITS;
+            true
+        };
+        self.normalize();
+        bit
+    }
+
+    pub fn decode_direct(&mut self, bits: u32) -> u32 {
+        let mut value = 0;
+        for _ in 0..bits {
+            self.range >>= 1;
+            let bit = self.code >= self.range;
+            if bit {
+                self.code -= self.range;
+            }
+            value = (value << 1) | bit as u32;
+            self.normalize();
+        }
+        value
+    }
+
+    pub fn decode_tree(&mut self, probabilities: &mut [u16], bits: u32) -> u32 {
+        let mut node = 1;
+        for _ in 0..bits {
+            let bit = self.decode_bit(&mut probabilities[node]);
+            node = (node << 1) | bit as usize;
+        }
+        (node - (1 << bits)) as u32
+    }
+}
+
+// The adaptive probabilities for coding LZW codes, the same on both sides
+struct CodeModel {
+    more: u16,
+    // Per code width
+    slots: Vec<[u16; 1 << SLOT_BITS]>,
+    // Per slot
+    low_bits: Vec<[u16; 1 << MODELED_BITS]>,
+}
+
+impl CodeModel {
+    fn new() -> CodeModel {
+        let half = PROBABILITY_ONE / 2;
+        CodeModel {
+            more: half,
+            slots: vec![[half; 1 << SLOT_BITS]; MAX_CODE_WIDTH as usize + 1],
+            low_bits: vec![[half; 1 << MODELED_BITS]; MAX_CODE_WIDTH as usize + 1],
+        }
+    }
+}
+
+// Bits below the leading 1 of codes in slot, split into the modeled top bits and the direct rest
+fn split_bits(slot: u32) -> (u32, u32) {
+    let below = slot.saturating_sub(1);
+    let modeled = below.min(MODELED_BITS);
+    (modeled, below - modeled)
+}
+
+pub struct CodeEncoder {
+    coder: RangeEncoder,
+    model: CodeModel,
+}
+
+impl Default for CodeEncoder {
+    fn default() -> Self {
+        CodeEncoder::new()
+    }
+}
+
+impl CodeEncoder {
+    pub fn new() -> CodeEncoder {
+        CodeEncoder {
+            coder: RangeEncoder::new(),
+            model: CodeModel::new(),
+        }
+    }
+
+    pub fn encode(&mut self, code: u32, width: u8) {
+        self.coder.encode_bit(&mut self.model.more, true);
+        let slot = u32::BITS - code.leading_zeros();
+        self.coder
+            .encode_tree(&mut self.model.slots[width as usize], slot, SLOT_BITS);
+        let (modeled, direct) = split_bits(slot);
+        if modeled > 0 {
+            let below = code & ((1 << (modeled + direct)) - 1);
+            self.coder.encode_tree(
+                &mut self.model.low_bits[slot as usize],
+                below >> direct,
+                modeled,
+            );
+            self.coder
+                .encode_direct(below & ((1 << direct) - 1), direct);
+        }
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.coder.encode_bit(&mut self.model.more, false);
+        self.coder.finish()
+    }
+}
+
+pub struct CodeDecoder<'a> {
+    coder: RangeDecoder<'a>,
+    model: CodeModel,
+}
+
+impl<'a> CodeDecoder<'a> {
+    pub fn new(input: &'a [u8]) -> CodeDecoder<'a> {
+        CodeDecoder {
+            coder: RangeDecoder::new(input),
+            model: CodeModel::new(),
+        }
+    }
+
+    // The next code, which must fit in width bits, or None after the last one
+    pub fn decode(&mut self, width: u8) -> Result<Option<u32>, LzwError> {
+        let more = self.coder.decode_bit(&mut self.model.more);
+        // The encoder flushes every byte the last decision needs
+        if self.coder.overrun() || (!more && self.coder.past_end()) {
+            return Err(LzwError::Decode(
+                "Range coded stream is truncated".to_string(),
+            ));
+        }
+        if !more {
+            return Ok(None);
+        }
+        let slot = self
+            .coder
+            .decode_tree(&mut self.model.slots[width as usize], SLOT_BITS);
+        if slot > width as u32 {
+            return Err(LzwError::Decode(format!(
+                "Code of {} bits is wider than {} bits",
+                slot, width
+            )));
+        }
+        let (modeled, direct) = split_bits(slot);
+        let mut code = if slot == 0 { 0 } else { 1 };
+        if modeled > 0 {
+            let top = self
+                .coder
+                .decode_tree(&mut self.model.low_bits[slot as usize], modeled);
+            code = (code << modeled) | top;
+            code = (code << direct) | self.coder.decode_direct(direct);
+        }
+        Ok(Some(code))
+    }
+
+    // After an End code, check that nothing follows and nothing is missing
+    pub fn finish(mut self) -> Result<(), LzwError> {
+        match self.decode(0)? {
+            None => Ok(()),
+            Some(_) => Err(LzwError::Decode("Codes after the End code".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn codes_round_trip() {
+        let mut state: u32 = 3;
+        let codes: Vec<(u32, u8)> = (0..20000)
+            .map(|i| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                let width = 9 + (i / 2000) as u8;
+                // Mostly small codes, as for the alphabet, with some anywhere in the width
+                let code = if state >> 30 == 0 {
+                    (state >> 8) & ((1 << width) - 1)
+                } else {
+                    (state >> 16) % 40
+                };
+                (code, width)
+            })
+            .chain([(0, 1), (1, 1), (0xff_ffff, 24), (0x80_0000, 24)])
+            .collect();
+
+        let mut encoder = CodeEncoder::new();
+        for (code, width) in codes.iter() {
+            encoder.encode(*code, *width);
+        }
+        let bytes = encoder.finish();
+        // Well under the packed size
+        let packed: usize = codes
+            .iter()
+            .map(|(_, width)| *width as usize)
+            .sum::<usize>()
+            / 8;
+        assert!(bytes.len() * 4 < packed * 3);
+
+        let mut decoder = CodeDecoder::new(&bytes);
+        for (code, width) in codes.iter() {
+            assert_eq!(decoder.decode(*width).unwrap(), Some(*code));
+        }
+        assert_eq!(decoder.decode(9).unwrap(), None);
+    }
+
+    #[test]
+    fn empty_and_truncated() {
+        let bytes = CodeEncoder::new().finish();
+        assert_eq!(CodeDecoder::new(&bytes).decode(12).unwrap(), None);
+
+        let mut encoder = CodeEncoder::new();
+        for code in 0..1000 {
+            encoder.encode(code * 7 % 4096, 12);
+        }
+        let bytes = encoder.finish();
+        for len in [0, 1, bytes.len() / 2, bytes.len() - 1] {
+            let mut decoder = CodeDecoder::new(&bytes[..len]);
+            let result = (0..2000).try_for_each(|_| match decoder.decode(12) {
+                Ok(Some(_)) => Ok(()),
+                Ok(None) => Err(None),
+                Err(e) => Err(Some(e)),
+            });
+            assert!(matches!(result, Err(Some(_))), "{} bytes", len);
+        }
+    }
+}
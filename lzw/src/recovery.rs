@@ -0,0 +1,382 @@
+use crate::alphabets::produce_byte_alphabet;
+use crate::bit_packing::{BitReader, BitWriter};
+use crate::checksum::{crc32, crc32_update};
+use crate::lzw_code::Code;
+use crate::lzw_decoder::LzwDecoder;
+use crate::lzw_encoder::LzwEncoder;
+use crate::lzw_error::LzwError;
+use crate::lzw_token::Token;
+use crate::stats::CodeStats;
+use crate::trie_dictionary::TrieDictionary;
+use crate::{Coding, LzwSpec};
+use std::ops::Range;
+
+/* RECOVERY
+    A damaged LZW stream is normally lost from the first bad code on, as the dictionary goes wrong with it.
+    A resilient stream instead restarts every sync_interval bytes of input, in segments of
+        offset          8 bytes     uncompressed offset of the segment
+        length          4 bytes     uncompressed length
+        checksum        4 bytes     CRC-32 of offset, length and the uncompressed bytes
+        Clear, Clear    the sync marker, at the initial code width
+        codes           from the initial dictionary, ending with End
+        padding         to a whole byte, so the next segment is byte aligned
+    All integers are little endian.
+
+    Segments are decoded one after another. When one fails to decode, or fails its checksum,
+    the decoder scans forward a byte at a time for the next sync marker that starts a good segment,
+    fills the gap in the output with zeros and reports the bytes that were lost. Decoding carries on from there.
+    The checksum covers the header too, so a false match would need a bad header and data that agree.
+    Anyone can compute a checksum though, so segments that would end past max_len, the most the
+    stream could hold, are treated as damage rather than trusted to size the output.
+    Only byte alphabets are supported, with packed codes and both control codes.
+*/
+
+pub const SEGMENT_HEADER_LEN: usize = 16;
+
+// Part of a damaged stream that could not be decoded, the output has zeros in its place
+#[derive(Debug, Clone, PartialEq)]
+pub struct LostRange {
+    pub compressed: Range<u64>,
+    pub uncompressed_start: u64,
+    // None if nothing after it could be recovered
+    pub uncompressed_end: Option<u64>,
+}
+
+pub fn check_spec(spec: LzwSpec) -> Result<(), LzwError> {
+    if !spec.clear_code || !spec.end_code {
+        return Err(LzwError::Spec(
+            "Resilient streams need both the Clear and End codes".to_string(),
+        ));
+    }
+    if spec.coding != Coding::Packed || !spec.alphabet.is_bytes() {
+        return Err(LzwError::Spec(
+            "Resilient streams need packed codes and a byte alphabet".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn segment_checksum(offset: u64, data: &[u8]) -> u32 {
+    let crc = crc32(&offset.to_le_bytes());
+    let crc = crc32_update(crc, &(data.len() as u32).to_le_bytes());
+    crc32_update(crc, data)
+}
+
+// The Clear code as the first code of a segment
+fn sync_code(spec: LzwSpec) -> Result<Code, LzwError> {
+    let encoder: LzwEncoder<u8, TrieDictionary<u8>> =
+        LzwEncoder::new(spec, produce_byte_alphabet(spec.alphabet));
+    encoder.clear_code()
+}
+
+pub fn compress_resilient(
+    spec: LzwSpec,
+    input: &[u8],
+    sync_interval: usize,
+) -> Result<Vec<u8>, LzwError> {
+    spec.validate()?;
+    check_spec(spec)?;
+    if sync_interval == 0 || sync_interval > u32::MAX as usize {
+        return Err(LzwError::Spec(format!(
+            "Sync interval {} is out of range",
+            sync_interval
+        )));
+    }
+    let alphabet = produce_byte_alphabet(spec.alphabet);
+    let sync = sync_code(spec)?;
+
+    let mut output = Vec::new();
+    for (i, segment) in input.chunks(sync_interval).enumerate() {
+        let offset = (i * sync_interval) as u64;
+        output.extend_from_slice(&offset.to_le_bytes());
+        output.extend_from_slice(&(segment.len() as u32).to_le_bytes());
+        output.extend_from_slice(&segment_checksum(offset, segment).to_le_bytes());
+
+        let mut encoder: LzwEncoder<u8, TrieDictionary<u8>> =
+            LzwEncoder::new(spec, alphabet.clone());
+        let codes = encoder.encode(segment.iter().map(|byte| Token::new(*byte)))?;
+        let mut writer = BitWriter::new(spec.pack_msb_first);
+        for code in [sync, sync].into_iter().chain(codes) {
+            writer.write(code.get_code(), code.get_used_bits());
+        }
+        output.extend(writer.finish());
+    }
+    Ok(output)
+}
+
+struct Segment {
+    offset: u64,
+    data: Vec<u8>,
+    // Bytes of the stream it took up
+    len: usize,
+    stats: CodeStats,
+}
+
+fn read_segment(spec: LzwSpec, sync: Code, input: &[u8]) -> Result<Segment, LzwError> {
+    let header = input
+        .get(..SEGMENT_HEADER_LEN)
+        .ok_or(LzwError::Format("Segment header is truncated".to_string()))?;
+    let offset = u64::from_le_bytes(header[..8].try_into().unwrap());
+    let length = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+    let checksum = u32::from_le_bytes(header[12..].try_into().unwrap());
+    let codes = &input[SEGMENT_HEADER_LEN..];
+
+    // Check for the marker before decoding anything, as most places scanned won't have it
+    let mut reader = BitReader::new(codes, spec.pack_msb_first);
+    for _ in 0..2 {
+        if reader.read(sync.get_used_bits()) != Some(sync.get_code()) {
+            return Err(LzwError::Format("No sync marker".to_string()));
+        }
+    }
+
+    let mut decoder = LzwDecoder::new(spec, produce_byte_alphabet(spec.alphabet));
+    let (tokens, codes_len) = decoder.decompress_until_end(codes)?;
+    let data: Vec<u8> = tokens
+        .iter()
+        .filter_map(|token| token.get_value())
+        .collect();
+    if data.len() != length || segment_checksum(offset, &data) != checksum {
+        return Err(LzwError::Decode(format!(
+            "Segment at offset {} fails its checksum",
+            offset
+        )));
+    }
+    Ok(Segment {
+        offset,
+        data,
+        len: SEGMENT_HEADER_LEN + codes_len,
+        stats: decoder.stats(),
+    })
+}
+
+// Decompress as much of the stream as can be, along with what was lost
+pub fn decompress_resilient(
+    spec: LzwSpec,
+    input: &[u8],
+    max_len: u64,
+) -> Result<(Vec<u8>, Vec<LostRange>), LzwError> {
+    decompress_resilient_with_stats(spec, input, max_len).map(|(output, lost, _)| (output, lost))
+}
+
+// The stats are merged from the segments decoded, see stats.rs, so nothing counts for lost ones
+pub fn decompress_resilient_with_stats(
+    spec: LzwSpec,
+    input: &[u8],
+    max_len: u64,
+) -> Result<(Vec<u8>, Vec<LostRange>, CodeStats), LzwError> {
+    spec.validate()?;
+    check_spec(spec)?;
+    let sync = sync_code(spec)?;
+
+    let mut output = Vec::new();
+    let mut lost = Vec::new();
+    let mut stats = CodeStats::default();
+    let mut position = 0;
+    while position < input.len() {
+        let expected = output.len() as u64;
+        // Doesn't go back over the output, or past the end of it
+        let fits = |segment: &Segment| {
+            segment.offset >= expected
+                && segment
+                    .offset
+                    .checked_add(segment.data.len() as u64)
+                    .is_some_and(|end| end <= max_len)
+        };
+        let found = match read_segment(spec, sync, &input[position..]) {
+            Ok(segment) if fits(&segment) => Some((position, segment)),
+            // Scan for the next good segment
+            _ => (position + 1..input.len()).find_map(|start| {
+                read_segment(spec, sync, &input[start..])
+                    .ok()
+                    .filter(fits)
+                    .map(|segment| (start, segment))
+            }),
+        };
+
+        let Some((start, segment)) = found else {
+            lost.push(LostRange {
+                compressed: position as u64..input.len() as u64,
+                uncompressed_start: expected,
+                uncompressed_end: None,
+            });
+            break;
+        };
+        // Segments can also go missing without damaging the ones either side
+        if start > position || segment.offset > expected {
+            lost.push(LostRange {
+                compressed: position as u64..start as u64,
+                uncompressed_start: expected,
+                uncompressed_end: Some(segment.offset),
+            });
+            output.resize(segment.offset as usize, 0);
+        }
+        output.extend(segment.data);
+        stats.merge(&segment.stats);
+        position = start + segment.len;
+    }
+    Ok((output, lost, stats))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::alphabets::Alphabet;
+    use crate::Growth;
+
+    const SPEC: LzwSpec = LzwSpec {
+        alphabet: Alphabet::Bytes,
+        variable_width: true,
+        width: 12,
+        min_width: 9,
+        max_width: 12,
+        end_code: true,
+        clear_code: true,
+        pack_msb_first: false,
+        early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
+    };
+
+    fn sample_input() -> Vec<u8> {
+        let mut state: u32 = 11;
+        (0..30000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                b"backup of /home/user "[(state >> 16) as usize % 21]
+            })
+            .collect()
+    }
+
+    // Offsets in the stream of each segment, found by walking the headers of an undamaged stream
+    fn segment_starts(spec: LzwSpec, stream: &[u8]) -> Vec<usize> {
+        let sync = sync_code(spec).unwrap();
+        let mut starts = Vec::new();
+        let mut position = 0;
+        while position < stream.len() {
+            starts.push(position);
+            position += read_segment(spec, sync, &stream[position..]).unwrap().len;
+        }
+        starts
+    }
+
+    #[test]
+    fn undamaged_round_trip() {
+        let input = sample_input();
+        for pack_msb_first in [true, false] {
+            let spec = LzwSpec {
+                pack_msb_first,
+                ..SPEC
+            };
+            for (input, sync_interval) in [
+                (&input[..100], 1),
+                (&input[..], 1000),
+                (&input[..], 4096),
+                (&input[..], input.len() * 2),
+            ] {
+                let stream = compress_resilient(spec, input, sync_interval).unwrap();
+                let (output, lost, stats) =
+                    decompress_resilient_with_stats(spec, &stream, input.len() as u64).unwrap();
+                assert_eq!(output, input);
+                assert!(lost.is_empty());
+                // Each segment starts with a sync marker of two Clear codes, and long ones fill up
+                let segments = input.len().div_ceil(sync_interval) as u64;
+                assert!(stats.clears >= 2 * segments);
+                assert!(stats.codes > 3 * segments);
+            }
+        }
+        let (output, lost) = decompress_resilient(SPEC, &[], 0).unwrap();
+        assert!(output.is_empty() && lost.is_empty());
+    }
+
+    #[test]
+    fn recovers_after_damage() {
+        let input = sample_input();
+        let mut stream = compress_resilient(SPEC, &input, 2000).unwrap();
+        let starts = segment_starts(SPEC, &stream);
+        assert_eq!(starts.len(), 15);
+
+        // Damage the middle of the fourth segment
+        let damaged = starts[3] + 100;
+        stream[damaged..damaged + 20].fill(0x55);
+        let (output, lost) = decompress_resilient(SPEC, &stream, input.len() as u64).unwrap();
+        assert_eq!(
+            lost,
+            vec![LostRange {
+                compressed: starts[3] as u64..starts[4] as u64,
+                uncompressed_start: 6000,
+                uncompressed_end: Some(8000),
+            }]
+        );
+        assert_eq!(output.len(), input.len());
+        assert_eq!(output[..6000], input[..6000]);
+        assert!(output[6000..8000].iter().all(|byte| *byte == 0));
+        assert_eq!(output[8000..], input[8000..]);
+    }
+
+    #[test]
+    fn missing_and_truncated_segments() {
+        let input = sample_input();
+        let stream = compress_resilient(SPEC, &input, 5000).unwrap();
+        let starts = segment_starts(SPEC, &stream);
+
+        // Second segment cut out cleanly
+        let mut cut = stream[..starts[1]].to_vec();
+        cut.extend_from_slice(&stream[starts[2]..]);
+        let (output, lost) = decompress_resilient(SPEC, &cut, input.len() as u64).unwrap();
+        assert_eq!(lost.len(), 1);
+        assert_eq!(lost[0].compressed, starts[1] as u64..starts[1] as u64);
+        assert_eq!(lost[0].uncompressed_end, Some(10000));
+        assert_eq!(output[10000..], input[10000..]);
+
+        // Stream cut short in the last segment
+        let (output, lost) =
+            decompress_resilient(SPEC, &stream[..stream.len() - 10], input.len() as u64).unwrap();
+        assert_eq!(output, input[..25000]);
+        assert_eq!(lost[0].uncompressed_start, 25000);
+        assert_eq!(lost[0].uncompressed_end, None);
+    }
+
+    #[test]
+    fn segment_offsets_out_of_range() {
+        let input = sample_input();
+        let mut stream = compress_resilient(SPEC, &input, 5000).unwrap();
+        let starts = segment_starts(SPEC, &stream);
+
+        // The second segment claims to be far along, with a checksum to match
+        let header = starts[1];
+        for offset in [1 << 40, u64::MAX] {
+            let checksum = segment_checksum(offset, &input[5000..10000]);
+            stream[header..header + 8].copy_from_slice(&offset.to_le_bytes());
+            stream[header + 12..header + 16].copy_from_slice(&checksum.to_le_bytes());
+            let (output, lost) = decompress_resilient(SPEC, &stream, input.len() as u64).unwrap();
+            assert_eq!(output.len(), input.len());
+            assert_eq!(lost.len(), 1);
+            assert_eq!(lost[0].uncompressed_start, 5000);
+            assert_eq!(lost[0].uncompressed_end, Some(10000));
+        }
+
+        // Or the stream holds more than it should
+        let stream = compress_resilient(SPEC, &input, 5000).unwrap();
+        let (output, lost) = decompress_resilient(SPEC, &stream, 12000).unwrap();
+        assert_eq!(output, input[..10000]);
+        assert_eq!(lost[0].uncompressed_end, None);
+    }
+
+    #[test]
+    fn needs_control_codes() {
+        let no_clear = LzwSpec {
+            clear_code: false,
+            ..SPEC
+        };
+        assert!(compress_resilient(no_clear, b"abc", 10).is_err());
+        let range = LzwSpec {
+            coding: Coding::Range,
+            ..SPEC
+        };
+        assert!(compress_resilient(range, b"abc", 10).is_err());
+        assert!(compress_resilient(SPEC, b"abc", 0).is_err());
+    }
+}
@@ -0,0 +1,341 @@
+use crate::lzw_decoder::LzwDecoder;
+use crate::lzw_encoder::LzwEncoder;
+use crate::lzw_error::LzwError;
+use crate::lzw_token::{HashableToken, Token};
+use crate::stats::CodeStats;
+use crate::trie_dictionary::TrieDictionary;
+use crate::LzwSpec;
+
+/* FIXED WIDTH SAMPLES
+    Audio samples and sensor readings repeat as whole 16 or 32 bit values, so LZW runs over the values
+    rather than their bytes. The input is read as little or big endian samples, as the alphabet says,
+    and written back the same way, so it must be a whole number of samples long.
+
+    Every u16 is in the alphabet, so u16 samples need codes of at least 17 bits.
+    There are far too many u32 values for that, so the alphabet is just the values the input uses,
+    in ascending order, written in front of the codes
+        value_count     4 bytes
+        values          4 bytes each, little endian
+    Samples always use TrieDictionary.
+*/
+
+pub trait Sample: HashableToken + Ord {
+    const BYTES: usize;
+
+    // Every value, if there are few enough for them all to be in the alphabet
+    fn every_value() -> Option<Vec<Self>>;
+
+    fn from_bytes(bytes: &[u8], big_endian: bool) -> Self;
+
+    fn write_bytes(self, big_endian: bool, output: &mut Vec<u8>);
+}
+
+impl Sample for u16 {
+    const BYTES: usize = 2;
+
+    fn every_value() -> Option<Vec<u16>> {
+        Some((0..=u16::MAX).collect())
+    }
+
+    fn from_bytes(bytes: &[u8], big_endian: bool) -> u16 {
+        let bytes = bytes.try_into().unwrap();
+        if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    }
+
+    fn write_bytes(self, big_endian: bool, output: &mut Vec<u8>) {
+        if big_endian {
+            output.extend_from_slice(&self.to_be_bytes());
+        } else {
+            output.extend_from_slice(&self.to_le_bytes());
+        }
+    }
+}
+
+impl Sample for u32 {
+    const BYTES: usize = 4;
+
+    fn every_value() -> Option<Vec<u32>> {
+        None
+    }
+
+    fn from_bytes(bytes: &[u8], big_endian: bool) -> u32 {
+        let bytes = bytes.try_into().unwrap();
+        if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    fn write_bytes(self, big_endian: bool, output: &mut Vec<u8>) {
+        if big_endian {
+            output.extend_from_slice(&self.to_be_bytes());
+        } else {
+            output.extend_from_slice(&self.to_le_bytes());
+        }
+    }
+}
+
+pub fn read_samples<S: Sample>(input: &[u8], big_endian: bool) -> Result<Vec<S>, LzwError> {
+    if !input.len().is_multiple_of(S::BYTES) {
+        return Err(LzwError::Encode(format!(
+            "Input of {} bytes is not a whole number of {} byte samples",
+            input.len(),
+            S::BYTES
+        )));
+    }
+    Ok(input
+        .chunks(S::BYTES)
+        .map(|bytes| S::from_bytes(bytes, big_endian))
+        .collect())
+}
+
+pub fn write_samples<S: Sample>(samples: &[S], big_endian: bool) -> Vec<u8> {
+    let mut output = Vec::with_capacity(samples.len() * S::BYTES);
+    for sample in samples.iter() {
+        sample.write_bytes(big_endian, &mut output);
+    }
+    output
+}
+
+pub fn compress_samples<S: Sample>(spec: LzwSpec, samples: &[S]) -> Result<Vec<u8>, LzwError> {
+    compress_samples_with_stats(spec, samples).map(|(output, _)| output)
+}
+
+pub fn compress_samples_with_stats<S: Sample>(
+    spec: LzwSpec,
+    samples: &[S],
+) -> Result<(Vec<u8>, CodeStats), LzwError> {
+    let mut output = Vec::new();
+    let values = match S::every_value() {
+        Some(values) => values,
+        None => {
+            let mut values = samples.to_vec();
+            values.sort_unstable();
+            values.dedup();
+            output.extend_from_slice(&(values.len() as u32).to_le_bytes());
+            for value in values.iter() {
+                value.write_bytes(false, &mut output);
+            }
+            values
+        }
+    };
+    spec.check_alphabet_size(values.len())?;
+
+    let alphabet = values.into_iter().map(Token::new).collect();
+    let mut encoder: LzwEncoder<S, TrieDictionary<S>> = LzwEncoder::new(spec, alphabet);
+    output.extend(encoder.compress(samples.iter().map(|sample| Token::new(*sample)))?);
+    Ok((output, encoder.stats()))
+}
+
+pub fn decompress_samples<S: Sample>(spec: LzwSpec, input: &[u8]) -> Result<Vec<S>, LzwError> {
+    decompress_samples_with_stats(spec, input).map(|(samples, _)| samples)
+}
+
+pub fn decompress_samples_with_stats<S: Sample>(
+    spec: LzwSpec,
+    input: &[u8],
+) -> Result<(Vec<S>, CodeStats), LzwError> {
+    let (values, codes) = match S::every_value() {
+        Some(values) => (values, input),
+        None => read_values(input)?,
+    };
+    spec.check_alphabet_size(values.len())?;
+
+    let alphabet = values.into_iter().map(Token::new).collect();
+    let mut decoder: LzwDecoder<S> = LzwDecoder::new(spec, alphabet);
+    let tokens = decoder.decompress(codes)?;
+    let samples = tokens
+        .iter()
+        .filter_map(|token| token.get_value())
+        .collect();
+    Ok((samples, decoder.stats()))
+}
+
+// The stored alphabet, and the codes that follow it
+fn read_values<S: Sample>(input: &[u8]) -> Result<(Vec<S>, &[u8]), LzwError> {
+    let truncated = || LzwError::Format("Sample values are truncated".to_string());
+    let (count, rest) = input.split_at_checked(4).ok_or_else(truncated)?;
+    let count = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+    let (values, codes) = rest
+        .split_at_checked(count.saturating_mul(S::BYTES))
+        .ok_or_else(truncated)?;
+    let values = read_samples::<S>(values, false)?;
+    // Strictly ascending, so no value is in the alphabet twice
+    if values.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err(LzwError::Format(
+            "Sample values are not in ascending order".to_string(),
+        ));
+    }
+    Ok((values, codes))
+}
+
+// The byte level versions, reading and writing samples as the spec's alphabet says
+pub fn compress(spec: LzwSpec, input: &[u8]) -> Result<Vec<u8>, LzwError> {
+    compress_with_stats(spec, input).map(|(output, _)| output)
+}
+
+pub fn compress_with_stats(spec: LzwSpec, input: &[u8]) -> Result<(Vec<u8>, CodeStats), LzwError> {
+    let big_endian = spec.alphabet.big_endian();
+    match spec.alphabet.sample_bytes() {
+        2 => compress_samples_with_stats(spec, &read_samples::<u16>(input, big_endian)?),
+        _ => compress_samples_with_stats(spec, &read_samples::<u32>(input, big_endian)?),
+    }
+}
+
+pub fn decompress(spec: LzwSpec, input: &[u8]) -> Result<Vec<u8>, LzwError> {
+    decompress_with_stats(spec, input).map(|(output, _)| output)
+}
+
+pub fn decompress_with_stats(
+    spec: LzwSpec,
+    input: &[u8],
+) -> Result<(Vec<u8>, CodeStats), LzwError> {
+    let big_endian = spec.alphabet.big_endian();
+    match spec.alphabet.sample_bytes() {
+        2 => {
+            let (samples, stats) = decompress_samples_with_stats::<u16>(spec, input)?;
+            Ok((write_samples(&samples, big_endian), stats))
+        }
+        _ => {
+            let (samples, stats) = decompress_samples_with_stats::<u32>(spec, input)?;
+            Ok((write_samples(&samples, big_endian), stats))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::alphabets::Alphabet;
+    use crate::{Coding, Growth};
+
+    const U16_SPEC: LzwSpec = LzwSpec {
+        alphabet: Alphabet::U16Le,
+        variable_width: true,
+        width: 20,
+        min_width: 17,
+        max_width: 20,
+        end_code: true,
+        clear_code: true,
+        pack_msb_first: true,
+        early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
+    };
+
+    // A repeating waveform with a little noise, like a sampled signal
+    fn signal() -> Vec<u16> {
+        let mut state: u32 = 21;
+        (0..20000u32)
+            .map(|i| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                let wave = [1000u16, 1500, 2000, 1500, 1000, 500, 0, 500][(i % 8) as usize];
+                if state >> 28 == 0 {
+                    wave + 40000
+                } else {
+                    wave
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn endianness() {
+        let input = [0x12, 0x34, 0x56, 0x78];
+        assert_eq!(
+            read_samples::<u16>(&input, false).unwrap(),
+            vec![0x3412, 0x7856]
+        );
+        assert_eq!(
+            read_samples::<u16>(&input, true).unwrap(),
+            vec![0x1234, 0x5678]
+        );
+        assert_eq!(read_samples::<u32>(&input, true).unwrap(), vec![0x12345678]);
+        assert_eq!(
+            write_samples(&read_samples::<u32>(&input, false).unwrap(), false),
+            input
+        );
+        assert!(read_samples::<u32>(&input[..3], false).is_err());
+    }
+
+    #[test]
+    fn u16_round_trip() {
+        let samples = signal();
+        let compressed = compress_samples(U16_SPEC, &samples).unwrap();
+        assert!(compressed.len() < samples.len());
+        assert_eq!(
+            decompress_samples::<u16>(U16_SPEC, &compressed).unwrap(),
+            samples
+        );
+        for samples in [&[][..], &[0], &[u16::MAX, 0, u16::MAX]] {
+            let compressed = compress_samples(U16_SPEC, samples).unwrap();
+            assert_eq!(
+                decompress_samples::<u16>(U16_SPEC, &compressed).unwrap(),
+                samples
+            );
+        }
+    }
+
+    #[test]
+    fn u32_round_trip() {
+        let samples: Vec<u32> = signal()
+            .into_iter()
+            .map(|sample| sample as u32 * 70001)
+            .collect();
+        let spec = LzwSpec {
+            alphabet: Alphabet::U32Be,
+            min_width: 9,
+            ..U16_SPEC
+        };
+        let compressed = compress_samples(spec, &samples).unwrap();
+        assert_eq!(
+            decompress_samples::<u32>(spec, &compressed).unwrap(),
+            samples
+        );
+
+        let bytes = write_samples(&samples, true);
+        let compressed = compress(spec, &bytes).unwrap();
+        assert_eq!(decompress(spec, &compressed).unwrap(), bytes);
+        assert!(compress(spec, &bytes[1..]).is_err());
+    }
+
+    #[test]
+    fn code_widths() {
+        // Every u16, plus the control codes, needs 17 bits
+        let narrow = LzwSpec {
+            max_width: 16,
+            min_width: 9,
+            ..U16_SPEC
+        };
+        assert!(narrow.validate().is_err());
+        assert!(U16_SPEC.validate().is_ok());
+
+        // u32 alphabets are only checked once the values are known
+        let narrow = LzwSpec {
+            alphabet: Alphabet::U32Le,
+            ..narrow
+        };
+        assert!(narrow.validate().is_ok());
+        let few: Vec<u32> = (0..100).map(|i| i * 1_000_000).collect();
+        assert!(compress_samples(narrow, &few).is_ok());
+        let many: Vec<u32> = (0..70000).collect();
+        assert!(compress_samples(narrow, &many).is_err());
+    }
+
+    #[test]
+    fn bad_values() {
+        // Count runs past the end
+        assert!(decompress_samples::<u32>(U16_SPEC, &[5, 0, 0, 0, 1, 0, 0, 0]).is_err());
+        // Not ascending
+        let input = [2, 0, 0, 0, 9, 0, 0, 0, 3, 0, 0, 0];
+        assert!(decompress_samples::<u32>(U16_SPEC, &input).is_err());
+    }
+}
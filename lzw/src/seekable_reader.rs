@@ -0,0 +1,249 @@
+use crate::block_mode;
+use crate::container::ContainerHeader;
+use crate::lzw_error::LzwError;
+use crate::shared_dictionary::SharedDictionary;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/* SEEKABLE READER
+    Reads the decompressed contents of a block mode container through Read and Seek.
+    Only the header is read up front. The block index gives the uncompressed offset of every block,
+    so a seek just moves the position, and a read loads and decompresses the one block holding it.
+    Jumping to the end of a large archive costs a single block, however many come before it.
+*/
+
+pub struct SeekableReader<R: Read + Seek> {
+    source: R,
+    header: ContainerHeader,
+    header_len: u64,
+    shared: Option<SharedDictionary>,
+    position: u64,
+    // The last block decompressed, kept until a read needs a different one
+    current: Option<(usize, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> SeekableReader<R> {
+    pub fn new(source: R) -> Result<SeekableReader<R>, LzwError> {
+        SeekableReader::with_dictionary(source, None)
+    }
+
+    pub fn with_dictionary(
+        mut source: R,
+        shared: Option<SharedDictionary>,
+    ) -> Result<SeekableReader<R>, LzwError> {
+        source
+            .seek(SeekFrom::Start(0))
+            .map_err(|e| LzwError::Io(e.to_string()))?;
+        let (header, header_len) = ContainerHeader::read_from_reader(&mut source)?;
+        header.check_dictionary(shared.as_ref())?;
+        Ok(SeekableReader {
+            source,
+            header,
+            header_len: header_len as u64,
+            shared,
+            position: 0,
+            current: None,
+        })
+    }
+
+    pub fn header(&self) -> &ContainerHeader {
+        &self.header
+    }
+
+    // Uncompressed length of the whole archive
+    pub fn len(&self) -> u64 {
+        self.header.uncompressed_len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn load_block(&mut self, index: usize) -> Result<(), LzwError> {
+        let block = self.header.blocks[index];
+        let start = self
+            .header_len
+            .checked_add(block.compressed_offset)
+            .ok_or_else(|| LzwError::Format(format!("Block {} is out of range", index)))?;
+        // Read rather than allocated up front, so a bad length can't ask for more than the file has
+        let mut compressed = Vec::new();
+        self.source
+            .seek(SeekFrom::Start(start))
+            .and_then(|_| {
+                (&mut self.source)
+                    .take(block.compressed_len as u64)
+                    .read_to_end(&mut compressed)
+            })
+            .map_err(|e| LzwError::Io(format!("Reading block {}: {}", index, e)))?;
+        if compressed.len() != block.compressed_len as usize {
+            return Err(LzwError::Format(format!(
+                "Block {} runs past the end of the input",
+                index
+            )));
+        }
+
+        let decompressed =
+            block_mode::decompress_block(&self.header, self.shared.as_ref(), &compressed)?;
+        if decompressed.len() != block.uncompressed_len as usize {
+            return Err(LzwError::Format(format!(
+                "Block {} decompressed to {} bytes, the index says {}",
+                index,
+                decompressed.len(),
+                block.uncompressed_len
+            )));
+        }
+        self.current = Some((index, decompressed));
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(index) = self.header.block_containing(self.position) else {
+            return Ok(0);
+        };
+        if !matches!(self.current, Some((current, _)) if current == index) {
+            self.load_block(index)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+        let (_, data) = self.current.as_ref().unwrap();
+
+        let start = (self.position - self.header.blocks[index].uncompressed_offset) as usize;
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableReader<R> {
+    // As for files, seeking past the end is allowed and reads nothing
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Seek to a negative or overflowing position",
+        ))?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::alphabets::Alphabet;
+    use crate::block_mode::compress_blocks;
+    use crate::lzw_decoder::LzwDecoder;
+    use crate::trie_dictionary::TrieDictionary;
+    use crate::LzwSpec;
+    use crate::{Coding, Growth};
+    use std::io::Cursor;
+
+    const SPEC: LzwSpec = LzwSpec {
+        alphabet: Alphabet::Bytes,
+        variable_width: true,
+        width: 12,
+        min_width: 9,
+        max_width: 12,
+        end_code: true,
+        clear_code: true,
+        pack_msb_first: false,
+        early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
+    };
+
+    fn sample_input() -> Vec<u8> {
+        let mut state: u32 = 9;
+        (0..20000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                b"log line 42\n"[(state >> 16) as usize % 12]
+            })
+            .collect()
+    }
+
+    fn reader_for(input: &[u8], block_size: usize) -> SeekableReader<Cursor<Vec<u8>>> {
+        let compressed = compress_blocks::<TrieDictionary<u8>>(SPEC, input, block_size, 1).unwrap();
+        LzwDecoder::seekable(Cursor::new(compressed), None).unwrap()
+    }
+
+    #[test]
+    fn read_everything() {
+        let input = sample_input();
+        let mut reader = reader_for(&input, 1000);
+        assert_eq!(reader.len(), input.len() as u64);
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn read_ranges() {
+        let input = sample_input();
+        let mut reader = reader_for(&input, 1000);
+        // Within a block, across block boundaries, and running off the end
+        for (start, len) in [(0, 10), (999, 2), (1500, 3000), (19990, 100), (20000, 5)] {
+            reader.seek(SeekFrom::Start(start)).unwrap();
+            let mut output = Vec::new();
+            reader.by_ref().take(len).read_to_end(&mut output).unwrap();
+            let start = (start as usize).min(input.len());
+            let end = (start + len as usize).min(input.len());
+            assert_eq!(output, &input[start..end]);
+        }
+    }
+
+    #[test]
+    fn seek_relative() {
+        let input = sample_input();
+        let mut reader = reader_for(&input, 700);
+        assert_eq!(reader.seek(SeekFrom::End(-100)).unwrap(), 19900);
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, &input[19900..]);
+
+        assert_eq!(reader.seek(SeekFrom::Current(-50)).unwrap(), 19950);
+        assert!(reader.seek(SeekFrom::Current(-20000)).is_err());
+        assert!(reader.seek(SeekFrom::End(-20001)).is_err());
+    }
+
+    #[test]
+    fn only_needed_blocks_are_decoded() {
+        let input = sample_input();
+        let compressed = compress_blocks::<TrieDictionary<u8>>(SPEC, &input, 1000, 1).unwrap();
+        let (header, header_len) = ContainerHeader::read_from(&compressed).unwrap();
+
+        // Corrupt the first block; the last one can still be read
+        let mut corrupted = compressed.clone();
+        let first = &header.blocks[0];
+        let start = header_len + first.compressed_offset as usize;
+        corrupted[start..start + first.compressed_len as usize].fill(0xff);
+
+        let mut reader = SeekableReader::new(Cursor::new(corrupted)).unwrap();
+        reader.seek(SeekFrom::End(-500)).unwrap();
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, &input[input.len() - 500..]);
+
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        assert!(reader.read(&mut [0; 10]).is_err());
+    }
+
+    #[test]
+    fn cut_short() {
+        let input = sample_input();
+        let compressed = compress_blocks::<TrieDictionary<u8>>(SPEC, &input, 1000, 1).unwrap();
+        let mut reader =
+            SeekableReader::new(Cursor::new(&compressed[..compressed.len() - 10])).unwrap();
+        reader.seek(SeekFrom::Start(1000)).unwrap();
+        assert!(reader.read(&mut [0; 10]).is_ok());
+        reader.seek(SeekFrom::End(-10)).unwrap();
+        assert!(reader.read(&mut [0; 10]).is_err());
+    }
+}
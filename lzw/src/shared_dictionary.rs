@@ -0,0 +1,264 @@
+use crate::lzw_error::LzwError;
+use crate::lzw_token::Token;
+use std::collections::{HashMap, HashSet};
+
+/* SHARED DICTIONARIES
+    Short messages are over before LZW has built up any useful entries. A shared dictionary is trained
+    once from a sample corpus, and the encoder and decoder both add its entries straight after the
+    initial dictionary, so the first message can use them. They are kept when the dictionary is cleared.
+
+    Each entry is a prefix plus one byte, where the prefix is
+        0..=255     a single byte
+        256 + i     entry i
+    so every prefix of an entry comes before it, just as in the dictionary itself.
+    The ID is a hash of the entries. It is stored in the container header, so an archive can't be
+    decompressed with the wrong dictionary.
+
+    FORMAT
+    magic           4 bytes     "LZWD"
+    version         1 byte
+    entry_count     4 bytes
+    entries         5 bytes each, prefix (u32) then byte
+*/
+
+pub const MAGIC: &[u8; 4] = b"LZWD";
+pub const VERSION: u8 = 1;
+
+// Keeps training on a large corpus within reason
+const MAX_TRAINING_ENTRIES: usize = 1 << 20;
+const SINGLE_BYTES: u32 = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharedDictionary {
+    entries: Vec<(u32, u8)>,
+}
+
+impl SharedDictionary {
+    pub fn new(entries: Vec<(u32, u8)>) -> Result<SharedDictionary, LzwError> {
+        let mut seen = HashSet::new();
+        for (i, entry) in entries.iter().enumerate() {
+            if entry.0 >= SINGLE_BYTES + i as u32 {
+                return Err(LzwError::Format(format!(
+                    "Shared dictionary entry {} has prefix {}, which does not come before it",
+                    i, entry.0
+                )));
+            }
+            if !seen.insert(*entry) {
+                return Err(LzwError::Format(format!(
+                    "Shared dictionary entry {} is a duplicate",
+                    i
+                )));
+            }
+        }
+        Ok(SharedDictionary { entries })
+    }
+
+    // Parse the corpus with LZW, then keep the entries that saved the most bytes, along with their prefixes
+    // Each sample is parsed from its start, as each message will be compressed on its own
+    pub fn train(corpus: &[&[u8]], max_entries: usize) -> SharedDictionary {
+        let mut savings: HashMap<&[u8], u64> = HashMap::new();
+        for sample in corpus.iter() {
+            let mut start = 0;
+            while start < sample.len() {
+                let mut end = start + 1;
+                while end < sample.len() && savings.contains_key(&sample[start..=end]) {
+                    end += 1;
+                }
+                if let Some(saved) = savings.get_mut(&sample[start..end]) {
+                    *saved += (end - start - 1) as u64;
+                }
+                if end < sample.len() && savings.len() < MAX_TRAINING_ENTRIES {
+                    savings.insert(&sample[start..=end], 0);
+                }
+                start = end;
+            }
+        }
+
+        let mut ranked: Vec<(&[u8], u64)> = savings
+            .into_iter()
+            .filter(|(_, saved)| *saved > 0)
+            .collect();
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+        let mut chosen: HashSet<&[u8]> = HashSet::new();
+        for (sequence, _) in ranked {
+            let missing: Vec<&[u8]> = (2..=sequence.len())
+                .map(|len| &sequence[..len])
+                .filter(|prefix| !chosen.contains(prefix))
+                .collect();
+            if chosen.len() + missing.len() <= max_entries {
+                chosen.extend(missing);
+            }
+        }
+
+        // Shorter entries first, so prefixes come before the entries built on them
+        let mut chosen: Vec<&[u8]> = chosen.into_iter().collect();
+        chosen.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+
+        let mut indices: HashMap<&[u8], u32> = HashMap::new();
+        let mut entries = Vec::with_capacity(chosen.len());
+        for (i, sequence) in chosen.into_iter().enumerate() {
+            let (last, prefix) = sequence.split_last().unwrap();
+            let prefix = match prefix {
+                [byte] => *byte as u32,
+                _ => SINGLE_BYTES + indices[prefix],
+            };
+            entries.push((prefix, *last));
+            indices.insert(sequence, i as u32);
+        }
+        SharedDictionary { entries }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // FNV-1a over the entries, 0 is kept for no dictionary
+    pub fn id(&self) -> u32 {
+        let mut bytes = Vec::new();
+        self.write_entries(&mut bytes);
+        let hash = bytes.iter().fold(0x811c9dc5u32, |hash, byte| {
+            (hash ^ *byte as u32).wrapping_mul(0x01000193)
+        });
+        hash.max(1)
+    }
+
+    pub fn sequences(&self) -> Vec<Vec<u8>> {
+        let mut sequences: Vec<Vec<u8>> = Vec::with_capacity(self.entries.len());
+        for (prefix, byte) in self.entries.iter() {
+            let mut sequence = match prefix.checked_sub(SINGLE_BYTES) {
+                None => vec![*prefix as u8],
+                Some(index) => sequences[index as usize].clone(),
+            };
+            sequence.push(*byte);
+            sequences.push(sequence);
+        }
+        sequences
+    }
+
+    // The entries in the form the encoder and decoder are seeded with
+    pub fn tokens(&self) -> Vec<Vec<Token<u8>>> {
+        self.sequences()
+            .into_iter()
+            .map(|sequence| sequence.into_iter().map(Token::new).collect())
+            .collect()
+    }
+
+    fn write_entries(&self, output: &mut Vec<u8>) {
+        for (prefix, byte) in self.entries.iter() {
+            output.extend_from_slice(&prefix.to_le_bytes());
+            output.push(*byte);
+        }
+    }
+
+    pub fn write_to(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(MAGIC);
+        output.push(VERSION);
+        output.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        self.write_entries(output);
+    }
+
+    pub fn read_from(input: &[u8]) -> Result<SharedDictionary, LzwError> {
+        let truncated = || LzwError::Format("Shared dictionary is truncated".to_string());
+        let (header, body) = input.split_at_checked(9).ok_or_else(truncated)?;
+        if &header[..4] != MAGIC {
+            return Err(LzwError::Format("Not a shared dictionary".to_string()));
+        }
+        if header[4] != VERSION {
+            return Err(LzwError::Format(format!(
+                "Unsupported shared dictionary version {}",
+                header[4]
+            )));
+        }
+        let count = u32::from_le_bytes(header[5..9].try_into().unwrap()) as usize;
+        if body.len() != count * 5 {
+            return Err(truncated());
+        }
+        let entries = body
+            .chunks(5)
+            .map(|entry| (u32::from_le_bytes(entry[..4].try_into().unwrap()), entry[4]))
+            .collect();
+        SharedDictionary::new(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn corpus() -> Vec<Vec<u8>> {
+        let mut state: u32 = 17;
+        (0..200)
+            .map(|i| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                format!(
+                    "{{\"event\":\"click\",\"user\":{},\"page\":\"/home\",\"ok\":true}}",
+                    (state >> 16) % 1000 + i
+                )
+                .into_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn train_common_sequences() {
+        let corpus = corpus();
+        let samples: Vec<&[u8]> = corpus.iter().map(|sample| &sample[..]).collect();
+        let dictionary = SharedDictionary::train(&samples, 500);
+        assert!(!dictionary.is_empty());
+        assert!(dictionary.len() <= 500);
+
+        let sequences = dictionary.sequences();
+        // Every prefix is there too
+        for sequence in sequences.iter() {
+            if sequence.len() > 2 {
+                assert!(sequences.contains(&sequence[..sequence.len() - 1].to_vec()));
+            }
+        }
+        // Long entries come from the text every sample shares
+        let shared_text = b",\"page\":\"/home\",\"ok\":true}";
+        assert!(sequences.iter().any(|sequence| sequence.len() >= 12
+            && shared_text
+                .windows(sequence.len())
+                .any(|window| window == &sequence[..])));
+    }
+
+    #[test]
+    fn train_is_limited() {
+        let corpus = corpus();
+        let samples: Vec<&[u8]> = corpus.iter().map(|sample| &sample[..]).collect();
+        assert_eq!(SharedDictionary::train(&samples, 0).len(), 0);
+        assert!(SharedDictionary::train(&samples, 10).len() <= 10);
+        assert!(SharedDictionary::train(&[], 10).is_empty());
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let corpus = corpus();
+        let samples: Vec<&[u8]> = corpus.iter().map(|sample| &sample[..]).collect();
+        let dictionary = SharedDictionary::train(&samples, 300);
+
+        let mut bytes = Vec::new();
+        dictionary.write_to(&mut bytes);
+        let read = SharedDictionary::read_from(&bytes).unwrap();
+        assert_eq!(read, dictionary);
+        assert_eq!(read.id(), dictionary.id());
+
+        assert!(SharedDictionary::read_from(&bytes[..bytes.len() - 1]).is_err());
+        assert!(SharedDictionary::read_from(b"LZWB").is_err());
+        assert_ne!(SharedDictionary::train(&samples, 100).id(), dictionary.id());
+    }
+
+    #[test]
+    fn invalid_entries() {
+        assert!(SharedDictionary::new(vec![(b'a' as u32, b'b'), (256, b'c')]).is_ok());
+        // Refers to itself
+        assert!(SharedDictionary::new(vec![(256, b'c')]).is_err());
+        assert!(SharedDictionary::new(vec![(b'a' as u32, b'b'), (b'a' as u32, b'b')]).is_err());
+    }
+}
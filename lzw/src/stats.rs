@@ -0,0 +1,287 @@
+use crate::checkpoint::StateReader;
+use crate::lzw_error::LzwError;
+use crate::MAX_CODE_WIDTH;
+use std::time::Duration;
+
+/* STATISTICS
+    The encoder and decoder count what they do as they go, see LzwEncoder::stats and LzwDecoder::stats.
+    Counting is cheap, and the dictionaries keep their longest entry as entries are added, so it is
+    read before every reset without walking them.
+    Blocks are compressed separately, so their stats are merged: counts are added up, while the
+    dictionary size, longest entry and peak memory are the largest of any block. Blocks compressed
+    on several threads at once can use more than that between them.
+    A Report adds the sizes, timing and memory budget of a whole run, and is written out as text or
+    JSON.
+*/
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CodeStats {
+    // Every code written or read, control codes included
+    pub codes: u64,
+    // Codes by the width they were written with
+    pub widths: [u64; MAX_CODE_WIDTH as usize + 1],
+    pub clears: u64,
+    // Entries in the dictionary at the end, initial ones included
+    pub dictionary_entries: u64,
+    // Tokens in the longest entry there has been, None if the dictionary can't tell
+    pub longest_entry: Option<u64>,
+    // Most heap bytes the encoder's dictionary was estimated to use, None if it can't tell or when
+    // decoding
+    pub peak_memory: Option<u64>,
+}
+
+impl CodeStats {
+    pub fn record_code(&mut self, width: u8) {
+        self.codes += 1;
+        self.widths[width as usize] += 1;
+    }
+
+    pub fn record_longest(&mut self, longest: Option<usize>) {
+        if let Some(longest) = longest {
+            self.longest_entry = Some(self.longest_entry.unwrap_or(0).max(longest as u64));
+        }
+    }
+
+    pub fn record_memory(&mut self, used: Option<usize>) {
+        if let Some(used) = used {
+            self.peak_memory = Some(self.peak_memory.unwrap_or(0).max(used as u64));
+        }
+    }
+
+    pub fn merge(&mut self, other: &CodeStats) {
+        self.codes += other.codes;
+        for (width, count) in self.widths.iter_mut().zip(other.widths.iter()) {
+            *width += count;
+        }
+        self.clears += other.clears;
+        self.dictionary_entries = self.dictionary_entries.max(other.dictionary_entries);
+        self.record_longest(other.longest_entry.map(|longest| longest as usize));
+        self.record_memory(other.peak_memory.map(|peak| peak as usize));
+    }
+
+    // For a checkpoint, see checkpoint.rs
+    pub fn write_to(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&self.codes.to_le_bytes());
+        for count in self.widths.iter() {
+            output.extend_from_slice(&count.to_le_bytes());
+        }
+        output.extend_from_slice(&self.clears.to_le_bytes());
+        output.extend_from_slice(&self.dictionary_entries.to_le_bytes());
+        // u64::MAX for None, no entry is that long
+        output.extend_from_slice(&self.longest_entry.unwrap_or(u64::MAX).to_le_bytes());
+        output.extend_from_slice(&self.peak_memory.unwrap_or(u64::MAX).to_le_bytes());
+    }
+
+    pub fn read_from(reader: &mut StateReader) -> Result<CodeStats, LzwError> {
+        let mut stats = CodeStats {
+            codes: reader.read_u64()?,
+            ..CodeStats::default()
+        };
+        for count in stats.widths.iter_mut() {
+            *count = reader.read_u64()?;
+        }
+        stats.clears = reader.read_u64()?;
+        stats.dictionary_entries = reader.read_u64()?;
+        stats.longest_entry = Some(reader.read_u64()?).filter(|longest| *longest != u64::MAX);
+        stats.peak_memory = Some(reader.read_u64()?).filter(|peak| *peak != u64::MAX);
+        Ok(stats)
+    }
+
+    // (width, count) of every width used
+    pub fn used_widths(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.widths
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(width, count)| (width, *count))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Operation {
+    Compress,
+    Decompress,
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub operation: Operation,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub blocks: u64,
+    pub elapsed: Duration,
+    pub codes: CodeStats,
+    // The spec's memory budget in bytes, if it had one
+    pub memory_budget: Option<u64>,
+}
+
+impl Report {
+    fn uncompressed_bytes(&self) -> u64 {
+        match self.operation {
+            Operation::Compress => self.input_bytes,
+            Operation::Decompress => self.output_bytes,
+        }
+    }
+
+    fn compressed_bytes(&self) -> u64 {
+        match self.operation {
+            Operation::Compress => self.output_bytes,
+            Operation::Decompress => self.input_bytes,
+        }
+    }
+
+    // Uncompressed over compressed size, whichever way the run went
+    pub fn ratio(&self) -> f64 {
+        self.uncompressed_bytes() as f64 / self.compressed_bytes().max(1) as f64
+    }
+
+    // Uncompressed bytes per second
+    pub fn throughput(&self) -> f64 {
+        self.uncompressed_bytes() as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+
+    pub fn to_text(&self) -> String {
+        let widths: Vec<String> = self
+            .codes
+            .used_widths()
+            .map(|(width, count)| format!("{} bits: {}", width, count))
+            .collect();
+        let longest = match self.codes.longest_entry {
+            Some(longest) => format!("{} tokens", longest),
+            None => "unknown".to_string(),
+        };
+        let peak_memory = match self.codes.peak_memory {
+            Some(peak) => format!("{} bytes", peak),
+            None => "unknown".to_string(),
+        };
+        let memory_budget = match self.memory_budget {
+            Some(budget) => format!("{} bytes", budget),
+            None => "none".to_string(),
+        };
+        format!(
+            "{:?}\n\
+             input           {} bytes\n\
+             output          {} bytes\n\
+             ratio           {:.3}\n\
+             blocks          {}\n\
+             codes           {}\n\
+             code widths     {}\n\
+             dictionary      {} entries at the end\n\
+             clears          {}\n\
+             longest entry   {}\n\
+             peak memory     {}\n\
+             memory budget   {}\n\
+             time            {:.3} s\n\
+             throughput      {:.1} MB/s\n",
+            self.operation,
+            self.input_bytes,
+            self.output_bytes,
+            self.ratio(),
+            self.blocks,
+            self.codes.codes,
+            if widths.is_empty() {
+                "none".to_string()
+            } else {
+                widths.join(", ")
+            },
+            self.codes.dictionary_entries,
+            self.codes.clears,
+            longest,
+            peak_memory,
+            memory_budget,
+            self.elapsed.as_secs_f64(),
+            self.throughput() / 1e6
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        let widths: Vec<String> = self
+            .codes
+            .used_widths()
+            .map(|(width, count)| format!("\"{}\":{}", width, count))
+            .collect();
+        format!(
+            "{{\"operation\":\"{}\",\"input_bytes\":{},\"output_bytes\":{},\"ratio\":{:.6},\"blocks\":{},\
+             \"codes\":{},\"code_widths\":{{{}}},\"dictionary_entries\":{},\"clears\":{},\"longest_entry\":{},\
+             \"peak_memory\":{},\"memory_budget\":{},\"seconds\":{:.6},\"throughput_bytes_per_second\":{:.1}}}\n",
+            match self.operation {
+                Operation::Compress => "compress",
+                Operation::Decompress => "decompress",
+            },
+            self.input_bytes,
+            self.output_bytes,
+            self.ratio(),
+            self.blocks,
+            self.codes.codes,
+            widths.join(","),
+            self.codes.dictionary_entries,
+            self.codes.clears,
+            self.codes
+                .longest_entry
+                .map_or("null".to_string(), |longest| longest.to_string()),
+            self.codes
+                .peak_memory
+                .map_or("null".to_string(), |peak| peak.to_string()),
+            self.memory_budget
+                .map_or("null".to_string(), |budget| budget.to_string()),
+            self.elapsed.as_secs_f64(),
+            self.throughput()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn merge_and_report() {
+        let mut first = CodeStats::default();
+        first.record_code(9);
+        first.record_code(9);
+        first.record_code(10);
+        first.clears = 1;
+        first.dictionary_entries = 600;
+        first.record_longest(Some(12));
+        first.record_memory(Some(30_000));
+        let mut second = CodeStats::default();
+        second.record_code(12);
+        second.dictionary_entries = 4096;
+        second.record_longest(None);
+        second.record_memory(Some(20_000));
+        first.merge(&second);
+        assert_eq!(first.codes, 4);
+        assert_eq!(
+            first.used_widths().collect::<Vec<_>>(),
+            vec![(9, 2), (10, 1), (12, 1)]
+        );
+        assert_eq!(first.dictionary_entries, 4096);
+        assert_eq!(first.longest_entry, Some(12));
+        assert_eq!(first.peak_memory, Some(30_000));
+
+        let report = Report {
+            operation: Operation::Decompress,
+            input_bytes: 250,
+            output_bytes: 1000,
+            blocks: 2,
+            elapsed: Duration::from_millis(2),
+            codes: first,
+            memory_budget: Some(65_536),
+        };
+        assert_eq!(report.ratio(), 4.0);
+        assert_eq!(report.throughput(), 500_000.0);
+        assert!(report
+            .to_text()
+            .contains("code widths     9 bits: 2, 10 bits: 1, 12 bits: 1\n"));
+        assert!(report
+            .to_text()
+            .contains("peak memory     30000 bytes\nmemory budget   65536 bytes\n"));
+        assert_eq!(
+            report.to_json(),
+            "{\"operation\":\"decompress\",\"input_bytes\":250,\"output_bytes\":1000,\"ratio\":4.000000,\
+             \"blocks\":2,\"codes\":4,\"code_widths\":{\"9\":2,\"10\":1,\"12\":1},\"dictionary_entries\":4096,\
+             \"clears\":1,\"longest_entry\":12,\"peak_memory\":30000,\"memory_budget\":65536,\"seconds\":0.002000,\"throughput_bytes_per_second\":500000.0}\n"
+        );
+    }
+}
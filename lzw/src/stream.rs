@@ -0,0 +1,444 @@
+use crate::alphabets;
+use crate::bit_packing::{BitReader, BitWriter};
+use crate::lzw_decoder::LzwDecoder;
+use crate::lzw_encoder::LzwEncoder;
+use crate::lzw_error::LzwError;
+use crate::lzw_token::Token;
+use crate::progress::{Monitor, Progress, UNMONITORED};
+use crate::shared_dictionary::SharedDictionary;
+use crate::stats::Operation;
+use crate::trie_dictionary::TrieDictionary;
+use crate::{Coding, Growth, LzwSpec, MAX_GROWN_LENGTH};
+
+/* STREAMING
+    Input can be compressed and decompressed as it arrives, a piece at a time, giving exactly the
+    stream that compress and decompress would for all of it at once. There is no container, so
+    the spec, and the shared dictionary if there is one, have to be known on both sides.
+
+    The encoder can't emit a code while its match could still run on into input yet to come, so
+    it holds back at least as many tokens as the longest entry could have. With Lzw growth that is
+    the longest entry when the encoder last caught up, plus one for every step since, as every entry
+    is an earlier one plus a token. Lzmw and Lzap entries stop growing at MAX_GROWN_LENGTH.
+    The encoder only catches up once STREAM_CHUNK more bytes have arrived since it last did, so what
+    it holds back stays small next to what it encodes each time.
+    The decoder keeps the bits of a code that is cut off by the end of a piece for the next one.
+    Only byte alphabets and packed codes can be streamed.
+
+    update_with_progress checks the Monitor's cancel token before taking the piece, then reports the
+    bytes taken and given out so far to its observer, see progress.rs. The length of a stream isn't
+    known, so total_in is always 0. A cancelled piece isn't taken, and the stream can't carry on.
+*/
+
+pub const STREAM_CHUNK: usize = 64 * 1024;
+
+fn check_spec(spec: LzwSpec) -> Result<(), LzwError> {
+    spec.validate()?;
+    if !spec.alphabet.is_bytes() || spec.coding != Coding::Packed {
+        return Err(LzwError::Spec(format!(
+            "Only byte alphabets and packed codes can be streamed, not {:?} with {:?} coding",
+            spec.alphabet, spec.coding
+        )));
+    }
+    Ok(())
+}
+
+fn check_cancelled(monitor: Monitor, done: Progress) -> Result<(), LzwError> {
+    match monitor.cancel {
+        Some(cancel) if cancel.is_cancelled() => Err(LzwError::Cancelled(format!(
+            "Stopped after {} bytes",
+            done.bytes_in
+        ))),
+        _ => Ok(()),
+    }
+}
+
+pub struct StreamEncoder {
+    spec: LzwSpec,
+    encoder: LzwEncoder<u8, TrieDictionary<u8>>,
+    writer: BitWriter,
+    // Input not encoded yet
+    pending: Vec<u8>,
+    // How long pending has to be before catching up again
+    ready_at: usize,
+    bytes_in: u64,
+    bytes_out: u64,
+}
+
+impl StreamEncoder {
+    pub fn new(spec: LzwSpec) -> Result<StreamEncoder, LzwError> {
+        StreamEncoder::with_dictionary(spec, None)
+    }
+
+    // The same shared dictionary has to be given to the decoder
+    pub fn with_dictionary(
+        spec: LzwSpec,
+        shared: Option<&SharedDictionary>,
+    ) -> Result<StreamEncoder, LzwError> {
+        check_spec(spec)?;
+        let encoder = LzwEncoder::with_dictionary(
+            spec,
+            alphabets::produce_byte_alphabet(spec.alphabet),
+            shared.map(|shared| shared.tokens()).unwrap_or_default(),
+        )?;
+        Ok(StreamEncoder {
+            spec,
+            encoder,
+            writer: BitWriter::new(spec.pack_msb_first),
+            pending: Vec::new(),
+            ready_at: STREAM_CHUNK,
+            bytes_in: 0,
+            bytes_out: 0,
+        })
+    }
+
+    pub fn progress(&self) -> Progress {
+        Progress {
+            operation: Operation::Compress,
+            bytes_in: self.bytes_in,
+            bytes_out: self.bytes_out,
+            total_in: 0,
+        }
+    }
+
+    // Take more input, adding whatever compressed data is ready to output
+    pub fn update(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), LzwError> {
+        self.update_with_progress(input, output, UNMONITORED)
+    }
+
+    pub fn update_with_progress(
+        &mut self,
+        input: &[u8],
+        output: &mut Vec<u8>,
+        monitor: Monitor,
+    ) -> Result<(), LzwError> {
+        check_cancelled(monitor, self.progress())?;
+        let start = output.len();
+        self.encode_ready(input, output)?;
+        self.bytes_in += input.len() as u64;
+        self.bytes_out += (output.len() - start) as u64;
+        if let Some(observer) = monitor.observer {
+            observer.update(self.progress());
+        }
+        Ok(())
+    }
+
+    fn encode_ready(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), LzwError> {
+        self.pending.extend_from_slice(input);
+        if self.pending.len() < self.ready_at {
+            return Ok(());
+        }
+
+        let longest = self.encoder.dictionary().longest_entry();
+        let (mut held_back, per_step) = match self.spec.growth {
+            Growth::Lzw => (longest, 1),
+            Growth::Lzmw | Growth::Lzap => (longest.max(MAX_GROWN_LENGTH), 0),
+        };
+        let mut tokens = self.pending.iter().map(|byte| Token::new(*byte)).peekable();
+        let mut codes = Vec::new();
+        // Peeked tokens are still counted as left, so the match can't reach the end of pending
+        while tokens.len() > held_back {
+            self.encoder.encode_step(&mut tokens, &mut codes)?;
+            for code in codes.drain(..) {
+                self.writer.write(code.get_code(), code.get_used_bits());
+            }
+            held_back += per_step;
+        }
+        let consumed = self.pending.len() - tokens.len();
+        self.pending.drain(..consumed);
+        self.ready_at = self.pending.len() + STREAM_CHUNK;
+        output.extend(self.writer.take_bytes());
+        Ok(())
+    }
+
+    // Compress the rest of the input, adding the End code if the spec has one
+    pub fn finish(mut self, output: &mut Vec<u8>) -> Result<(), LzwError> {
+        let mut tokens = self.pending.iter().map(|byte| Token::new(*byte)).peekable();
+        let mut codes = Vec::new();
+        while self.encoder.encode_step(&mut tokens, &mut codes)? {}
+        self.encoder.encode_end(&mut codes)?;
+        for code in codes {
+            self.writer.write(code.get_code(), code.get_used_bits());
+        }
+        output.extend(self.writer.finish());
+        Ok(())
+    }
+}
+
+pub struct StreamDecoder {
+    spec: LzwSpec,
+    decoder: LzwDecoder<u8>,
+    // Bits of a code cut off by the end of the last piece, see BitReader::leftover
+    leftover: (u64, u8),
+    bytes_in: u64,
+    bytes_out: u64,
+}
+
+impl StreamDecoder {
+    pub fn new(spec: LzwSpec) -> Result<StreamDecoder, LzwError> {
+        StreamDecoder::with_dictionary(spec, None)
+    }
+
+    pub fn with_dictionary(
+        spec: LzwSpec,
+        shared: Option<&SharedDictionary>,
+    ) -> Result<StreamDecoder, LzwError> {
+        check_spec(spec)?;
+        let decoder = LzwDecoder::with_dictionary(
+            spec,
+            alphabets::produce_byte_alphabet(spec.alphabet),
+            &shared.map(|shared| shared.tokens()).unwrap_or_default(),
+        )?;
+        Ok(StreamDecoder {
+            spec,
+            decoder,
+            leftover: (0, 0),
+            bytes_in: 0,
+            bytes_out: 0,
+        })
+    }
+
+    pub fn progress(&self) -> Progress {
+        Progress {
+            operation: Operation::Decompress,
+            bytes_in: self.bytes_in,
+            bytes_out: self.bytes_out,
+            total_in: 0,
+        }
+    }
+
+    // Take more compressed data, adding what it decodes to output
+    // Anything after the End code is ignored
+    pub fn update(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), LzwError> {
+        self.update_with_progress(input, output, UNMONITORED)
+    }
+
+    pub fn update_with_progress(
+        &mut self,
+        input: &[u8],
+        output: &mut Vec<u8>,
+        monitor: Monitor,
+    ) -> Result<(), LzwError> {
+        check_cancelled(monitor, self.progress())?;
+        let mut reader = BitReader::resume(input, self.spec.pack_msb_first, self.leftover);
+        let mut tokens = Vec::new();
+        self.decoder.read_codes(&mut reader, &mut tokens)?;
+        self.leftover = reader.leftover();
+        let start = output.len();
+        output.extend(tokens.iter().filter_map(|token| token.get_value()));
+        self.bytes_in += input.len() as u64;
+        self.bytes_out += (output.len() - start) as u64;
+        if let Some(observer) = monitor.observer {
+            observer.update(self.progress());
+        }
+        Ok(())
+    }
+
+    // Unlike decompress, fails if the spec has an End code and it hasn't been read,
+    // as the stream was then cut short
+    pub fn finish(self) -> Result<(), LzwError> {
+        if self.spec.end_code && !self.decoder.has_ended() {
+            return Err(LzwError::Decode(
+                "Stream ended before its End code".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::alphabets::Alphabet;
+    use crate::progress::CancelToken;
+    use std::sync::Mutex;
+
+    const SPEC: LzwSpec = LzwSpec {
+        alphabet: Alphabet::Bytes,
+        variable_width: true,
+        width: 12,
+        min_width: 9,
+        max_width: 12,
+        end_code: true,
+        clear_code: true,
+        pack_msb_first: false,
+        early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
+    };
+
+    // Text with long repeats, so that entries get long enough to matter
+    fn sample_input(len: usize) -> Vec<u8> {
+        let mut state: u32 = 9;
+        let mut input = Vec::with_capacity(len);
+        while input.len() < len {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            if state >> 29 == 0 && input.len() > 5000 {
+                let start = (state >> 8) as usize % (input.len() - 5000);
+                let repeat = input[start..start + 3000].to_vec();
+                input.extend(repeat);
+            } else {
+                input.push(b"aaab abcab bc  "[(state >> 16) as usize % 15]);
+            }
+        }
+        input.truncate(len);
+        input
+    }
+
+    fn compress_in_pieces(spec: LzwSpec, input: &[u8], piece: usize) -> Vec<u8> {
+        let mut encoder = StreamEncoder::new(spec).unwrap();
+        let mut output = Vec::new();
+        for chunk in input.chunks(piece) {
+            encoder.update(chunk, &mut output).unwrap();
+        }
+        encoder.finish(&mut output).unwrap();
+        output
+    }
+
+    fn decompress_in_pieces(spec: LzwSpec, input: &[u8], piece: usize) -> Vec<u8> {
+        let mut decoder = StreamDecoder::new(spec).unwrap();
+        let mut output = Vec::new();
+        for chunk in input.chunks(piece) {
+            decoder.update(chunk, &mut output).unwrap();
+        }
+        decoder.finish().unwrap();
+        output
+    }
+
+    #[test]
+    fn same_as_all_at_once() {
+        let input = sample_input(400_000);
+        let specs = [
+            SPEC,
+            LzwSpec {
+                max_width: 16,
+                pack_msb_first: true,
+                early_change: true,
+                ..SPEC
+            },
+            LzwSpec {
+                clear_code: false,
+                evict_lru: true,
+                ..SPEC
+            },
+            LzwSpec {
+                growth: Growth::Lzmw,
+                ..SPEC
+            },
+            LzwSpec {
+                growth: Growth::Lzap,
+                end_code: false,
+                ..SPEC
+            },
+        ];
+        for spec in specs {
+            let whole = crate::compress::<TrieDictionary<u8>>(spec, &input).unwrap();
+            for piece in [1000, 70_000, input.len()] {
+                let streamed = compress_in_pieces(spec, &input, piece);
+                assert_eq!(streamed, whole, "{:?} in pieces of {}", spec, piece);
+            }
+            for piece in [1, 3, 4096] {
+                assert_eq!(decompress_in_pieces(spec, &whole, piece), input);
+            }
+        }
+    }
+
+    #[test]
+    fn short_and_empty_input() {
+        for input in [&b""[..], b"a", b"abababababab"] {
+            let whole = crate::compress::<TrieDictionary<u8>>(SPEC, input).unwrap();
+            assert_eq!(compress_in_pieces(SPEC, input, 1), whole);
+            assert_eq!(decompress_in_pieces(SPEC, &whole, 1), input);
+        }
+    }
+
+    #[test]
+    fn shared_dictionary_stream() {
+        let input = sample_input(100_000);
+        let samples: Vec<&[u8]> = input.chunks(500).collect();
+        let shared = SharedDictionary::train(&samples, 500);
+        let whole =
+            crate::compress_with_dictionary::<TrieDictionary<u8>>(SPEC, Some(&shared), &input)
+                .unwrap();
+
+        let mut encoder = StreamEncoder::with_dictionary(SPEC, Some(&shared)).unwrap();
+        let mut streamed = Vec::new();
+        for chunk in input.chunks(30_000) {
+            encoder.update(chunk, &mut streamed).unwrap();
+        }
+        encoder.finish(&mut streamed).unwrap();
+        assert_eq!(streamed, whole);
+
+        let mut decoder = StreamDecoder::with_dictionary(SPEC, Some(&shared)).unwrap();
+        let mut output = Vec::new();
+        decoder.update(&whole, &mut output).unwrap();
+        decoder.finish().unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn progress_and_cancel() {
+        let input = sample_input(200_000);
+        let whole = crate::compress::<TrieDictionary<u8>>(SPEC, &input).unwrap();
+        let seen = Mutex::new(Vec::new());
+        let observer = |progress: Progress| seen.lock().unwrap().push(progress);
+        let cancel = CancelToken::new();
+        let monitor = Monitor {
+            observer: Some(&observer),
+            cancel: Some(&cancel),
+        };
+
+        let mut encoder = StreamEncoder::new(SPEC).unwrap();
+        let mut output = Vec::new();
+        for piece in input.chunks(50_000) {
+            encoder
+                .update_with_progress(piece, &mut output, monitor)
+                .unwrap();
+        }
+        let seen = seen.lock().unwrap().clone();
+        assert_eq!(seen.len(), 4);
+        assert_eq!(seen[3].bytes_in, input.len() as u64);
+        assert_eq!(seen[3].bytes_out, output.len() as u64);
+        assert_eq!(encoder.progress(), seen[3]);
+        encoder.finish(&mut output).unwrap();
+        assert_eq!(output, whole);
+
+        let mut decoder = StreamDecoder::new(SPEC).unwrap();
+        let mut decoded = Vec::new();
+        decoder
+            .update_with_progress(&whole[..1000], &mut decoded, monitor)
+            .unwrap();
+        assert_eq!(decoder.progress().bytes_in, 1000);
+        assert_eq!(decoder.progress().bytes_out, decoded.len() as u64);
+        cancel.cancel();
+        let result = decoder.update_with_progress(&whole[1000..], &mut decoded, monitor);
+        assert!(matches!(result, Err(LzwError::Cancelled(_))));
+        assert_eq!(decoder.progress().bytes_in, 1000);
+    }
+
+    #[test]
+    fn cut_short_or_unsupported() {
+        let input = sample_input(10_000);
+        let whole = crate::compress::<TrieDictionary<u8>>(SPEC, &input).unwrap();
+        let mut decoder = StreamDecoder::new(SPEC).unwrap();
+        let mut output = Vec::new();
+        decoder
+            .update(&whole[..whole.len() / 2], &mut output)
+            .unwrap();
+        assert!(input.starts_with(&output));
+        assert!(decoder.finish().is_err());
+
+        let range = LzwSpec {
+            coding: Coding::Range,
+            ..SPEC
+        };
+        assert!(StreamEncoder::new(range).is_err());
+        let words = LzwSpec {
+            alphabet: Alphabet::Words,
+            ..SPEC
+        };
+        assert!(StreamDecoder::new(words).is_err());
+    }
+}
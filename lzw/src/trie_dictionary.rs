@@ -2,11 +2,15 @@
 // https://docs.rs/trie-rs/latest/trie_rs/
 // https://crates.io/crates/louds-rs
 
-use crate::alphabets;
+use crate::checkpoint::{self, StateReader};
+use crate::lru_tracker::LruTracker;
 use crate::lzw_code;
-use crate::lzw_token::{HashableToken, Token};
-use crate::LzwSpec;
-use std::collections::HashMap;
+use crate::lzw_dictionary::LzwDictionary;
+use crate::lzw_error::LzwError;
+use crate::lzw_token::{ControlToken, HashableToken, Token};
+use crate::{Growth, LzwSpec, MAX_GROWN_LENGTH};
+use std::collections::{HashMap, VecDeque};
+use std::iter::Peekable;
 
 // TODO implement with generics
 // TODO compare speed against trie_rs
@@ -26,9 +30,20 @@ The trie is ideal as the *sequential* lookup/search takes linear time, and still
 */
 
 /* FURTHER CONSIDERATIONS
-    We don't need a delete function as either dictionary entries are never removed,
+    Usually we don't need a delete function as either dictionary entries are never removed,
     or all of the non-starting-dictionary elements are removed when the max code size is reached.
     In this case, we can just reinitialize the dictionary
+
+    The exception is LRU replacement (see lru_tracker.rs), which removes one leaf at a time and reuses its code.
+    The victim is picked by code, so in that mode we also keep the prefix code and last token of every code,
+    which gives the path down the trie to any entry.
+
+    LZMW entries are not prefix closed (see Growth), so there can be nodes on the way to an entry that
+    have no code of their own. Walking the trie can then go past the longest match, and the tokens read
+    beyond it are held back in lookahead for the next step.
+
+    The length of the longest entry is kept as entries are added, for the stats and the stream encoder,
+    rather than found by walking the trie. Entries replaced by LRU are still counted.
 */
 
 #[derive(Debug)]
@@ -39,11 +54,21 @@ pub struct TrieNode<T: HashableToken> {
     children: HashMap<Token<T>, TrieNode<T>>,
 }
 
+// The prefix code, if any, and last token of a code
+type CodeEntry<T> = (Option<u32>, Token<T>);
+
 pub struct TrieDictionary<T: HashableToken> {
     root: TrieNode<T>,
     // alphabet: Vec<char>,
-    clear_code: bool,
-    end_code: bool,
+    // Only used for LRU replacement
+    lru: Option<LruTracker>,
+    entries: Vec<CodeEntry<T>>,
+    // Only used for LZMW and LZAP growth
+    growth: Growth,
+    previous_match: Option<Vec<Token<T>>>,
+    lookahead: VecDeque<Token<T>>,
+    // Longest entry added since the dictionary was created or reset
+    longest: usize,
 }
 
 impl<T: HashableToken> TrieNode<T> {
@@ -56,6 +81,16 @@ impl<T: HashableToken> TrieNode<T> {
         }
     }
 
+    // A node on the way to a longer entry, that is not an entry itself
+    pub fn new_without_value(key: Token<T>) -> TrieNode<T> {
+        TrieNode {
+            key: Option::Some(key),
+            value: Option::None,
+            terminator: true,
+            children: HashMap::new(),
+        }
+    }
+
     pub fn new_root() -> TrieNode<T> {
         TrieNode {
             key: Option::None,
@@ -71,35 +106,28 @@ impl<T: HashableToken> TrieNode<T> {
             .insert(val, TrieNode::new(val, sequence_code, terminator));
         match inserted {
             None => {}
-            Some(old) => println!("Already had an entry!!! : {:?}", old),
+            Some(old) => tracing::warn!("Replaced an existing entry: {:?}", old),
         }
     }
-}
-
-// TODO: struct for result of fetch_code_and_insert -> need to know how many tokens consumed
 
-pub struct LookupResult {
-    code: lzw_code::Code,
-    consumed_tokens: u32, // TODO establish neccessary size
-}
-
-impl LookupResult {
-    fn new(code: lzw_code::Code, consumed_tokens: u32) -> LookupResult {
-        LookupResult {
-            code,
-            consumed_tokens,
+    pub fn remove_child(&mut self, val: &Token<T>) -> Option<TrieNode<T>> {
+        let removed = self.children.remove(val);
+        if self.children.is_empty() {
+            self.terminator = true;
         }
+        removed
     }
 }
 
 impl<T: HashableToken> TrieDictionary<T> {
     // really we want a reference to an iterator
     // or make the struct more stateful and expose a step(char) or similar
+    // Gives the code of the longest known prefix, and how many tokens it took
     pub fn fetch_code_and_insert(
         &mut self,
         search_seq: &[Token<T>],
         next_code: lzw_code::Code, // TODO: Take a reference to the code generator so that codes advance only when needed
-    ) -> LookupResult {
+    ) -> (lzw_code::Code, u32) {
         let mut current_node = &mut self.root;
         let mut fetched_code: Option<lzw_code::Code> = Option::None;
         let mut consumed_tokens: u32 = 0;
@@ -114,7 +142,7 @@ impl<T: HashableToken> TrieDictionary<T> {
             }
         }
         match fetched_code {
-            Some(code) => LookupResult::new(code, consumed_tokens),
+            Some(code) => (code, consumed_tokens),
             None => panic!("didn't fetch a code"),
         }
     }
@@ -153,37 +181,30 @@ impl<T: HashableToken> TrieDictionary<T> {
         let mut new_trie = TrieDictionary {
             root: TrieNode::new_root(),
             // alphabet,
-            clear_code: lzw_spec.clear_code,
-            end_code: lzw_spec.end_code,
+            lru: None,
+            entries: Vec::new(),
+            growth: lzw_spec.growth,
+            previous_match: None,
+            lookahead: VecDeque::new(),
+            longest: 0,
         };
 
-        println!("Size of initial dictionary before alphabet: {}", {
-            new_trie.root.children.len()
-        });
-
         // ADD the alphabet
         for symbol in alphabet.iter() {
             if let Some(code) = code_gen.get_next_code() {
                 new_trie.root.add_child(*symbol, code, true);
-                //println!("code: {}", code);
+                new_trie.entries.push((None, *symbol));
             } else {
                 panic!("Base alphabet too large for starting bit width");
             }
         }
 
-        println!("Size of initial dictionary before control: {}", {
-            new_trie.root.children.len()
-        });
-
         // ADD the clear code control character
         if lzw_spec.clear_code {
             if let Some(code) = code_gen.get_next_code() {
-                new_trie.root.add_child(
-                    Token::new_control(crate::lzw_token::ControlToken::Clear),
-                    code,
-                    true,
-                );
-                println!("code END: {}", code);
+                let token = Token::new_control(crate::lzw_token::ControlToken::Clear);
+                new_trie.root.add_child(token, code, true);
+                new_trie.entries.push((None, token));
             } else {
                 panic!("Base alphabet too large for starting bit width");
             }
@@ -192,29 +213,691 @@ impl<T: HashableToken> TrieDictionary<T> {
         // ADD the end code control character
         if lzw_spec.end_code {
             if let Some(code) = code_gen.get_next_code() {
-                new_trie.root.add_child(
-                    Token::new_control(crate::lzw_token::ControlToken::End),
-                    code,
-                    true,
-                );
-                println!("code END: {}", code);
+                let token = Token::new_control(crate::lzw_token::ControlToken::End);
+                new_trie.root.add_child(token, code, true);
+                new_trie.entries.push((None, token));
             } else {
                 panic!("Base alphabet too large for starting bit width");
             }
         }
 
-        println!("Size of initial dictionary: {}", {
+        new_trie.longest = (!new_trie.root.children.is_empty()) as usize;
+
+        // Called for every block and after every Clear, see stats.rs for counts worth reporting
+        tracing::trace!(
+            "Initial dictionary of {} entries",
             new_trie.root.children.len()
-        });
+        );
+
+        if lzw_spec.evict_lru {
+            new_trie.lru = Some(LruTracker::new(code_gen.get_current_code()));
+        } else {
+            new_trie.entries.clear();
+        }
         new_trie
     }
+
+    fn track_entry(&mut self, code: u32, prefix_code: u32, token: Token<T>) {
+        let Some(lru) = &mut self.lru else {
+            return;
+        };
+        lru.add(code, prefix_code);
+        let entry = (Some(prefix_code), token);
+        if code as usize == self.entries.len() {
+            self.entries.push(entry);
+        } else {
+            self.entries[code as usize] = entry;
+        }
+    }
+
+    fn path_to(&self, code: u32) -> Vec<Token<T>> {
+        let mut path = Vec::new();
+        let mut next = Some(code);
+        while let Some(code) = next {
+            let (prefix_code, token) = self.entries[code as usize];
+            path.push(token);
+            next = prefix_code;
+        }
+        path.reverse();
+        path
+    }
+
+    fn node_at(&mut self, path: &[Token<T>]) -> &mut TrieNode<T> {
+        let mut current_node = &mut self.root;
+        for symbol in path.iter() {
+            current_node = current_node
+                .children
+                .get_mut(symbol)
+                .expect("LRU entries out of step with the trie");
+        }
+        current_node
+    }
+
+    // Out of codes, so give prefix + token the code of the least recently used leaf
+    fn replace_lru(&mut self, prefix: lzw_code::Code, token: Token<T>, width: u8) {
+        let Some(victim) = self
+            .lru
+            .as_mut()
+            .and_then(|lru| lru.evict(prefix.get_code()))
+        else {
+            return;
+        };
+        let victim_path = self.path_to(victim);
+        let (last, victim_prefix) = victim_path.split_last().unwrap();
+        self.node_at(victim_prefix).remove_child(last);
+
+        let prefix_path = self.path_to(prefix.get_code());
+        self.longest = self.longest.max(prefix_path.len() + 1);
+        let prefix_node = self.node_at(&prefix_path);
+        prefix_node.add_child(token, lzw_code::Code::new(victim, width), true);
+        prefix_node.terminator = false;
+        self.track_entry(victim, prefix.get_code(), token);
+    }
+
+    fn peek_token<I>(&self, tokens: &mut Peekable<I>) -> Option<Token<T>>
+    where
+        I: Iterator<Item = Token<T>>,
+    {
+        self.lookahead
+            .front()
+            .copied()
+            .or_else(|| tokens.peek().copied())
+    }
+
+    // LZMW and LZAP step: find the longest match, backing off from any nodes without a value,
+    // then add entries built from the previous match and this one
+    fn grow_step<I>(
+        &mut self,
+        tokens: &mut Peekable<I>,
+        code_gen: &mut lzw_code::CodeGenerator,
+    ) -> Result<Option<lzw_code::Code>, LzwError>
+    where
+        I: Iterator<Item = Token<T>>,
+    {
+        let mut walked = Vec::new();
+        let mut longest = None;
+        let mut current_node = &self.root;
+        while let Some(token) = self.peek_token(tokens) {
+            let Some(child) = current_node.children.get(&token) else {
+                break;
+            };
+            if self.lookahead.pop_front().is_none() {
+                tokens.next();
+            }
+            walked.push(token);
+            current_node = child;
+            if let Some(code) = child.value {
+                longest = Some((walked.len(), code));
+            }
+        }
+
+        let Some((length, code)) = longest else {
+            return match self.peek_token(tokens) {
+                None => Ok(None),
+                Some(token) => Err(LzwError::Dictionary(format!(
+                    "Token {:?} is not in the alphabet",
+                    token
+                ))),
+            };
+        };
+        for token in walked.drain(length..).rev() {
+            self.lookahead.push_front(token);
+        }
+
+        if let Some(previous) = self.previous_match.take() {
+            self.grow(&previous, &walked, code_gen);
+        }
+        self.previous_match = Some(walked);
+        Ok(Some(code))
+    }
+
+    // Lzmw adds previous + matched, Lzap adds previous + every prefix of matched
+    // A code is used for every entry, even one already in the dictionary, as the decoder can't tell
+    fn grow(
+        &mut self,
+        previous: &[Token<T>],
+        matched: &[Token<T>],
+        code_gen: &mut lzw_code::CodeGenerator,
+    ) {
+        if previous.len() + matched.len() > MAX_GROWN_LENGTH || code_gen.is_exhausted() {
+            return;
+        }
+        let mut current_node = &mut self.root;
+        for token in previous.iter() {
+            current_node = current_node
+                .children
+                .get_mut(token)
+                .expect("Previous match is not in the dictionary");
+        }
+        let mut grown = 0;
+        for (i, token) in matched.iter().enumerate() {
+            let code = if self.growth == Growth::Lzap || i + 1 == matched.len() {
+                let Some(code) = code_gen.get_next_code() else {
+                    break;
+                };
+                Some(code)
+            } else {
+                None
+            };
+            current_node.terminator = false;
+            current_node = current_node
+                .children
+                .entry(*token)
+                .or_insert_with(|| TrieNode::new_without_value(*token));
+            if current_node.value.is_none() {
+                current_node.value = code;
+            }
+            if current_node.value.is_some() {
+                grown = previous.len() + i + 1;
+            }
+        }
+        self.longest = self.longest.max(grown);
+    }
+}
+
+// Heap bytes of a node's HashMap, worked out from its capacity as hashbrown lays it out:
+// a power of two buckets, at most 7/8 full once there are 8 or more, and a control byte per bucket plus a group
+fn map_bytes<K, V>(map: &HashMap<K, V>) -> usize {
+    let capacity = map.capacity();
+    if capacity == 0 {
+        return 0;
+    }
+    let buckets = if capacity < 8 {
+        (capacity + 1).next_power_of_two()
+    } else {
+        (capacity * 8 / 7).next_power_of_two()
+    };
+    buckets * (std::mem::size_of::<(K, V)>() + 1) + 16
+}
+
+// Tokens in the longest entry below a node, for a dictionary read back from a checkpoint
+fn longest_below<T: HashableToken>(root: &TrieNode<T>) -> usize {
+    let mut longest = 0;
+    let mut pending: Vec<(usize, &TrieNode<T>)> =
+        root.children.values().map(|node| (1, node)).collect();
+    while let Some((length, node)) = pending.pop() {
+        if node.value.is_some() {
+            longest = longest.max(length);
+        }
+        pending.extend(node.children.values().map(|child| (length + 1, child)));
+    }
+    longest
+}
+
+impl<T: HashableToken> TrieDictionary<T> {
+    pub fn longest_entry(&self) -> usize {
+        self.longest
+    }
+
+    // Walks the whole trie, so is best not called every step
+    pub fn memory_used(&self) -> usize {
+        let mut used = map_bytes(&self.root.children);
+        let mut pending: Vec<&TrieNode<T>> = self.root.children.values().collect();
+        while let Some(node) = pending.pop() {
+            used += map_bytes(&node.children);
+            pending.extend(node.children.values());
+        }
+        let lru = self.lru.as_ref().map_or(0, |lru| lru.memory_used());
+        let token_bytes = std::mem::size_of::<Token<T>>();
+        used + lru
+            + self.entries.capacity() * std::mem::size_of::<(Option<u32>, Token<T>)>()
+            + self.lookahead.capacity() * token_bytes
+            + self
+                .previous_match
+                .as_ref()
+                .map_or(0, |previous| previous.capacity() * token_bytes)
+    }
+}
+
+/* EXPORT
+    The whole trie as a Graphviz DOT graph or as JSON, to see what the encoder has learnt so far.
+    Both list every node with its token, code and terminator flag, the root first.
+    Children are ordered by code, and nodes without a code by token, so the same dictionary
+    always gives the same output and two dumps can be diffed.
+*/
+
+struct ExportNode<'a, T: HashableToken> {
+    parent: Option<usize>,
+    node: &'a TrieNode<T>,
+}
+
+fn token_label<T: HashableToken>(token: Option<Token<T>>) -> String {
+    match token {
+        None => "root".to_string(),
+        Some(token) => match (token.get_value(), token.get_control()) {
+            (Some(value), _) => format!("{:?}", value),
+            (None, Some(control)) => format!("{:?}", control),
+            (None, None) => "?".to_string(),
+        },
+    }
+}
+
+// For both DOT labels and JSON strings
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl<T: HashableToken> TrieDictionary<T> {
+    // Pre-order, walked with a stack rather than recursion, as entries can be very long
+    fn export_nodes(&self) -> Vec<ExportNode<'_, T>> {
+        let mut nodes = Vec::new();
+        let mut pending = vec![ExportNode {
+            parent: None,
+            node: &self.root,
+        }];
+        while let Some(current) = pending.pop() {
+            let id = nodes.len();
+            let mut children: Vec<&TrieNode<T>> = current.node.children.values().collect();
+            children.sort_by_cached_key(|child| {
+                (
+                    child.value.map_or(u32::MAX, |code| code.get_code()),
+                    token_label(child.key),
+                )
+            });
+            // Reversed so the lowest code comes off the stack first
+            for child in children.into_iter().rev() {
+                pending.push(ExportNode {
+                    parent: Some(id),
+                    node: child,
+                });
+            }
+            nodes.push(current);
+        }
+        nodes
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph dictionary {\n    node [shape=box];\n");
+        for (id, export) in self.export_nodes().iter().enumerate() {
+            let node = export.node;
+            let mut label = escape(&token_label(node.key));
+            if let Some(code) = node.value {
+                label.push_str(&format!("\\n{}", code));
+            }
+            if node.terminator {
+                label.push_str("\\nterminator");
+            }
+            dot.push_str(&format!("    n{} [label=\"{}\"];\n", id, label));
+            if let Some(parent) = export.parent {
+                dot.push_str(&format!("    n{} -> n{};\n", parent, id));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_json(&self) -> String {
+        let nodes: Vec<String> = self
+            .export_nodes()
+            .iter()
+            .enumerate()
+            .map(|(id, export)| {
+                let node = export.node;
+                let token = match node.key {
+                    None => "null".to_string(),
+                    Some(_) => format!("\"{}\"", escape(&token_label(node.key))),
+                };
+                let (code, bits) = match node.value {
+                    None => ("null".to_string(), "null".to_string()),
+                    Some(code) => (
+                        code.get_code().to_string(),
+                        code.get_used_bits().to_string(),
+                    ),
+                };
+                format!(
+                    "{{\"id\":{},\"parent\":{},\"token\":{},\"code\":{},\"bits\":{},\"terminator\":{}}}",
+                    id,
+                    export.parent.map_or("null".to_string(), |parent| parent.to_string()),
+                    token,
+                    code,
+                    bits,
+                    node.terminator
+                )
+            })
+            .collect();
+        format!("{{\"nodes\":[\n{}\n]}}\n", nodes.join(",\n"))
+    }
+}
+
+// Dropping nodes recursively can overflow the stack on a trie with very long entries
+impl<T: HashableToken> Drop for TrieDictionary<T> {
+    fn drop(&mut self) {
+        let mut pending = vec![std::mem::take(&mut self.root.children)];
+        while let Some(children) = pending.pop() {
+            for (_, mut child) in children {
+                pending.push(std::mem::take(&mut child.children));
+            }
+        }
+    }
+}
+
+// The state of a byte dictionary for a checkpoint, see checkpoint.rs
+// Nodes are written in the same order as for EXPORT, each after its parent
+// The prefix code and last token of every code, kept for LRU, are rebuilt from the trie rather than
+// written, so they can't disagree with it
+impl TrieDictionary<u8> {
+    pub fn write_state(&self, output: &mut Vec<u8>) {
+        let nodes = self.export_nodes();
+        output.extend_from_slice(&(nodes.len() as u64).to_le_bytes());
+        for export in nodes.iter() {
+            let node = export.node;
+            let parent = export.parent.map_or(u64::MAX, |parent| parent as u64);
+            output.extend_from_slice(&parent.to_le_bytes());
+            if let Some(key) = node.key {
+                checkpoint::write_token(key, output);
+            }
+            output.push(node.value.is_some() as u8);
+            if let Some(code) = node.value {
+                checkpoint::write_code(code, output);
+            }
+            output.push(node.terminator as u8);
+        }
+
+        output.push(self.lru.is_some() as u8);
+        if let Some(lru) = &self.lru {
+            lru.write_state(output);
+        }
+        output.push(self.previous_match.is_some() as u8);
+        let previous_match = self.previous_match.clone().unwrap_or_default();
+        let lookahead: Vec<Token<u8>> = self.lookahead.iter().copied().collect();
+        for tokens in [previous_match, lookahead] {
+            output.extend_from_slice(&(tokens.len() as u64).to_le_bytes());
+            for token in tokens {
+                checkpoint::write_token(token, output);
+            }
+        }
+    }
+
+    // next_code is the code generator's, which every code in the trie must be below
+    pub fn read_state(
+        lzw_spec: LzwSpec,
+        next_code: u32,
+        reader: &mut StateReader,
+    ) -> Result<TrieDictionary<u8>, LzwError> {
+        let count = reader.read_count(10)?;
+        let mut nodes: Vec<Option<(usize, TrieNode<u8>)>> = Vec::with_capacity(count);
+        for id in 0..count {
+            let parent = reader.read_u64()?;
+            let mut node = if id == 0 {
+                if parent != u64::MAX {
+                    return Err(checkpoint::invalid("The root has a parent".to_string()));
+                }
+                TrieNode::new_root()
+            } else {
+                if parent >= id as u64 {
+                    return Err(checkpoint::invalid(format!(
+                        "Node {} comes before its parent",
+                        id
+                    )));
+                }
+                TrieNode::new_without_value(checkpoint::read_token(reader)?)
+            };
+            if reader.read_bool()? {
+                node.value = Some(checkpoint::read_code(reader)?);
+            }
+            node.terminator = reader.read_bool()?;
+            nodes.push(Some((parent as usize, node)));
+        }
+        // Children come after their parents, so each node is complete by the time it is moved
+        for id in (1..count).rev() {
+            let (parent, node) = nodes[id].take().unwrap();
+            let key = node.key.unwrap();
+            let (_, parent_node) = nodes[parent].as_mut().unwrap();
+            if parent_node.children.insert(key, node).is_some() {
+                return Err(checkpoint::invalid(format!(
+                    "Node {} has two children for {:?}",
+                    parent, key
+                )));
+            }
+        }
+        let Some(Some((_, root))) = nodes.into_iter().next() else {
+            return Err(checkpoint::invalid(
+                "The dictionary has no root".to_string(),
+            ));
+        };
+
+        let entries = code_entries(&root, next_code, lzw_spec.evict_lru)?;
+        let lru = if reader.read_bool()? {
+            let prefixes: Vec<Option<u32>> = entries.iter().map(|(prefix, _)| *prefix).collect();
+            Some(LruTracker::read_state(reader, &prefixes)?)
+        } else {
+            None
+        };
+        if lru.is_some() != lzw_spec.evict_lru {
+            return Err(checkpoint::invalid(
+                "LRU replacement doesn't match the spec".to_string(),
+            ));
+        }
+        let has_previous = reader.read_bool()?;
+        let mut read_tokens = || -> Result<Vec<Token<u8>>, LzwError> {
+            (0..reader.read_count(2)?)
+                .map(|_| checkpoint::read_token(reader))
+                .collect()
+        };
+        let previous_match = Some(read_tokens()?).filter(|_| has_previous);
+        let lookahead = read_tokens()?.into();
+        // The next entries grow from the previous match, so it has to be in the trie
+        if let Some(previous) = &previous_match {
+            let mut current_node = &root;
+            for token in previous.iter() {
+                current_node = current_node.children.get(token).ok_or_else(|| {
+                    checkpoint::invalid("The previous match is not in the dictionary".to_string())
+                })?;
+            }
+        }
+
+        Ok(TrieDictionary {
+            longest: longest_below(&root),
+            root,
+            lru,
+            entries,
+            growth: lzw_spec.growth,
+            previous_match,
+            lookahead,
+        })
+    }
+}
+
+// The prefix code and last token of every code in a trie read from a checkpoint, by code
+// Codes must be unique and below next_code. With LRU, every node has a code and every code up to
+// next_code is in the trie, as LRU needs the path to any code; otherwise nothing is kept
+fn code_entries(
+    root: &TrieNode<u8>,
+    next_code: u32,
+    evict_lru: bool,
+) -> Result<Vec<CodeEntry<u8>>, LzwError> {
+    let mut coded = Vec::new();
+    let mut pending: Vec<(Option<u32>, &TrieNode<u8>)> =
+        root.children.values().map(|node| (None, node)).collect();
+    while let Some((prefix_code, node)) = pending.pop() {
+        let code = node.value.map(|value| value.get_code());
+        match code {
+            Some(code) if code >= next_code => {
+                return Err(checkpoint::invalid(format!(
+                    "Code {} is past the next code {}",
+                    code, next_code
+                )))
+            }
+            Some(code) => coded.push((code, prefix_code, node.key.unwrap())),
+            None if evict_lru => {
+                return Err(checkpoint::invalid(format!(
+                    "Node for {:?} has no code",
+                    node.key
+                )))
+            }
+            None => {}
+        }
+        pending.extend(node.children.values().map(|child| (code, child)));
+    }
+
+    let mut entries = vec![None; if evict_lru { next_code as usize } else { 0 }];
+    if evict_lru && coded.len() != entries.len() {
+        return Err(checkpoint::invalid(format!(
+            "{} codes in the dictionary, {} handed out",
+            coded.len(),
+            next_code
+        )));
+    }
+    let mut seen = std::collections::HashSet::new();
+    for (code, prefix_code, token) in coded {
+        if !seen.insert(code) {
+            return Err(checkpoint::invalid(format!("Code {} is used twice", code)));
+        }
+        if let Some(entry) = entries.get_mut(code as usize) {
+            *entry = Some((prefix_code, token));
+        }
+    }
+    Ok(entries.into_iter().flatten().collect())
+}
+
+impl<T: HashableToken> LzwDictionary<T> for TrieDictionary<T> {
+    fn initialise(
+        lzw_spec: LzwSpec,
+        code_gen: &mut lzw_code::CodeGenerator,
+        alphabet: Vec<Token<T>>,
+    ) -> TrieDictionary<T> {
+        TrieDictionary::new(lzw_spec, code_gen, alphabet)
+    }
+
+    fn lzw_step<I>(
+        &mut self,
+        tokens: &mut Peekable<I>,
+        code_gen: &mut lzw_code::CodeGenerator,
+    ) -> Result<Option<lzw_code::Code>, LzwError>
+    where
+        I: Iterator<Item = Token<T>>,
+    {
+        if self.growth != Growth::Lzw {
+            return self.grow_step(tokens, code_gen);
+        }
+        let mut current_node = &mut self.root;
+        let mut depth = 0;
+        while let Some(&token) = tokens.peek() {
+            if current_node.children.contains_key(&token) {
+                current_node = current_node.children.get_mut(&token).unwrap();
+                depth += 1;
+                tokens.next();
+            } else {
+                let Some(code) = current_node.value else {
+                    return Err(LzwError::Dictionary(format!(
+                        "Token {:?} is not in the alphabet",
+                        token
+                    )));
+                };
+                if let Some(lru) = &mut self.lru {
+                    lru.touch(code.get_code());
+                }
+                // Only advance the code generator if the new sequence is actually added
+                if let Some(next_code) = code_gen.get_next_code() {
+                    current_node.add_child(token, next_code, true);
+                    current_node.terminator = false;
+                    self.longest = self.longest.max(depth + 1);
+                    self.track_entry(next_code.get_code(), code.get_code(), token);
+                } else if self.lru.is_some() {
+                    self.replace_lru(code, token, code_gen.max_code_width());
+                }
+                return Ok(Some(code));
+            }
+        }
+        // Tokens exhausted, the root has no value so an empty input gives None
+        if let (Some(lru), Some(code)) = (&mut self.lru, current_node.value) {
+            lru.touch(code.get_code());
+        }
+        Ok(current_node.value)
+    }
+
+    fn control_code(&self, control_token: ControlToken) -> Option<lzw_code::Code> {
+        self._search(&[Token::new_control(control_token)])
+    }
+
+    // Like the alphabet, seeded entries are never replaced by LRU, so the tracker starts after them
+    fn seed(
+        &mut self,
+        entries: &[Vec<Token<T>>],
+        code_gen: &mut lzw_code::CodeGenerator,
+    ) -> Result<(), LzwError> {
+        for entry in entries.iter() {
+            let Some((last, prefix)) = entry.split_last().filter(|(_, prefix)| !prefix.is_empty())
+            else {
+                return Err(LzwError::Dictionary(
+                    "Shared dictionary entries must be at least two tokens long".to_string(),
+                ));
+            };
+            let mut current_node = &mut self.root;
+            for token in prefix.iter() {
+                current_node = current_node.children.get_mut(token).ok_or_else(|| {
+                    LzwError::Dictionary(format!(
+                        "Shared dictionary entry {:?} is not built on an earlier entry",
+                        entry
+                    ))
+                })?;
+            }
+            if current_node.children.contains_key(last) {
+                return Err(LzwError::Dictionary(format!(
+                    "Shared dictionary entry {:?} is already in the dictionary",
+                    entry
+                )));
+            }
+            let Some(code) = code_gen.get_next_code() else {
+                return Err(LzwError::Dictionary(format!(
+                    "Shared dictionary of {} entries does not fit in the code space",
+                    entries.len()
+                )));
+            };
+            let prefix_code = current_node.value.map(|value| value.get_code());
+            current_node.add_child(*last, code, true);
+            current_node.terminator = false;
+            self.longest = self.longest.max(entry.len());
+            if self.lru.is_some() {
+                self.entries.push((prefix_code, *last));
+            }
+        }
+        if let Some(lru) = &mut self.lru {
+            *lru = LruTracker::new(code_gen.get_current_code());
+        }
+        Ok(())
+    }
+
+    fn reset(
+        &mut self,
+        lzw_spec: LzwSpec,
+        code_gen: &mut lzw_code::CodeGenerator,
+        alphabet: Vec<Token<T>>,
+    ) {
+        let lookahead = std::mem::take(&mut self.lookahead);
+        *self = TrieDictionary::new(lzw_spec, code_gen, alphabet);
+        self.lookahead = lookahead;
+    }
+
+    fn has_lookahead(&self) -> bool {
+        !self.lookahead.is_empty()
+    }
+
+    fn memory_used(&self) -> Option<usize> {
+        Some(TrieDictionary::memory_used(self))
+    }
+
+    fn longest_entry(&self) -> Option<usize> {
+        Some(TrieDictionary::longest_entry(self))
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
-    use crate::{lzw_code::CodeGenerator, lzw_token};
+    use crate::alphabets;
+    use crate::{lzw_code::CodeGenerator, lzw_token, Coding};
 
     const _TEST_SPEC: LzwSpec = LzwSpec {
         alphabet: alphabets::Alphabet::_Test,
@@ -226,6 +909,10 @@ mod tests {
         clear_code: true,
         pack_msb_first: true,
         early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
     };
 
     const ASCII_SPEC: LzwSpec = LzwSpec {
@@ -238,6 +925,10 @@ mod tests {
         clear_code: true,
         pack_msb_first: true,
         early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
     };
 
     #[test]
@@ -329,15 +1020,116 @@ mod tests {
 
         let fetch_insert_seq = &[Token::new('A'), Token::new('B')];
         let expected_insert_code = code_gen.get_next_code().unwrap();
-        let fetched_result = dict.fetch_code_and_insert(fetch_insert_seq, expected_insert_code);
+        let (fetched_code, consumed_tokens) =
+            dict.fetch_code_and_insert(fetch_insert_seq, expected_insert_code);
 
-        assert_eq!(expected_fetched_code, fetched_result.code);
+        assert_eq!(expected_fetched_code, fetched_code);
         assert_eq!(
             fetch_insert_seq.len() as u32 - 1, // n-1 tokens should be consumed
-            fetched_result.consumed_tokens
+            consumed_tokens
         );
 
         let inserted_code = dict._search(fetch_insert_seq).unwrap();
         assert_eq!(expected_insert_code, inserted_code);
     }
+
+    #[test]
+    fn lru_replaces_leaves() {
+        let spec = LzwSpec {
+            width: 8,
+            min_width: 8,
+            max_width: 8,
+            evict_lru: true,
+            ..ASCII_SPEC
+        };
+        let mut code_gen = CodeGenerator::new(spec);
+        let mut dict = TrieDictionary::new(spec, &mut code_gen, alphabets::generate_ascii());
+        let first_free = code_gen.get_current_code();
+
+        let input: Vec<Token<char>> = "the cat sat on the mat with the hat "
+            .repeat(40)
+            .chars()
+            .map(Token::new)
+            .collect();
+        let mut tokens = input.into_iter().peekable();
+        while dict.lzw_step(&mut tokens, &mut code_gen).unwrap().is_some() {}
+
+        // Out of codes, but every reused code still finds its way back to itself
+        assert!(code_gen.is_exhausted());
+        for code in first_free..256 {
+            let path = dict.path_to(code);
+            assert_eq!(dict._search(&path).unwrap().get_code(), code);
+        }
+        // The initial dictionary is untouched
+        assert_eq!(
+            dict._search(&[Token::new('t')]).unwrap().get_code(),
+            't' as u32 - 32
+        );
+    }
+
+    #[test]
+    fn longest_kept_as_added() {
+        for growth in [Growth::Lzw, Growth::Lzmw, Growth::Lzap] {
+            let spec = LzwSpec {
+                growth,
+                ..ASCII_SPEC
+            };
+            let mut code_gen = CodeGenerator::new(spec);
+            let mut dict = TrieDictionary::new(spec, &mut code_gen, alphabets::generate_ascii());
+            assert_eq!(dict.longest_entry(), 1);
+
+            let input = "the cat sat on the mat with the hat ".repeat(20);
+            let mut tokens = input.chars().map(Token::new).peekable();
+            while dict.lzw_step(&mut tokens, &mut code_gen).unwrap().is_some() {
+                assert_eq!(
+                    dict.longest_entry(),
+                    longest_below(&dict.root),
+                    "{:?}",
+                    growth
+                );
+            }
+            assert!(dict.longest_entry() > 4);
+
+            let mut code_gen = CodeGenerator::new(spec);
+            dict.reset(spec, &mut code_gen, alphabets::generate_ascii());
+            assert_eq!(dict.longest_entry(), 1);
+        }
+    }
+
+    #[test]
+    fn export_after_steps() {
+        let mut code_gen = CodeGenerator::new(ASCII_SPEC);
+        let mut dict = TrieDictionary::new(ASCII_SPEC, &mut code_gen, alphabets::generate_ascii());
+        let initial_nodes = dict.export_nodes().len();
+        let ab = code_gen.get_current_code();
+
+        let mut tokens = "abababa".chars().map(Token::new).peekable();
+        while dict.lzw_step(&mut tokens, &mut code_gen).unwrap().is_some() {}
+
+        // ab, ba and aba were added
+        let json = dict.to_json();
+        assert_eq!(json.matches("\"id\":").count(), initial_nodes + 3);
+        assert!(json.starts_with("{\"nodes\":[\n{\"id\":0,\"parent\":null,\"token\":null"));
+        let a = dict._search(&[Token::new('a')]).unwrap().get_code();
+        assert!(json.contains(&format!(
+            "\"token\":\"'a'\",\"code\":{},\"bits\":12,\"terminator\":false",
+            a
+        )));
+        assert!(json.contains(&format!(
+            "\"token\":\"'b'\",\"code\":{},\"bits\":12,\"terminator\":false",
+            ab
+        )));
+        assert!(json.contains("\"token\":\"End\""));
+
+        let dot = dict.to_dot();
+        assert!(dot.starts_with("digraph dictionary {"));
+        assert_eq!(dot.matches(" -> ").count(), initial_nodes + 2);
+        // aba is a leaf
+        assert!(dot.contains(&format!(
+            "[label=\"'a'\\nCode({},12)\\nterminator\"]",
+            ab + 2
+        )));
+        // The same dictionary always gives the same output
+        assert_eq!(dot, dict.to_dot());
+    }
 }
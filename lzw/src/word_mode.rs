@@ -0,0 +1,256 @@
+use crate::lzw_decoder::LzwDecoder;
+use crate::lzw_encoder::LzwEncoder;
+use crate::lzw_error::LzwError;
+use crate::lzw_token::Token;
+use crate::stats::CodeStats;
+use crate::trie_dictionary::TrieDictionary;
+use crate::LzwSpec;
+use std::collections::HashMap;
+
+/* WORD TOKENS
+    For natural language the words repeat far more than the byte sequences LZW would learn early on,
+    so with Alphabet::Words the input is split into word tokens and LZW runs over those instead.
+    The input is split into runs of
+        letters and digits, counting any non-ascii byte as a letter, so UTF-8 words stay whole
+        whitespace
+    and every other byte (punctuation) is a token on its own.
+    Tokens are byte strings, so any input works, and joining them gives back exactly the input.
+
+    The initial alphabet is the vocabulary, the distinct tokens in order of first appearance.
+    Each token becomes its index into the vocabulary, and the vocabulary is written in front of the codes
+        word_count      4 bytes
+        word lengths    4 bytes each
+        words           the bytes of every word, one after another
+    Word tokens always use TrieDictionary.
+*/
+
+#[derive(Copy, Clone, PartialEq)]
+enum ByteClass {
+    Word,
+    Space,
+    Other,
+}
+
+fn byte_class(byte: u8) -> ByteClass {
+    if byte.is_ascii_alphanumeric() || !byte.is_ascii() {
+        ByteClass::Word
+    } else if byte.is_ascii_whitespace() {
+        ByteClass::Space
+    } else {
+        ByteClass::Other
+    }
+}
+
+pub fn tokenize(input: &[u8]) -> Vec<&[u8]> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    while start < input.len() {
+        let class = byte_class(input[start]);
+        let mut end = start + 1;
+        if class != ByteClass::Other {
+            while end < input.len() && byte_class(input[end]) == class {
+                end += 1;
+            }
+        }
+        tokens.push(&input[start..end]);
+        start = end;
+    }
+    tokens
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Vocabulary {
+    words: Vec<Vec<u8>>,
+}
+
+impl Vocabulary {
+    // The vocabulary of the tokens, and each token's index into it
+    pub fn from_tokens(tokens: &[&[u8]]) -> (Vocabulary, Vec<u32>) {
+        let mut ids: HashMap<&[u8], u32> = HashMap::new();
+        let mut words = Vec::new();
+        let indices = tokens
+            .iter()
+            .map(|token| {
+                *ids.entry(token).or_insert_with(|| {
+                    words.push(token.to_vec());
+                    words.len() as u32 - 1
+                })
+            })
+            .collect();
+        (Vocabulary { words }, indices)
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    pub fn word(&self, index: u32) -> Option<&[u8]> {
+        self.words.get(index as usize).map(|word| &word[..])
+    }
+
+    pub fn alphabet(&self) -> Vec<Token<u32>> {
+        (0..self.words.len() as u32).map(Token::new).collect()
+    }
+
+    pub fn write_to(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&(self.words.len() as u32).to_le_bytes());
+        for word in self.words.iter() {
+            output.extend_from_slice(&(word.len() as u32).to_le_bytes());
+        }
+        for word in self.words.iter() {
+            output.extend_from_slice(word);
+        }
+    }
+
+    // Returns the vocabulary and the number of bytes it took up
+    pub fn read_from(input: &[u8]) -> Result<(Vocabulary, usize), LzwError> {
+        let truncated = || LzwError::Format("Vocabulary is truncated".to_string());
+        let read_u32 = |position: usize| -> Result<usize, LzwError> {
+            let bytes = input.get(position..position + 4).ok_or_else(truncated)?;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+        };
+
+        let count = read_u32(0)?;
+        // Every word takes at least 4 bytes, so a bad count can't cause a huge allocation
+        if count > input.len() / 4 {
+            return Err(truncated());
+        }
+        let mut position = 4 + count * 4;
+        let mut words = Vec::with_capacity(count);
+        for i in 0..count {
+            let len = read_u32(4 + i * 4)?;
+            let word = input
+                .get(position..position.saturating_add(len))
+                .ok_or_else(truncated)?;
+            words.push(word.to_vec());
+            position += len;
+        }
+        Ok((Vocabulary { words }, position))
+    }
+}
+
+pub fn compress(spec: LzwSpec, input: &[u8]) -> Result<Vec<u8>, LzwError> {
+    compress_with_stats(spec, input).map(|(output, _)| output)
+}
+
+pub fn compress_with_stats(spec: LzwSpec, input: &[u8]) -> Result<(Vec<u8>, CodeStats), LzwError> {
+    let (vocabulary, indices) = Vocabulary::from_tokens(&tokenize(input));
+    spec.check_alphabet_size(vocabulary.len())?;
+
+    let mut output = Vec::new();
+    vocabulary.write_to(&mut output);
+    let mut encoder: LzwEncoder<u32, TrieDictionary<u32>> =
+        LzwEncoder::new(spec, vocabulary.alphabet());
+    output.extend(encoder.compress(indices.into_iter().map(Token::new))?);
+    Ok((output, encoder.stats()))
+}
+
+pub fn decompress(spec: LzwSpec, input: &[u8]) -> Result<Vec<u8>, LzwError> {
+    decompress_with_stats(spec, input).map(|(output, _)| output)
+}
+
+pub fn decompress_with_stats(
+    spec: LzwSpec,
+    input: &[u8],
+) -> Result<(Vec<u8>, CodeStats), LzwError> {
+    let (vocabulary, vocabulary_len) = Vocabulary::read_from(input)?;
+    spec.check_alphabet_size(vocabulary.len())?;
+
+    let mut decoder: LzwDecoder<u32> = LzwDecoder::new(spec, vocabulary.alphabet());
+    let tokens = decoder.decompress(&input[vocabulary_len..])?;
+    let mut output = Vec::new();
+    for token in tokens.iter() {
+        let word = token
+            .get_value()
+            .and_then(|index| vocabulary.word(index))
+            .ok_or(LzwError::Decode(format!(
+                "Token {:?} is not in the vocabulary",
+                token
+            )))?;
+        output.extend_from_slice(word);
+    }
+    Ok((output, decoder.stats()))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::alphabets::Alphabet;
+    use crate::{Coding, Growth};
+
+    const WORDS_SPEC: LzwSpec = LzwSpec {
+        alphabet: Alphabet::Words,
+        variable_width: true,
+        width: 16,
+        min_width: 9,
+        max_width: 16,
+        end_code: true,
+        clear_code: true,
+        pack_msb_first: true,
+        early_change: false,
+        evict_lru: false,
+        growth: Growth::Lzw,
+        coding: Coding::Packed,
+        memory_budget: None,
+    };
+
+    #[test]
+    fn tokenize_words() {
+        let tokens = tokenize("Hello,  wörld!\n\tfoo_bar 42".as_bytes());
+        let expected: Vec<&[u8]> = vec![
+            b"Hello",
+            b",",
+            b"  ",
+            "wörld".as_bytes(),
+            b"!",
+            b"\n\t",
+            b"foo",
+            b"_",
+            b"bar",
+            b" ",
+            b"42",
+        ];
+        assert_eq!(tokens, expected);
+        assert!(tokenize(b"").is_empty());
+        assert_eq!(tokenize(b"..."), vec![b".", b".", b"."]);
+    }
+
+    #[test]
+    fn vocabulary_round_trip() {
+        let tokens = tokenize(b"the cat and the hat");
+        let (vocabulary, indices) = Vocabulary::from_tokens(&tokens);
+        assert_eq!(vocabulary.len(), 5);
+        assert_eq!(indices, vec![0, 1, 2, 1, 3, 1, 0, 1, 4]);
+
+        let mut bytes = Vec::new();
+        vocabulary.write_to(&mut bytes);
+        bytes.push(0xff);
+        let (read, len) = Vocabulary::read_from(&bytes).unwrap();
+        assert_eq!(read, vocabulary);
+        assert_eq!(len, bytes.len() - 1);
+
+        assert!(Vocabulary::read_from(&bytes[..len - 1]).is_err());
+        assert!(Vocabulary::read_from(&[0xff; 8]).is_err());
+    }
+
+    #[test]
+    fn vocabulary_must_fit() {
+        let input: Vec<u8> = (0..600)
+            .flat_map(|i| format!("w{} ", i).into_bytes())
+            .collect();
+        let narrow = LzwSpec {
+            variable_width: false,
+            width: 9,
+            ..WORDS_SPEC
+        };
+        assert!(compress(narrow, &input).is_err());
+        let compressed = compress(WORDS_SPEC, &input).unwrap();
+        assert!(decompress(narrow, &compressed).is_err());
+        assert_eq!(decompress(WORDS_SPEC, &compressed).unwrap(), input);
+    }
+}
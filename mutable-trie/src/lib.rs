@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::iter::{self, Peekable};
-use tracing::{info, Level};
+use tracing::info;
 
 pub trait TrieKey: Copy + Hash + Debug + Eq + PartialEq {}
 impl<T: Copy + Hash + Debug + Eq + PartialEq> TrieKey for T {}
@@ -51,6 +51,11 @@ pub trait IterLzwDict<T, K, V> {
         key_it: &mut Peekable<I>,
         value: V,
     ) -> Result<Option<V>, TrieError>;
+
+    fn lzw_search_iter<I: Iterator<Item = K>>(
+        root: &T,
+        key_it: &mut Peekable<I>,
+    ) -> Result<Option<V>, TrieError>;
 }
 
 #[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
@@ -329,8 +334,31 @@ where
                 return Ok(node.value);
             }
         }
-        // TODO return the code without adding new sequence
-        Err(TrieError::Lzw("Reached end of sequence".to_string()))
+        // Reached the end of the sequence, return the code of the final match without adding anything
+        Ok(node.value)
+    }
+
+    fn lzw_search_iter<I>(
+        root: &TrieNode<K, V>,
+        key_it: &mut Peekable<I>,
+    ) -> Result<Option<V>, TrieError>
+    where
+        I: Iterator<Item = K>,
+    {
+        // Descend as lzw_insert_iter does, but never add a child
+        // Used once there are no values left to assign to new sequences
+        let mut node = root;
+
+        while let Some(k) = key_it.peek() {
+            match node.children.get(k) {
+                Some(child) => {
+                    node = child;
+                    key_it.next();
+                }
+                None => break,
+            }
+        }
+        Ok(node.value)
     }
 }
 
@@ -594,6 +622,72 @@ mod test {
         assert_eq!(val, 101);
     }
 
+    #[traced_test]
+    #[test]
+    fn test_lzw_insert_iter_end_of_sequence() {
+        let mut root = TrieNode::new(None, None);
+        for (i, c) in "abcde".chars().enumerate() {
+            root.insert(iter::once(c), i).unwrap();
+        }
+
+        let mut key_sequence = "abab".chars().peekable();
+
+        // Insert sequence "ab" and recieve the code for sequence "a"
+        let Ok(Some(val)) = TrieNode::lzw_insert_iter(&mut root, &mut key_sequence, 99) else{
+            panic!("expected to recieve a value from lzw_insert");
+        };
+        assert_eq!(val, 0);
+
+        // Insert sequence "ba" and recieve the code for sequence "b"
+        let Ok(Some(val)) = TrieNode::lzw_insert_iter(&mut root, &mut key_sequence, 100) else{
+            panic!("expected to recieve a value from lzw_insert");
+        };
+        assert_eq!(val, 1);
+
+        // Sequence runs out while matching "ab", so recieve its code without inserting
+        let Ok(Some(val)) = TrieNode::lzw_insert_iter(&mut root, &mut key_sequence, 101) else{
+            panic!("expected to recieve a value from lzw_insert");
+        };
+        assert_eq!(val, 99);
+        assert!(key_sequence.peek().is_none());
+        assert!(root.search("ab".chars()).unwrap().is_some());
+        assert!(TrieNode::search_iter(&root, "abx".chars()).unwrap().is_none());
+
+        // Nothing left to match
+        let Ok(None) = TrieNode::lzw_insert_iter(&mut root, &mut key_sequence, 102) else{
+            panic!("expected no value from an empty sequence");
+        };
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_lzw_search_iter() {
+        let mut root = TrieNode::new(None, None);
+        for (i, c) in "abcde".chars().enumerate() {
+            root.insert(iter::once(c), i).unwrap();
+        }
+        root.insert("ab".chars(), 99).unwrap();
+
+        let mut key_sequence = "abac".chars().peekable();
+
+        // Longest match is "ab", nothing is inserted
+        let Ok(Some(val)) = TrieNode::lzw_search_iter(&root, &mut key_sequence) else{
+            panic!("expected to recieve a value from lzw_search_iter");
+        };
+        assert_eq!(val, 99);
+        assert!(TrieNode::search_iter(&root, "aba".chars()).unwrap().is_none());
+
+        let Ok(Some(val)) = TrieNode::lzw_search_iter(&root, &mut key_sequence) else{
+            panic!("expected to recieve a value from lzw_search_iter");
+        };
+        assert_eq!(val, 0);
+
+        let Some(c) = key_sequence.next() else{
+            panic!();
+        };
+        assert_eq!(c, 'c');
+    }
+
     #[traced_test]
     #[test]
     fn test_token() {